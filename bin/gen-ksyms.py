@@ -0,0 +1,125 @@
+#!/usr/bin/env python3
+#
+# Embeds the symbol table of a kernel ELF into its `.ksyms` section, which
+# the linker script reserves, so that the kernel can symbolise backtraces.
+#
+# usage: gen-ksyms.py kernel.elf
+#
+# The table is laid out as (all integers little endian):
+#
+#   magic:   8 bytes, b"KSYMTAB\0"
+#   count:   u64, the number of symbols
+#   entries: count * (addr: u64, size: u64, name: u32 offset, u32 length),
+#            sorted by address; offsets are from the start of the strings
+#   strings: the demangled names
+
+import re
+import struct
+import sys
+
+MAGIC = b"KSYMTAB\0"
+
+SHT_SYMTAB = 2
+STT_FUNC = 2
+
+ESCAPES = [
+    ("$SP$", "@"), ("$BP$", "*"), ("$RF$", "&"), ("$LT$", "<"), ("$GT$", ">"),
+    ("$LP$", "("), ("$RP$", ")"), ("$C$", ","), ("$u7e$", "~"), ("$u20$", " "),
+    ("$u27$", "'"), ("$u5b$", "["), ("$u5d$", "]"), ("$u7b$", "{"),
+    ("$u7d$", "}"), ("$u3b$", ";"), ("$u2b$", "+"), ("$u22$", "\""),
+]
+
+def demangle(name):
+    """Demangles a legacy Rust symbol, dropping its hash. Other symbols are
+    returned as they are."""
+    if not name.startswith("_ZN") or not name.endswith("E"):
+        return name
+
+    parts = []
+    rest = name[3:-1]
+    while rest:
+        m = re.match(r"(\d+)", rest)
+        if m is None:
+            return name
+        n = int(m.group(1))
+        start = len(m.group(1))
+        parts.append(rest[start:start + n])
+        rest = rest[start + n:]
+
+    if parts and re.match(r"^h[0-9a-f]{16}$", parts[-1]):
+        parts.pop()
+
+    out = []
+    for part in parts:
+        if part.startswith("_$"):
+            part = part[1:]
+        for k, v in ESCAPES:
+            part = part.replace(k, v)
+        out.append(part.replace("..", "::"))
+    return "::".join(out)
+
+def sections(elf):
+    (shoff,) = struct.unpack_from("<Q", elf, 0x28)
+    shentsize, shnum, shstrndx = struct.unpack_from("<HHH", elf, 0x3a)
+
+    headers = []
+    for i in range(shnum):
+        off = shoff + i * shentsize
+        (name, kind, _, _, offset, size, link, _, _, entsize) = \
+            struct.unpack_from("<IIQQQQIIQQ", elf, off)
+        headers.append((name, kind, offset, size, link, entsize))
+
+    strtab = headers[shstrndx]
+    for (name, kind, offset, size, link, entsize) in headers:
+        end = elf.index(b"\0", strtab[2] + name)
+        yield (elf[strtab[2] + name:end].decode(), kind, offset, size,
+               headers[link] if link < len(headers) else None, entsize)
+
+def symbols(elf):
+    for (_, kind, offset, size, strtab, entsize) in sections(elf):
+        if kind != SHT_SYMTAB:
+            continue
+
+        for off in range(offset, offset + size, entsize):
+            (name, info, _, shndx, value, sz) = \
+                struct.unpack_from("<IBBHQQ", elf, off)
+            if info & 0xf != STT_FUNC or shndx == 0 or value == 0:
+                continue
+            end = elf.index(b"\0", strtab[2] + name)
+            yield (value, sz, demangle(elf[strtab[2] + name:end].decode()))
+
+def main():
+    if len(sys.argv) != 2:
+        print("usage: %s kernel.elf" % sys.argv[0])
+        sys.exit(1)
+
+    path = sys.argv[1]
+    elf = bytearray(open(path, "rb").read())
+
+    ksyms = [s for s in sections(elf) if s[0] == ".ksyms"]
+    if not ksyms:
+        print("[!] %s has no .ksyms section" % path)
+        sys.exit(1)
+    (_, _, offset, capacity, _, _) = ksyms[0]
+
+    syms = sorted(set(symbols(elf)))
+    entries = bytearray()
+    strings = bytearray()
+    for (addr, size, name) in syms:
+        name = name.encode()
+        entries += struct.pack("<QQII", addr, size, len(strings), len(name))
+        strings += name
+
+    table = MAGIC + struct.pack("<Q", len(syms)) + entries + strings
+    if len(table) > capacity:
+        print("[!] the symbol table (%d bytes) does not fit in .ksyms (%d bytes)"
+              % (len(table), capacity))
+        sys.exit(1)
+
+    elf[offset:offset + len(table)] = table
+    open(path, "wb").write(elf)
+    print("+ Embedded %d symbols (%d of %d bytes) into %s"
+          % (len(syms), len(table), capacity, path))
+
+if __name__ == "__main__":
+    main()
@@ -2,29 +2,300 @@ mod linked_list;
 mod util;
 
 mod bin;
+mod buddy;
 mod bump;
+#[cfg(any(feature = "alloc-debug", test))]
+mod debug;
+pub mod slab;
 
-type AllocatorImpl = bump::Allocator;
+#[cfg(any(
+    all(feature = "alloc-bump", feature = "alloc-bin"),
+    all(feature = "alloc-bump", feature = "alloc-buddy"),
+    all(feature = "alloc-bin", feature = "alloc-buddy"),
+))]
+compile_error!("at most one of `alloc-bump`, `alloc-bin` and `alloc-buddy` may be enabled");
+
+#[cfg(feature = "alloc-bump")]
+type Backend = bump::Allocator;
+#[cfg(feature = "alloc-buddy")]
+type Backend = buddy::Allocator;
+#[cfg(not(any(feature = "alloc-bump", feature = "alloc-buddy")))]
+type Backend = bin::Allocator;
+
+#[cfg(not(feature = "alloc-debug"))]
+type AllocatorImpl = Backend;
+#[cfg(feature = "alloc-debug")]
+type AllocatorImpl = debug::Allocator<Backend>;
+
+/// Returns the allocator of the kernel heap between `start` and `end`.
+#[cfg(not(feature = "alloc-debug"))]
+fn backend(start: usize, end: usize) -> AllocatorImpl {
+    Backend::new(start, end)
+}
+
+/// Returns the allocator of the kernel heap between `start` and `end`.
+#[cfg(feature = "alloc-debug")]
+fn backend(start: usize, end: usize) -> AllocatorImpl {
+    debug::Allocator::wrap(Backend::new(start, end))
+}
 
 #[cfg(test)]
 mod tests;
 
 use core::alloc::{GlobalAlloc, Layout};
 use core::fmt;
+use core::mem::{align_of, size_of};
 
 use crate::console::kprintln;
 use crate::mutex::Mutex;
+use crate::param::{KERNEL_BASE, KERNEL_HEAP_SIZE};
+use crate::process::Process;
+use crate::traps::TrapFrame;
 use pi::atags::{Atag, Atags};
 
+use self::slab::{SlabCache, SlabStats};
+
 /// `LocalAlloc` is an analogous trait to the standard library's `GlobalAlloc`,
 /// but it takes `&mut self` in `alloc()` and `dealloc()`.
 pub trait LocalAlloc {
     unsafe fn alloc(&mut self, layout: Layout) -> *mut u8;
     unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout);
+
+    /// Returns how the memory of the heap is used.
+    fn usage(&self) -> Usage;
+
+    /// Returns the counts of the allocations made so far.
+    fn counters(&self) -> Counters;
+}
+
+/// How the memory of an allocator's heap is used.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Usage {
+    /// The size of the heap.
+    pub total: usize,
+    /// The bytes requested by live allocations.
+    pub requested: usize,
+    /// The bytes of the blocks handed out for live allocations, including
+    /// the padding they were rounded up with.
+    pub allocated: usize,
+    /// The bytes that can still be allocated.
+    pub free: usize,
+    /// The size of the largest allocation that could still succeed.
+    pub largest_free: usize,
+}
+
+impl Usage {
+    /// Returns the percentage of allocated bytes that were not requested:
+    /// the internal fragmentation.
+    pub fn internal_fragmentation(&self) -> usize {
+        match self.allocated {
+            0 => 0,
+            allocated => (allocated - self.requested) * 100 / allocated,
+        }
+    }
+
+    /// Returns the percentage of free bytes that are not part of the largest
+    /// free block: the external fragmentation.
+    pub fn external_fragmentation(&self) -> usize {
+        match self.free {
+            0 => 0,
+            free => (free - self.largest_free) * 100 / free,
+        }
+    }
+
+    /// Returns the bytes that are neither allocated nor free: lost to
+    /// alignment, or freed but never reused.
+    pub fn lost(&self) -> usize {
+        self.total - self.allocated - self.free
+    }
+}
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} bytes allocated ({} requested, {}% internal fragmentation), \
+             {} free (largest {}, {}% external fragmentation), {} lost",
+            self.allocated,
+            self.total,
+            self.requested,
+            self.internal_fragmentation(),
+            self.free,
+            self.largest_free,
+            self.external_fragmentation(),
+            self.lost()
+        )
+    }
+}
+
+/// The number of size classes allocations are counted in: powers of two
+/// from 2^3 to 2^22 bytes. The last class counts larger allocations too.
+pub const NUM_SIZE_CLASSES: usize = 20;
+
+/// The counts of the allocations of an allocator.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Counters {
+    /// The number of allocations and deallocations served so far.
+    pub allocs: usize,
+    pub frees: usize,
+    /// The number of allocations that could not be served.
+    pub failed: usize,
+    /// The most bytes allocated at once.
+    pub peak: usize,
+    /// The number of allocations served in each size class, by requested
+    /// size.
+    pub by_class: [usize; NUM_SIZE_CLASSES],
+}
+
+impl Counters {
+    /// Returns the size class of allocations of `size` bytes.
+    pub fn size_class(size: usize) -> usize {
+        let class = size.max(8).next_power_of_two().trailing_zeros() as usize - 3;
+        class.min(NUM_SIZE_CLASSES - 1)
+    }
+
+    /// Returns the size of the blocks of `class`: the largest allocation it
+    /// counts, but for the last class.
+    pub fn class_size(class: usize) -> usize {
+        1 << (class + 3)
+    }
+
+    /// Counts an allocation of `layout`, after which `allocated` bytes are
+    /// allocated.
+    pub fn alloc(&mut self, layout: &Layout, allocated: usize) {
+        self.allocs += 1;
+        self.by_class[Counters::size_class(layout.size())] += 1;
+        self.peak = self.peak.max(allocated);
+    }
+
+    /// Counts a deallocation.
+    pub fn free(&mut self) {
+        self.frees += 1;
+    }
+
+    /// Counts an allocation that could not be served.
+    pub fn fail(&mut self) {
+        self.failed += 1;
+    }
+}
+
+/// The statistics of the kernel heap, as returned by `Allocator::stats()`.
+#[derive(Debug, Copy, Clone)]
+pub struct Stats {
+    pub usage: Usage,
+    pub counters: Counters,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counters = &self.counters;
+        writeln!(f, "heap: {}", self.usage)?;
+        writeln!(f, "peak: {} bytes allocated", counters.peak)?;
+        write!(
+            f,
+            "{} allocations, {} frees, {} live, {} failed",
+            counters.allocs,
+            counters.frees,
+            counters.allocs - counters.frees,
+            counters.failed
+        )?;
+        for (class, &count) in counters.by_class.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let (bound, size) = match class {
+                class if class == NUM_SIZE_CLASSES - 1 => (">", Counters::class_size(class - 1)),
+                class => ("<=", Counters::class_size(class)),
+            };
+            write!(f, "\n  {:>2} {:>8} bytes: {} allocations", bound, size, count)?;
+        }
+        Ok(())
+    }
 }
 
-/// Thread-safe (locking) wrapper around a particular memory allocator.
-pub struct Allocator(Mutex<Option<AllocatorImpl>>);
+/// The number of slab caches of the kernel heap.
+pub const NUM_CACHES: usize = 4;
+
+/// Returns the slab caches of the kernel heap, one for each kind of kernel
+/// object that is allocated over and over. A cache serves every allocation
+/// of exactly its object size, whatever the type.
+fn caches() -> [SlabCache; NUM_CACHES] {
+    [
+        SlabCache::new("process", size_of::<Process>(), align_of::<Process>(), None),
+        SlabCache::new("trap-frame", size_of::<TrapFrame>(), align_of::<TrapFrame>(), None),
+        // The sector buffers of the FAT file system's `CacheEntry`s.
+        SlabCache::new("cache-entry", 512, 1, None),
+        // The `Arc`s irq handlers are kept in once registered: two reference
+        // counts in front of a closure that captures nothing.
+        SlabCache::new("irq-handler", 2 * size_of::<usize>(), align_of::<usize>(), None),
+    ]
+}
+
+/// A backend allocator, and the slab caches it backs.
+struct Heap {
+    backend: AllocatorImpl,
+    caches: [SlabCache; NUM_CACHES],
+}
+
+impl Heap {
+    /// Returns the cache serving `layout`, if any. With the `alloc-debug`
+    /// feature, no cache does, so that every allocation is checked.
+    fn cache(caches: &mut [SlabCache], layout: &Layout) -> Option<&mut SlabCache> {
+        if cfg!(feature = "alloc-debug") {
+            return None;
+        }
+        caches.iter_mut().find(|cache| cache.serves(layout))
+    }
+
+    /// Allocates from the cache serving `layout`, or from the backend if no
+    /// cache does.
+    unsafe fn try_alloc(&mut self, layout: Layout) -> *mut u8 {
+        let Heap { backend, caches } = self;
+        match Heap::cache(caches, &layout) {
+            Some(cache) => cache.alloc(backend),
+            None => backend.alloc(layout),
+        }
+    }
+
+    /// Allocates for `layout`. If the backend is out of memory, the empty
+    /// slabs of every cache are given back to it and the allocation retried.
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let ptr = self.try_alloc(layout);
+        if ptr.is_null() && self.reclaim() > 0 {
+            return self.try_alloc(layout);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        match Heap::cache(&mut self.caches, &layout) {
+            Some(cache) => cache.dealloc(ptr),
+            None => self.backend.dealloc(ptr, layout),
+        }
+    }
+
+    /// Gives the empty slabs of every cache back to the backend. Returns the
+    /// number of bytes given back.
+    fn reclaim(&mut self) -> usize {
+        let Heap { backend, caches } = self;
+        caches
+            .iter_mut()
+            .map(|cache| unsafe { cache.reclaim(backend) })
+            .sum()
+    }
+}
+
+/// The number of live allocations `Allocator::dump_live()` lists one by one.
+#[cfg(feature = "alloc-debug")]
+const MAX_DUMPED: usize = 128;
+
+/// Thread-safe (locking) wrapper around a particular memory allocator: the
+/// bin allocator, or the bump or buddy allocator with the `alloc-bump` or
+/// `alloc-buddy` feature. Its `Debug` output reports the heap's `Usage`.
+///
+/// Allocations of the kernel objects with a slab cache are served from slabs
+/// carved out of the backend instead.
+pub struct Allocator(Mutex<Option<Heap>>);
 
 impl Allocator {
     /// Returns an uninitialized `Allocator`.
@@ -35,16 +306,95 @@ impl Allocator {
         Allocator(Mutex::new(None))
     }
 
-    /// Initializes the memory allocator.
-    /// The caller should assure that the method is invoked only once during the
-    /// kernel initialization.
+    /// Initializes the memory allocator with the first `KERNEL_HEAP_SIZE`
+    /// bytes of available memory; the rest is left to the page frame
+    /// allocator. The caller should assure that the method is invoked only
+    /// once during the kernel initialization.
     ///
     /// # Panics
     ///
     /// Panics if the system's memory map could not be retrieved.
     pub unsafe fn initialize(&self) {
         let (start, end) = memory_map().expect("failed to find memory map");
-        *self.0.lock() = Some(AllocatorImpl::new(start, end));
+        let end = end.min(start.saturating_add(KERNEL_HEAP_SIZE));
+        *self.0.lock() = Some(Heap {
+            backend: backend(KERNEL_BASE + start, KERNEL_BASE + end),
+            caches: caches(),
+        });
+    }
+
+    /// Returns the statistics of the kernel heap's backend allocator.
+    ///
+    /// Allocations served from a slab cache are not counted: the backend
+    /// only sees the slabs.
+    pub fn stats(&self) -> Stats {
+        let heap = self.0.lock();
+        let backend = &heap.as_ref().expect("allocator uninitialized").backend;
+        Stats {
+            usage: backend.usage(),
+            counters: backend.counters(),
+        }
+    }
+
+    /// Returns the statistics of every slab cache.
+    pub fn slabs(&self) -> [SlabStats; NUM_CACHES] {
+        let heap = self.0.lock();
+        let caches = &heap.as_ref().expect("allocator uninitialized").caches;
+        let mut stats = [caches[0].stats(); NUM_CACHES];
+        for (stats, cache) in stats.iter_mut().zip(caches.iter()) {
+            *stats = cache.stats();
+        }
+        stats
+    }
+
+    /// Prints every live allocation of the kernel heap, with the
+    /// `alloc-debug` feature: the most recent `MAX_DUMPED` ones one by one,
+    /// and the count and size of all of them.
+    #[cfg(feature = "alloc-debug")]
+    pub fn dump_live(&self) {
+        // Printing with the heap locked would stall every allocation for as
+        // long as the console takes: copy the allocations out first.
+        let mut live = [None; MAX_DUMPED];
+        let (mut count, mut bytes) = (0, 0);
+        {
+            let heap = self.0.lock();
+            let backend = &heap.as_ref().expect("allocator uninitialized").backend;
+            backend.for_each_live(|allocation| {
+                if count < MAX_DUMPED {
+                    live[count] = Some(allocation);
+                }
+                count += 1;
+                bytes += allocation.size;
+            });
+        }
+
+        for allocation in live.iter().flatten() {
+            kprintln!(
+                "#{:<8} {:#x}: {} bytes, align {}",
+                allocation.seq, allocation.addr, allocation.size, allocation.align
+            );
+        }
+        if count > MAX_DUMPED {
+            kprintln!("... and {} older ones", count - MAX_DUMPED);
+        }
+        kprintln!("{} live allocations, {} bytes", count, bytes);
+    }
+
+    /// Prints every live allocation of the kernel heap, with the
+    /// `alloc-debug` feature.
+    #[cfg(not(feature = "alloc-debug"))]
+    pub fn dump_live(&self) {
+        kprintln!("live allocations are tracked with the `alloc-debug` feature only");
+    }
+
+    /// Gives the empty slabs of every slab cache back to the backend.
+    /// Returns the number of bytes given back.
+    pub fn reclaim(&self) -> usize {
+        self.0
+            .lock()
+            .as_mut()
+            .expect("allocator uninitialized")
+            .reclaim()
     }
 }
 
@@ -71,12 +421,13 @@ extern "C" {
 }
 
 /// Returns the (start address, end address) of the available memory on this
-/// system if it can be determined. If it cannot, `None` is returned.
+/// system, as physical addresses, if it can be determined. If it cannot,
+/// `None` is returned.
 ///
 /// This function is expected to return `Some` under all normal cirumstances.
 pub fn memory_map() -> Option<(usize, usize)> {
     let page_size = 1 << 12;
-    let binary_end = unsafe { (&__text_end as *const u8) as usize };
+    let binary_end = unsafe { (&__text_end as *const u8) as usize } - KERNEL_BASE;
     for tag in Atags::get() {
         match tag.mem() {
             Some(mem) => {
@@ -98,7 +449,12 @@ pub fn memory_map() -> Option<(usize, usize)> {
 impl fmt::Debug for Allocator {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self.0.lock().as_mut() {
-            Some(ref alloc) => write!(f, "{:?}", alloc)?,
+            Some(ref heap) => {
+                write!(f, "{:?}", heap.backend)?;
+                for cache in heap.caches.iter() {
+                    write!(f, "\n  {:?}", cache)?;
+                }
+            }
             None => write!(f, "Not yet initialized")?,
         }
         Ok(())
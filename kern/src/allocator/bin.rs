@@ -4,7 +4,7 @@ use core::ptr;
 
 use crate::allocator::linked_list::LinkedList;
 use crate::allocator::util::*;
-use crate::allocator::LocalAlloc;
+use crate::allocator::{Counters, LocalAlloc, Usage};
 
 /// A simple allocator that allocates based on size classes.
 ///   bin 0 (2^3 bytes)    : handles allocations in (0, 2^3]
@@ -17,20 +17,43 @@ use crate::allocator::LocalAlloc;
 pub struct Allocator {
     free_list: [LinkedList; 30],
     heap_ptr: usize,
+    start: usize,
     end: usize,
+    requested: usize,
+    allocated: usize,
+    /// The bytes of the blocks in `free_list`.
+    binned: usize,
+    counters: Counters,
 }
 
 impl Allocator {
     /// Creates a new bin allocator that will allocate memory from the region
     /// starting at address `start` and ending at address `end`.
+    #[allow(dead_code)]
     pub fn new(start: usize, end: usize) -> Allocator {
         let mut free_list = [LinkedList::new(); 30];
         Allocator {
             free_list: free_list,
             heap_ptr: start,
+            start,
             end: end,
+            requested: 0,
+            allocated: 0,
+            binned: 0,
+            counters: Counters::default(),
         }
     }
+
+    /// Returns the size of the blocks of the bin serving `layout`, and the
+    /// bin's number. Blocks are large enough to link into a free list.
+    fn bin(layout: &Layout) -> (usize, usize) {
+        let size = layout
+            .size()
+            .max(layout.align())
+            .max(core::mem::size_of::<usize>())
+            .next_power_of_two();
+        (size, size.trailing_zeros().saturating_sub(3) as usize)
+    }
 }
 
 impl LocalAlloc for Allocator {
@@ -56,26 +79,35 @@ impl LocalAlloc for Allocator {
     /// or `layout` does not meet this allocator's
     /// size or alignment constraints.
     unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
-        let mut size = layout.size();
-        if size < layout.align() {
-            size = layout.align();
-        }
-        if size * 2 != size.next_power_of_two() {
-            size = size.next_power_of_two();
+        let (size, bin_num) = Allocator::bin(&layout);
+        if bin_num >= self.free_list.len() {
+            self.counters.fail();
+            return core::ptr::null_mut();
         }
-        let bin_num = (size.trailing_zeros().saturating_sub(3)) as usize;
 
-        if !self.free_list[bin_num].is_empty() {
-            return (self.free_list[bin_num].pop().unwrap() as *mut u8);
+        //reuse a freed block if one is aligned well enough
+        let reused = self.free_list[bin_num]
+            .iter_mut()
+            .find(|node| node.value() as usize % layout.align() == 0)
+            .map(|node| node.pop());
+        if let Some(block) = reused {
+            self.binned -= size;
+            self.requested += layout.size();
+            self.allocated += size;
+            self.counters.alloc(&layout, self.allocated);
+            return block as *mut u8;
         }
+
         //make new space
         let pointer_addr = align_up(self.heap_ptr, layout.align());
         if self.end.saturating_sub(size) < pointer_addr {
+            self.counters.fail();
             return core::ptr::null_mut() as *mut u8;
         } else {
-            let padding = pointer_addr - self.heap_ptr;
-            //let size = padding + layout.size();
-            self.heap_ptr = self.heap_ptr + size + padding;
+            self.heap_ptr = pointer_addr + size;
+            self.requested += layout.size();
+            self.allocated += size;
+            self.counters.alloc(&layout, self.allocated);
             return (pointer_addr as *mut u8);
         }
 
@@ -95,26 +127,40 @@ impl LocalAlloc for Allocator {
     /// Parameters not meeting these conditions may result in undefined
     /// behavior.
     unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
-        let mut size = layout.size();
-        if size < layout.align() {
-            size = layout.align();
-        }
-        if size * 2 != size.next_power_of_two() {
-            size = size.next_power_of_two();
-        }
-        let bin_num = size.trailing_zeros().saturating_sub(3) as usize;
+        let (size, bin_num) = Allocator::bin(&layout);
 
         unsafe {
             self.free_list[bin_num].push(ptr as *mut usize);
         }
+        self.binned += size;
+        self.requested -= layout.size();
+        self.allocated -= size;
+        self.counters.free();
+    }
+
+    fn usage(&self) -> Usage {
+        let wilderness = self.end - self.heap_ptr;
+        let largest_bin = self
+            .free_list
+            .iter()
+            .rposition(|list| !list.is_empty())
+            .map_or(0, |bin_num| 1 << (bin_num + 3));
+        Usage {
+            total: self.end - self.start,
+            requested: self.requested,
+            allocated: self.allocated,
+            free: self.binned + wilderness,
+            largest_free: largest_bin.max(wilderness),
+        }
+    }
+
+    fn counters(&self) -> Counters {
+        self.counters
     }
 }
 
-// FIXME: Implement `Debug` for `Allocator`.
 impl fmt::Debug for Allocator {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "{:?}", self.heap_ptr);
-        writeln!(f, "{:?}", self.end);
-        Ok(())
+        write!(f, "bin allocator at {:#x}: {}", self.heap_ptr, self.usage())
     }
 }
@@ -0,0 +1,217 @@
+use core::alloc::Layout;
+use core::fmt;
+use core::mem;
+
+use crate::allocator::linked_list::LinkedList;
+use crate::allocator::util::*;
+use crate::allocator::{Counters, LocalAlloc, Usage};
+
+/// log2 of the smallest block: large enough to link into a free list.
+const MIN_ORDER: usize = 3;
+/// The number of block sizes: from 2^3 to 2^32 bytes.
+const NUM_ORDERS: usize = 30;
+
+/// A buddy allocator.
+///
+/// Memory is handed out in blocks of power-of-two sizes, each aligned to its
+/// own size. An allocation takes the smallest free block that fits it,
+/// splitting a larger block in halves ("buddies") as often as needed; the
+/// unused halves go on the free lists of their sizes. A freed block is
+/// coalesced with its buddy, whose address differs from it only in the bit
+/// of the block size, for as long as the buddy is free too.
+///
+///   order 0 (2^3 bytes) : blocks for allocations in (0, 2^3]
+///   order 1 (2^4 bytes) : blocks for allocations in (2^3, 2^4]
+///   ...
+///   order 29 (2^32 bytes): blocks for allocations in (2^31, 2^32]
+pub struct Allocator {
+    free_list: [LinkedList; NUM_ORDERS],
+    start: usize,
+    end: usize,
+    requested: usize,
+    allocated: usize,
+    counters: Counters,
+}
+
+impl Allocator {
+    /// Creates a new buddy allocator that will allocate memory from the
+    /// region starting at address `start` and ending at address `end`.
+    ///
+    /// The region is carved into the largest naturally aligned blocks that
+    /// fit; bytes before the first and after the last such block are unused.
+    #[allow(dead_code)]
+    pub fn new(start: usize, end: usize) -> Allocator {
+        let mut allocator = Allocator {
+            free_list: [LinkedList::new(); NUM_ORDERS],
+            start,
+            end,
+            requested: 0,
+            allocated: 0,
+            counters: Counters::default(),
+        };
+
+        let mut addr = align_up(start, 1 << MIN_ORDER);
+        while addr.saturating_add(1 << MIN_ORDER) <= end {
+            let mut order = NUM_ORDERS - 1;
+            while addr % Allocator::size(order) != 0 || addr + Allocator::size(order) > end {
+                order -= 1;
+            }
+            unsafe { allocator.free_list[order].push(addr as *mut usize) };
+            addr += Allocator::size(order);
+        }
+
+        allocator
+    }
+
+    /// Returns the size of the blocks of `order`.
+    fn size(order: usize) -> usize {
+        1 << (order + MIN_ORDER)
+    }
+
+    /// Returns the order of the smallest block serving `layout`, if any.
+    fn order(layout: &Layout) -> Option<usize> {
+        let size = layout
+            .size()
+            .max(layout.align())
+            .max(mem::size_of::<usize>())
+            .checked_next_power_of_two()?;
+        let order = size.trailing_zeros() as usize - MIN_ORDER;
+        if order < NUM_ORDERS {
+            Some(order)
+        } else {
+            None
+        }
+    }
+
+    /// Removes the block at `addr` from the free list of `order`. Returns
+    /// `false` if it is not there.
+    fn take(&mut self, order: usize, addr: usize) -> bool {
+        match self.free_list[order]
+            .iter_mut()
+            .find(|node| node.value() as usize == addr)
+        {
+            Some(node) => {
+                node.pop();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl LocalAlloc for Allocator {
+    /// Allocates memory. Returns a pointer meeting the size and alignment
+    /// properties of `layout.size()` and `layout.align()`.
+    ///
+    /// If this method returns an `Ok(addr)`, `addr` will be non-null address
+    /// pointing to a block of storage suitable for holding an instance of
+    /// `layout`. In particular, the block will be at least `layout.size()`
+    /// bytes large and will be aligned to `layout.align()`. The returned block
+    /// of storage may or may not have its contents initialized or zeroed.
+    ///
+    /// # Safety
+    ///
+    /// The _caller_ must ensure that `layout.size() > 0` and that
+    /// `layout.align()` is a power of two. Parameters not meeting these
+    /// conditions may result in undefined behavior.
+    ///
+    /// # Errors
+    ///
+    /// Returning null pointer (`core::ptr::null_mut`)
+    /// indicates that either memory is exhausted
+    /// or `layout` does not meet this allocator's
+    /// size or alignment constraints.
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let order = match Allocator::order(&layout) {
+            Some(order) => order,
+            None => {
+                self.counters.fail();
+                return core::ptr::null_mut();
+            }
+        };
+
+        let mut current = match (order..NUM_ORDERS).find(|&o| !self.free_list[o].is_empty()) {
+            Some(current) => current,
+            None => {
+                self.counters.fail();
+                return core::ptr::null_mut();
+            }
+        };
+        let block = self.free_list[current].pop().unwrap() as usize;
+
+        // Split the block, keeping its lower half each time.
+        while current > order {
+            current -= 1;
+            self.free_list[current].push((block + Allocator::size(current)) as *mut usize);
+        }
+
+        self.requested += layout.size();
+        self.allocated += Allocator::size(order);
+        self.counters.alloc(&layout, self.allocated);
+        block as *mut u8
+    }
+
+    /// Deallocates the memory referenced by `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// The _caller_ must ensure the following:
+    ///
+    ///   * `ptr` must denote a block of memory currently allocated via this
+    ///     allocator
+    ///   * `layout` must properly represent the original layout used in the
+    ///     allocation call that returned `ptr`
+    ///
+    /// Parameters not meeting these conditions may result in undefined
+    /// behavior.
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let mut order = Allocator::order(&layout).expect("layout was never allocated");
+        self.requested -= layout.size();
+        self.allocated -= Allocator::size(order);
+        self.counters.free();
+
+        let mut block = ptr as usize;
+        while order + 1 < NUM_ORDERS && self.take(order, block ^ Allocator::size(order)) {
+            block &= !Allocator::size(order);
+            order += 1;
+        }
+        self.free_list[order].push(block as *mut usize);
+    }
+
+    fn usage(&self) -> Usage {
+        let mut free = 0;
+        let mut largest_free = 0;
+        for (order, list) in self.free_list.iter().enumerate() {
+            let count = list.iter().count();
+            free += count * Allocator::size(order);
+            if count > 0 {
+                largest_free = Allocator::size(order);
+            }
+        }
+
+        Usage {
+            total: self.end - self.start,
+            requested: self.requested,
+            allocated: self.allocated,
+            free,
+            largest_free,
+        }
+    }
+
+    fn counters(&self) -> Counters {
+        self.counters
+    }
+}
+
+impl fmt::Debug for Allocator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "buddy allocator: {}", self.usage())?;
+        for (order, list) in self.free_list.iter().enumerate() {
+            let count = list.iter().count();
+            if count > 0 {
+                write!(f, "\n  {:>10} bytes: {} free", Allocator::size(order), count)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,14 +1,18 @@
 use core::alloc::Layout;
+use core::fmt;
 use core::ptr;
 
 use crate::allocator::util::*;
-use crate::allocator::LocalAlloc;
+use crate::allocator::{Counters, LocalAlloc, Usage};
 
 /// A "bump" allocator: allocates memory by bumping a pointer; never frees.
-#[derive(Debug)]
 pub struct Allocator {
+    start: usize,
     current: usize,
     end: usize,
+    requested: usize,
+    allocated: usize,
+    counters: Counters,
 }
 
 impl Allocator {
@@ -17,8 +21,12 @@ impl Allocator {
     #[allow(dead_code)]
     pub fn new(start: usize, end: usize) -> Allocator {
         Allocator {
+            start,
             current: start,
             end: end,
+            requested: 0,
+            allocated: 0,
+            counters: Counters::default(),
         }
     }
 }
@@ -49,9 +57,13 @@ impl LocalAlloc for Allocator {
         let heap_ptr = align_up(self.current, layout.align());
         let new_heap_ptr = heap_ptr.saturating_add(layout.size());
         if new_heap_ptr >= self.end {
+            self.counters.fail();
             return core::ptr::null_mut() as *mut u8;
         }
         self.current = new_heap_ptr;
+        self.requested += layout.size();
+        self.allocated += layout.size();
+        self.counters.alloc(&layout, self.allocated);
         heap_ptr as *mut u8
     }
 
@@ -68,7 +80,31 @@ impl LocalAlloc for Allocator {
     ///
     /// Parameters not meeting these conditions may result in undefined
     /// behavior.
-    unsafe fn dealloc(&mut self, _ptr: *mut u8, _layout: Layout) {
+    unsafe fn dealloc(&mut self, _ptr: *mut u8, layout: Layout) {
         // LEAKED
+        self.requested -= layout.size();
+        self.allocated -= layout.size();
+        self.counters.free();
+    }
+
+    fn usage(&self) -> Usage {
+        let free = self.end - self.current;
+        Usage {
+            total: self.end - self.start,
+            requested: self.requested,
+            allocated: self.allocated,
+            free,
+            largest_free: free,
+        }
+    }
+
+    fn counters(&self) -> Counters {
+        self.counters
+    }
+}
+
+impl fmt::Debug for Allocator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "bump allocator at {:#x}: {}", self.current, self.usage())
     }
 }
@@ -0,0 +1,281 @@
+use core::alloc::Layout;
+use core::fmt;
+use core::mem;
+use core::ptr;
+
+use crate::allocator::util::*;
+use crate::allocator::{Counters, LocalAlloc, Usage};
+
+/// The number of guard bytes before and after every block.
+const REDZONE: usize = 16;
+/// The byte guard bytes are filled with.
+const REDZONE_BYTE: u8 = 0xBB;
+/// The byte fresh blocks are filled with, so that reads of uninitialized
+/// memory stand out.
+const POISON_INUSE: u8 = 0x5A;
+/// The byte freed blocks are filled with, so that uses after free stand out
+/// and are detected when the block leaves quarantine.
+const POISON_FREE: u8 = 0x6B;
+
+const MAGIC_LIVE: usize = 0x11fe_a110_c8ed_0001;
+const MAGIC_FREED: usize = 0xdead_f4ee_d000_0002;
+
+/// The number of freed blocks held back from the backend, so that a double
+/// free or a write after free of a recent block is caught.
+const QUARANTINE: usize = 64;
+
+/// The header right before the front redzone of every block. Live blocks are
+/// linked into a list.
+#[repr(C)]
+struct Header {
+    magic: usize,
+    size: usize,
+    align: usize,
+    /// The start of the block in the backend, and the block's layout there.
+    block: usize,
+    block_size: usize,
+    block_align: usize,
+    /// The number of the allocation, counting from 1.
+    seq: usize,
+    prev: *mut Header,
+    next: *mut Header,
+}
+
+/// A live allocation, as reported by `for_each_live()`.
+#[derive(Debug, Copy, Clone)]
+pub struct Live {
+    pub addr: usize,
+    pub size: usize,
+    pub align: usize,
+    pub seq: usize,
+}
+
+/// A debugging wrapper around the allocator `A`.
+///
+/// Every block is laid out as a header, a front redzone, the caller's bytes
+/// and a back redzone. Fresh blocks are filled with `POISON_INUSE` and freed
+/// ones with `POISON_FREE`. On `dealloc()` the wrapper panics, naming the
+/// block, if:
+///
+///   * the pointer was never allocated, or was freed already,
+///   * the `Layout` differs from the one the block was allocated with, or
+///   * a redzone was overwritten.
+///
+/// Freed blocks wait in a quarantine of `QUARANTINE` blocks before they go
+/// back to `A`; a block whose poison was overwritten meanwhile was written
+/// after it was freed, which panics too.
+pub struct Allocator<A: LocalAlloc> {
+    inner: A,
+    live: *mut Header,
+    live_count: usize,
+    live_bytes: usize,
+    seq: usize,
+    quarantine: [*mut Header; QUARANTINE],
+    next_quarantined: usize,
+}
+
+// The headers are owned by the allocator alone.
+unsafe impl<A: LocalAlloc + Send> Send for Allocator<A> {}
+
+impl<A: LocalAlloc> Allocator<A> {
+    /// Wraps `inner`.
+    pub fn wrap(inner: A) -> Allocator<A> {
+        Allocator {
+            inner,
+            live: ptr::null_mut(),
+            live_count: 0,
+            live_bytes: 0,
+            seq: 0,
+            quarantine: [ptr::null_mut(); QUARANTINE],
+            next_quarantined: 0,
+        }
+    }
+
+    /// Returns the offset of the caller's bytes in a block for `align`.
+    fn front(align: usize) -> usize {
+        align_up(mem::size_of::<Header>() + REDZONE, align)
+    }
+
+    /// Returns the header of the block whose caller's bytes start at `ptr`.
+    fn header(ptr: *mut u8) -> *mut Header {
+        (ptr as usize - REDZONE - mem::size_of::<Header>()) as *mut Header
+    }
+
+    /// Calls `f` with every live allocation, most recent first.
+    pub fn for_each_live<F: FnMut(Live)>(&self, mut f: F) {
+        let mut header = self.live;
+        while !header.is_null() {
+            unsafe {
+                f(Live {
+                    addr: header as usize + mem::size_of::<Header>() + REDZONE,
+                    size: (*header).size,
+                    align: (*header).align,
+                    seq: (*header).seq,
+                });
+                header = (*header).next;
+            }
+        }
+    }
+
+    /// Panics if a redzone of the block of `header` was overwritten.
+    unsafe fn check_redzones(&self, header: *mut Header) {
+        let user = header as usize + mem::size_of::<Header>() + REDZONE;
+        let front = core::slice::from_raw_parts((user - REDZONE) as *const u8, REDZONE);
+        let back = core::slice::from_raw_parts((user + (*header).size) as *const u8, REDZONE);
+        if let Some(i) = front.iter().position(|&b| b != REDZONE_BYTE) {
+            panic!(
+                "heap underflow: {} bytes before block {:#x} (size {}, allocation #{}) overwritten",
+                REDZONE - i,
+                user,
+                (*header).size,
+                (*header).seq
+            );
+        }
+        if let Some(i) = back.iter().rposition(|&b| b != REDZONE_BYTE) {
+            panic!(
+                "heap overflow: {} bytes after block {:#x} (size {}, allocation #{}) overwritten",
+                i + 1,
+                user,
+                (*header).size,
+                (*header).seq
+            );
+        }
+    }
+
+    /// Gives the block of `header`, which is freed, back to the inner
+    /// allocator, after checking that it was not written to since.
+    unsafe fn release(&mut self, header: *mut Header) {
+        let user = header as usize + mem::size_of::<Header>() + REDZONE;
+        let bytes = core::slice::from_raw_parts(user as *const u8, (*header).size);
+        if let Some(i) = bytes.iter().position(|&b| b != POISON_FREE) {
+            panic!(
+                "use after free: byte {} of block {:#x} (size {}, allocation #{}) written after it was freed",
+                i,
+                user,
+                (*header).size,
+                (*header).seq
+            );
+        }
+        self.check_redzones(header);
+
+        // Keep the magic: a late double free of this block is then caught
+        // unless the inner allocator hands the memory out again.
+        let layout = Layout::from_size_align_unchecked((*header).block_size, (*header).block_align);
+        self.inner.dealloc((*header).block as *mut u8, layout);
+    }
+}
+
+impl<A: LocalAlloc> LocalAlloc for Allocator<A> {
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let align = layout.align().max(mem::align_of::<Header>());
+        let front = Allocator::<A>::front(align);
+        let block_size = match front.checked_add(layout.size() + REDZONE) {
+            Some(size) => size,
+            None => return ptr::null_mut(),
+        };
+        let block = self
+            .inner
+            .alloc(Layout::from_size_align_unchecked(block_size, align));
+        if block.is_null() {
+            return block;
+        }
+
+        let user = block.add(front);
+        let header = Allocator::<A>::header(user);
+        self.seq += 1;
+        ptr::write(
+            header,
+            Header {
+                magic: MAGIC_LIVE,
+                size: layout.size(),
+                align: layout.align(),
+                block: block as usize,
+                block_size,
+                block_align: align,
+                seq: self.seq,
+                prev: ptr::null_mut(),
+                next: self.live,
+            },
+        );
+        if !self.live.is_null() {
+            (*self.live).prev = header;
+        }
+        self.live = header;
+        self.live_count += 1;
+        self.live_bytes += layout.size();
+
+        ptr::write_bytes(user.sub(REDZONE), REDZONE_BYTE, REDZONE);
+        ptr::write_bytes(user, POISON_INUSE, layout.size());
+        ptr::write_bytes(user.add(layout.size()), REDZONE_BYTE, REDZONE);
+        user
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let header = Allocator::<A>::header(ptr);
+        match (*header).magic {
+            MAGIC_LIVE => {}
+            MAGIC_FREED => panic!(
+                "double free of block {:p} (size {}, allocation #{})",
+                ptr,
+                (*header).size,
+                (*header).seq
+            ),
+            _ => panic!("free of {:p}, which was not allocated", ptr),
+        }
+        if (*header).size != layout.size() || (*header).align != layout.align() {
+            panic!(
+                "block {:p} (allocation #{}) allocated with size {} and align {}, freed with {:?}",
+                ptr,
+                (*header).seq,
+                (*header).size,
+                (*header).align,
+                layout
+            );
+        }
+        self.check_redzones(header);
+
+        let (prev, next) = ((*header).prev, (*header).next);
+        if prev.is_null() {
+            self.live = next;
+        } else {
+            (*prev).next = next;
+        }
+        if !next.is_null() {
+            (*next).prev = prev;
+        }
+        self.live_count -= 1;
+        self.live_bytes -= layout.size();
+
+        (*header).magic = MAGIC_FREED;
+        ptr::write_bytes(ptr, POISON_FREE, layout.size());
+
+        let oldest = mem::replace(&mut self.quarantine[self.next_quarantined], header);
+        self.next_quarantined = (self.next_quarantined + 1) % QUARANTINE;
+        if !oldest.is_null() {
+            self.release(oldest);
+        }
+    }
+
+    fn usage(&self) -> Usage {
+        Usage {
+            requested: self.live_bytes,
+            ..self.inner.usage()
+        }
+    }
+
+    /// Returns the counters of the inner allocator, whose blocks include
+    /// the headers and redzones.
+    fn counters(&self) -> Counters {
+        self.inner.counters()
+    }
+}
+
+impl<A: LocalAlloc + fmt::Debug> fmt::Debug for Allocator<A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:?} (debugging: {} live allocations of {} bytes)",
+            self.inner, self.live_count, self.live_bytes
+        )
+    }
+}
@@ -0,0 +1,272 @@
+use core::alloc::Layout;
+use core::fmt;
+use core::mem;
+use core::ptr;
+
+use crate::allocator::linked_list::LinkedList;
+use crate::allocator::util::*;
+use crate::allocator::LocalAlloc;
+
+/// The size of a slab, which is also its alignment: the slab holding an
+/// object is found by aligning the object's address down.
+pub const SLAB_SIZE: usize = 16 * 1024;
+
+/// The header at the start of every slab. Its first word links the slab into
+/// the `partial` or `full` list of its cache.
+#[repr(C)]
+struct Slab {
+    link: usize,
+    free: LinkedList,
+    in_use: usize,
+}
+
+/// The numbers describing a `SlabCache`.
+#[derive(Debug, Copy, Clone)]
+pub struct SlabStats {
+    /// The name of the cache.
+    pub name: &'static str,
+    /// The size of the cached objects.
+    pub size: usize,
+    /// The number of slabs.
+    pub slabs: usize,
+    /// The number of objects the slabs hold, free or not.
+    pub objects: usize,
+    /// The number of objects handed out.
+    pub in_use: usize,
+    /// The number of allocations and deallocations served so far.
+    pub allocs: usize,
+    pub frees: usize,
+    /// The number of empty slabs given back so far.
+    pub reclaimed: usize,
+}
+
+impl fmt::Display for SlabStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<12} {:>6} bytes: {:>5}/{:<5} objects in {:>3} slabs, {} allocs, {} frees, {} slabs reclaimed",
+            self.name,
+            self.size,
+            self.in_use,
+            self.objects,
+            self.slabs,
+            self.allocs,
+            self.frees,
+            self.reclaimed
+        )
+    }
+}
+
+/// A cache of equally sized objects, carved out of slabs of `SLAB_SIZE`
+/// bytes that are allocated from a backing `LocalAlloc`.
+///
+/// Slabs with free objects are kept on the `partial` list and slabs without
+/// on the `full` list, so that an allocation never searches. Empty slabs are
+/// kept until `reclaim()` gives them back.
+///
+/// If the cache has a constructor, every object is constructed once, when its
+/// slab is allocated, and must be freed in its constructed state. The free
+/// list then links objects through a word after each object rather than
+/// through the object's first word, which would clobber it.
+pub struct SlabCache {
+    name: &'static str,
+    size: usize,
+    align: usize,
+    ctor: Option<fn(*mut u8)>,
+    partial: LinkedList,
+    full: LinkedList,
+    slabs: usize,
+    in_use: usize,
+    allocs: usize,
+    frees: usize,
+    reclaimed: usize,
+}
+
+impl SlabCache {
+    /// Returns an empty cache named `name` of objects of `size` bytes
+    /// aligned to `align`, constructed with `ctor` if it is given.
+    pub const fn new(
+        name: &'static str,
+        size: usize,
+        align: usize,
+        ctor: Option<fn(*mut u8)>,
+    ) -> SlabCache {
+        SlabCache {
+            name,
+            size,
+            align,
+            ctor,
+            partial: LinkedList::new(),
+            full: LinkedList::new(),
+            slabs: 0,
+            in_use: 0,
+            allocs: 0,
+            frees: 0,
+            reclaimed: 0,
+        }
+    }
+
+    /// Returns the name of this cache.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns `true` if this cache serves allocations of `layout`.
+    pub fn serves(&self, layout: &Layout) -> bool {
+        layout.size() == self.size && layout.align() <= self.align
+    }
+
+    /// Returns the layout of a slab, as allocated from the backing allocator.
+    fn slab_layout() -> Layout {
+        Layout::from_size_align(SLAB_SIZE, SLAB_SIZE).unwrap()
+    }
+
+    /// Returns the offset from an object of the word linking it into the
+    /// free list of its slab.
+    fn link(&self) -> usize {
+        match self.ctor {
+            Some(_) => align_up(self.size, mem::align_of::<usize>()),
+            None => 0,
+        }
+    }
+
+    /// Returns the distance between two objects in a slab.
+    fn stride(&self) -> usize {
+        let size = self.size.max(self.link() + mem::size_of::<usize>());
+        align_up(size, self.align.max(mem::align_of::<usize>()))
+    }
+
+    /// Returns the offset of the first object in a slab.
+    fn first(&self) -> usize {
+        align_up(mem::size_of::<Slab>(), self.align)
+    }
+
+    /// Returns the number of objects a slab holds.
+    fn capacity(&self) -> usize {
+        (SLAB_SIZE - self.first()) / self.stride()
+    }
+
+    /// Allocates a slab from `backend`, constructs its objects and puts it
+    /// on the `partial` list. Returns `false` if `backend` is out of memory.
+    unsafe fn grow<A: LocalAlloc>(&mut self, backend: &mut A) -> bool {
+        let slab = backend.alloc(SlabCache::slab_layout()) as *mut Slab;
+        if slab.is_null() {
+            return false;
+        }
+
+        ptr::write(
+            slab,
+            Slab {
+                link: 0,
+                free: LinkedList::new(),
+                in_use: 0,
+            },
+        );
+
+        // Push the objects from the end so that they are handed out in
+        // address order.
+        for i in (0..self.capacity()).rev() {
+            let object = (slab as usize + self.first() + i * self.stride()) as *mut u8;
+            if let Some(ctor) = self.ctor {
+                ctor(object);
+            }
+            (*slab).free.push(object.add(self.link()) as *mut usize);
+        }
+
+        self.partial.push(slab as *mut usize);
+        self.slabs += 1;
+        true
+    }
+
+    /// Allocates an object, growing the cache from `backend` if every slab
+    /// is full. Returns a null pointer if `backend` is out of memory.
+    ///
+    /// # Safety
+    ///
+    /// `backend` must be the allocator every slab of this cache came from.
+    pub unsafe fn alloc<A: LocalAlloc>(&mut self, backend: &mut A) -> *mut u8 {
+        if self.partial.is_empty() && !self.grow(backend) {
+            return ptr::null_mut();
+        }
+
+        let slab = self.partial.peek().unwrap() as *mut Slab;
+        let object = ((*slab).free.pop().unwrap() as *mut u8).sub(self.link());
+        (*slab).in_use += 1;
+        if (*slab).free.is_empty() {
+            let slab = self.partial.pop().unwrap();
+            self.full.push(slab);
+        }
+
+        self.in_use += 1;
+        self.allocs += 1;
+        object
+    }
+
+    /// Returns the object at `ptr` to this cache.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated from this cache and not freed since.
+    pub unsafe fn dealloc(&mut self, ptr: *mut u8) {
+        let slab = align_down(ptr as usize, SLAB_SIZE) as *mut Slab;
+        if (*slab).free.is_empty() {
+            // The slab was full: it has a free object again.
+            let node = self
+                .full
+                .iter_mut()
+                .find(|node| node.value() as usize == slab as usize)
+                .expect("object freed to the wrong slab cache");
+            node.pop();
+            self.partial.push(slab as *mut usize);
+        }
+
+        (*slab).free.push(ptr.add(self.link()) as *mut usize);
+        (*slab).in_use -= 1;
+        self.in_use -= 1;
+        self.frees += 1;
+    }
+
+    /// Gives every empty slab back to `backend`. Returns the number of bytes
+    /// given back.
+    ///
+    /// # Safety
+    ///
+    /// `backend` must be the allocator every slab of this cache came from.
+    pub unsafe fn reclaim<A: LocalAlloc>(&mut self, backend: &mut A) -> usize {
+        let mut kept = LinkedList::new();
+        let mut freed = 0;
+        while let Some(slab) = self.partial.pop() {
+            if (*(slab as *mut Slab)).in_use == 0 {
+                backend.dealloc(slab as *mut u8, SlabCache::slab_layout());
+                freed += 1;
+            } else {
+                kept.push(slab);
+            }
+        }
+        self.partial = kept;
+
+        self.slabs -= freed;
+        self.reclaimed += freed;
+        freed * SLAB_SIZE
+    }
+
+    /// Returns the statistics of this cache.
+    pub fn stats(&self) -> SlabStats {
+        SlabStats {
+            name: self.name,
+            size: self.size,
+            slabs: self.slabs,
+            objects: self.slabs * self.capacity(),
+            in_use: self.in_use,
+            allocs: self.allocs,
+            frees: self.frees,
+            reclaimed: self.reclaimed,
+        }
+    }
+}
+
+impl fmt::Debug for SlabCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.stats())
+    }
+}
@@ -1,3 +1,20 @@
+extern crate alloc;
+use alloc::raw_vec::RawVec;
+
+/// Returns `size` bytes of host memory, their start and end, and the
+/// allocator `new` makes of them. The memory is freed with the `RawVec`,
+/// which must thus outlive the allocator.
+fn test_heap<A, F>(size: usize, new: F) -> (RawVec<u8>, usize, usize, A)
+where
+    F: FnOnce(usize, usize) -> A,
+{
+    let mem: RawVec<u8> = RawVec::with_capacity(size);
+    let start = mem.ptr() as usize;
+    let end = start + size;
+    let allocator = new(start, end);
+    (mem, start, end, allocator)
+}
+
 mod align_util {
     use crate::allocator::util::{align_down, align_up};
 
@@ -71,22 +88,15 @@ mod align_util {
 }
 
 mod allocator {
-    extern crate alloc;
-    use alloc::raw_vec::RawVec;
-
     use core::alloc::Layout;
 
-    use crate::allocator::{bin, bump, LocalAlloc};
+    use crate::allocator::{bin, buddy, bump, Counters, LocalAlloc, Usage, NUM_SIZE_CLASSES};
 
     macro_rules! test_allocators {
         (@$kind:ident, $name:ident, $mem:expr, |$info:pat| $block:expr) => {
             #[test]
             fn $name() {
-                let mem: RawVec<u8> = RawVec::with_capacity($mem);
-                let start = mem.ptr() as usize;
-                let end = start + $mem;
-
-                let allocator = $kind::Allocator::new(start, end);
+                let (_mem, start, end, allocator) = super::test_heap($mem, $kind::Allocator::new);
                 let $info = (start, end, allocator);
 
                 #[allow(unused_unsafe)]
@@ -96,9 +106,16 @@ mod allocator {
             }
         };
 
-        ($bin:ident, $bump:ident, $mem:expr, |$info:pat| $block:expr) => (
+        ($bin:ident, $bump:ident, $buddy:ident, $mem:expr, |$info:pat| $block:expr) => (
             test_allocators!(@bin, $bin, $mem, |$info| $block);
             test_allocators!(@bump, $bump, $mem, |$info| $block);
+            test_allocators!(@buddy, $buddy, $mem, |$info| $block);
+        );
+
+        // For the allocators that can free memory.
+        ($bin:ident, $buddy:ident, $mem:expr, |$info:pat| $block:expr) => (
+            test_allocators!(@bin, $bin, $mem, |$info| $block);
+            test_allocators!(@buddy, $buddy, $mem, |$info| $block);
         );
     }
 
@@ -157,12 +174,12 @@ mod allocator {
         }
     }
 
-    test_allocators!(bin_exhausted, bump_exhausted, 128, |(_, _, mut a)| {
+    test_allocators!(bin_exhausted, bump_exhausted, buddy_exhausted, 128, |(_, _, mut a)| {
         let result = a.alloc(layout!(1024, 128));
         assert!(result.is_null());
     });
 
-    test_allocators!(bin_alloc, bump_alloc, 8 * (1 << 20), |(start, end, a)| {
+    test_allocators!(bin_alloc, bump_alloc, buddy_alloc, 8 * (1 << 20), |(start, end, a)| {
         let layouts = [
             layout!(16, 16),
             layout!(16, 128),
@@ -188,7 +205,7 @@ mod allocator {
         test_layouts!(layouts, start, end, a);
     });
 
-    test_allocators!(bin_alloc_2, bump_alloc_2, 16 * (1 << 20), |(
+    test_allocators!(bin_alloc_2, bump_alloc_2, buddy_alloc_2, 16 * (1 << 20), |(
         start,
         end,
         a,
@@ -208,7 +225,7 @@ mod allocator {
         }
     }
 
-    test_allocators!(bin_dealloc_s, bump_dealloc_s, 4096, |(_, _, mut a)| {
+    test_allocators!(bin_dealloc_s, bump_dealloc_s, buddy_dealloc_s, 4096, |(_, _, mut a)| {
         let layouts = [layout!(16, 16), layout!(16, 128), layout!(16, 256)];
 
         let mut pointers: Vec<(usize, Layout)> = vec![];
@@ -226,7 +243,7 @@ mod allocator {
         }
     });
 
-    test_allocators!(@bin, bin_dealloc_1, 65536, |(_, _, mut a)| {
+    test_allocators!(bin_dealloc_1, buddy_dealloc_1, 65536, |(_, _, mut a)| {
         let layouts = [
             layout!(16, 16),
             layout!(16, 256),
@@ -266,7 +283,7 @@ mod allocator {
         }
     });
 
-    test_allocators!(@bin, bin_dealloc_2, 8192, |(_, _, mut a)| {
+    test_allocators!(bin_dealloc_2, buddy_dealloc_2, 8192, |(_, _, mut a)| {
         let layouts = [
             layout!(3072, 16),
             layout!(512, 32),
@@ -289,6 +306,343 @@ mod allocator {
             }
         }
     });
+
+    test_allocators!(@buddy, buddy_split_coalesce, 1 << 16, |(start, end, mut a)| {
+        // Carve the heap into the smallest blocks, then free them all: the
+        // blocks must coalesce back so that the whole heap can be handed out
+        // again.
+        let whole = a.usage().largest_free;
+        assert!(whole >= 1 << 15);
+
+        let small = layout!(8, 8);
+        let mut ptrs = vec![];
+        loop {
+            let ptr = a.alloc(small.clone());
+            if ptr.is_null() {
+                break;
+            }
+            assert!(ptr as usize >= start && ptr as usize + 8 <= end);
+            ptrs.push(ptr);
+        }
+        assert_eq!(a.usage().free, 0);
+        assert!(a.alloc(small.clone()).is_null());
+
+        for ptr in ptrs {
+            a.dealloc(ptr, small.clone());
+        }
+        assert_eq!(a.usage().largest_free, whole);
+
+        let ptr = a.alloc(layout!(whole, 8));
+        assert!(!ptr.is_null());
+        scribble(ptr, whole);
+        a.dealloc(ptr, layout!(whole, 8));
+    });
+
+    test_allocators!(@buddy, buddy_natural_alignment, 1 << 16, |(_, _, mut a)| {
+        // Blocks are aligned to their size, whatever the requested alignment.
+        for &size in &[8, 24, 100, 512, 3000] {
+            let ptr = a.alloc(layout!(size, 1));
+            assert!(!ptr.is_null());
+            let block = size.next_power_of_two();
+            assert!(ptr as usize % block == 0, "{:x} is not aligned to {}", ptr as usize, block);
+        }
+    });
+
+    test_allocators!(bin_usage, bump_usage, buddy_usage, 1 << 16, |(start, end, mut a)| {
+        let empty = a.usage();
+        assert_eq!(empty.total, end - start);
+        assert_eq!(empty.requested, 0);
+        assert_eq!(empty.allocated, 0);
+
+        let ptr = a.alloc(layout!(100, 8));
+        assert!(!ptr.is_null());
+        let used = a.usage();
+        assert_eq!(used.requested, 100);
+        assert!(used.allocated >= 100);
+        assert!(used.free + used.allocated <= used.total);
+        assert!(used.largest_free <= used.free);
+
+        a.dealloc(ptr, layout!(100, 8));
+        let freed = a.usage();
+        assert_eq!(freed.requested, 0);
+        assert_eq!(freed.allocated, 0);
+    });
+
+    test_allocators!(bin_counters, bump_counters, buddy_counters, 1 << 16, |(_, _, mut a)| {
+        assert_eq!(a.counters(), Counters::default());
+
+        let small = a.alloc(layout!(8, 8));
+        let large = a.alloc(layout!(1000, 8));
+        assert!(!small.is_null() && !large.is_null());
+        let peak = a.usage().allocated;
+        a.dealloc(small, layout!(8, 8));
+        a.dealloc(large, layout!(1000, 8));
+        assert!(a.alloc(layout!(1 << 17, 8)).is_null());
+
+        let counters = a.counters();
+        assert_eq!(counters.allocs, 2);
+        assert_eq!(counters.frees, 2);
+        assert_eq!(counters.failed, 1);
+        assert_eq!(counters.peak, peak);
+        assert_eq!(counters.by_class[0], 1);
+        assert_eq!(counters.by_class[7], 1);
+        assert_eq!(counters.by_class.iter().sum::<usize>(), 2);
+    });
+
+    #[test]
+    fn size_classes() {
+        assert_eq!(Counters::size_class(1), 0);
+        assert_eq!(Counters::size_class(8), 0);
+        assert_eq!(Counters::size_class(9), 1);
+        assert_eq!(Counters::size_class(1024), 7);
+        assert_eq!(Counters::size_class(1 << 22), NUM_SIZE_CLASSES - 1);
+        assert_eq!(Counters::size_class(usize::max_value() / 2), NUM_SIZE_CLASSES - 1);
+        assert_eq!(Counters::class_size(7), 1024);
+    }
+
+    #[test]
+    fn usage_fragmentation() {
+        let usage = Usage {
+            total: 1000,
+            requested: 150,
+            allocated: 200,
+            free: 400,
+            largest_free: 100,
+        };
+        assert_eq!(usage.internal_fragmentation(), 25);
+        assert_eq!(usage.external_fragmentation(), 75);
+        assert_eq!(usage.lost(), 400);
+        assert_eq!(Usage::default().external_fragmentation(), 0);
+    }
+}
+
+mod slab {
+    use crate::allocator::slab::{SlabCache, SLAB_SIZE};
+    use crate::allocator::{buddy, LocalAlloc};
+
+    macro test_cache($name:ident, $slabs:expr, |$cache:ident, $backend:ident| $block:expr) {
+        #[test]
+        fn $name() {
+            let (_mem, _, _, mut $backend) = super::test_heap($slabs * SLAB_SIZE, buddy::Allocator::new);
+
+            #[allow(unused_unsafe)]
+            unsafe {
+                $block
+            }
+        }
+    }
+
+    fn construct(ptr: *mut u8) {
+        unsafe { ::core::ptr::write_bytes(ptr, 0x5A, 24) };
+    }
+
+    test_cache!(slab_reuse, 4, |cache, backend| {
+        let mut cache = SlabCache::new("test", 48, 16, None);
+
+        let a = cache.alloc(&mut backend);
+        let b = cache.alloc(&mut backend);
+        assert!(!a.is_null() && !b.is_null());
+        assert_ne!(a, b);
+        assert_eq!(a as usize % 16, 0);
+        assert_eq!(b as usize % 16, 0);
+        assert!((b as usize).wrapping_sub(a as usize) >= 48);
+
+        cache.dealloc(a);
+        assert_eq!(cache.alloc(&mut backend), a);
+
+        let stats = cache.stats();
+        assert_eq!(stats.slabs, 1);
+        assert_eq!(stats.in_use, 2);
+        assert_eq!(stats.allocs, 3);
+        assert_eq!(stats.frees, 1);
+    });
+
+    test_cache!(slab_grow_and_reclaim, 8, |cache, backend| {
+        let mut cache = SlabCache::new("test", 1000, 8, None);
+
+        // Fill more than one slab, then free everything.
+        let mut ptrs = vec![];
+        for _ in 0..40 {
+            let ptr = cache.alloc(&mut backend);
+            assert!(!ptr.is_null());
+            ::core::ptr::write_bytes(ptr, 0xAF, 1000);
+            ptrs.push(ptr);
+        }
+        let stats = cache.stats();
+        assert!(stats.slabs >= 3);
+        assert!(stats.objects >= 40);
+
+        for ptr in ptrs {
+            cache.dealloc(ptr);
+        }
+        assert_eq!(cache.stats().in_use, 0);
+
+        let slabs = cache.stats().slabs;
+        assert_eq!(cache.reclaim(&mut backend), slabs * SLAB_SIZE);
+        assert_eq!(cache.stats().slabs, 0);
+        assert_eq!(cache.stats().reclaimed, slabs);
+        assert_eq!(backend.usage().allocated, 0);
+
+        // The cache grows again after a reclaim.
+        assert!(!cache.alloc(&mut backend).is_null());
+    });
+
+    test_cache!(slab_reclaim_keeps_used, 4, |cache, backend| {
+        let mut cache = SlabCache::new("test", 64, 8, None);
+        let ptr = cache.alloc(&mut backend);
+        assert_eq!(cache.reclaim(&mut backend), 0);
+        cache.dealloc(ptr);
+        assert_eq!(cache.reclaim(&mut backend), SLAB_SIZE);
+    });
+
+    test_cache!(slab_constructor, 4, |cache, backend| {
+        let mut cache = SlabCache::new("test", 24, 8, Some(construct));
+
+        let mut ptrs = vec![];
+        for _ in 0..100 {
+            let ptr = cache.alloc(&mut backend);
+            assert!(!ptr.is_null());
+            ptrs.push(ptr);
+        }
+
+        // Objects come back constructed, and freeing them keeps them so.
+        for &ptr in &ptrs {
+            cache.dealloc(ptr);
+        }
+        for _ in 0..100 {
+            let ptr = cache.alloc(&mut backend);
+            let object = ::core::slice::from_raw_parts(ptr, 24);
+            assert!(object.iter().all(|&b| b == 0x5A));
+        }
+    });
+
+    test_cache!(slab_exhausted, 1, |cache, backend| {
+        // With room for at most one slab, the cache runs out of memory once
+        // that slab is full.
+        let mut cache = SlabCache::new("test", 4096, 8, None);
+        let mut count = 0;
+        while !cache.alloc(&mut backend).is_null() {
+            count += 1;
+            assert!(count <= SLAB_SIZE / 4096);
+        }
+    });
+}
+
+mod debug {
+    use core::alloc::Layout;
+
+    use crate::allocator::{bin, debug, LocalAlloc};
+
+    macro test_debug($(#[$attr:meta])* $name:ident, |$a:ident| $block:expr) {
+        #[test]
+        $(#[$attr])*
+        fn $name() {
+            let (_mem, _, _, mut $a) = super::test_heap(1 << 20, |start, end| {
+                debug::Allocator::wrap(bin::Allocator::new(start, end))
+            });
+
+            #[allow(unused_unsafe)]
+            unsafe {
+                $block
+            }
+        }
+    }
+
+    macro layout($size:expr, $align:expr) {
+        Layout::from_size_align($size, $align).unwrap()
+    }
+
+    fn live(a: &debug::Allocator<bin::Allocator>) -> Vec<(usize, usize)> {
+        let mut live = vec![];
+        a.for_each_live(|l| live.push((l.addr, l.size)));
+        live
+    }
+
+    test_debug!(debug_alloc, |a| {
+        let layouts = [layout!(1, 1), layout!(24, 8), layout!(100, 64), layout!(4096, 4096)];
+        let mut ptrs = vec![];
+        for layout in layouts.iter() {
+            let ptr = a.alloc(layout.clone());
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % layout.align(), 0);
+            let bytes = ::core::slice::from_raw_parts(ptr, layout.size());
+            assert!(bytes.iter().all(|&b| b == 0x5A));
+            ::core::ptr::write_bytes(ptr, 0xAF, layout.size());
+            ptrs.push(ptr);
+        }
+
+        let mut expected: Vec<_> = ptrs
+            .iter()
+            .zip(layouts.iter())
+            .map(|(&ptr, layout)| (ptr as usize, layout.size()))
+            .collect();
+        expected.reverse();
+        assert_eq!(live(&a), expected);
+        assert_eq!(a.usage().requested, 1 + 24 + 100 + 4096);
+
+        for (&ptr, layout) in ptrs.iter().zip(layouts.iter()) {
+            a.dealloc(ptr, layout.clone());
+        }
+        assert!(live(&a).is_empty());
+        assert_eq!(a.usage().requested, 0);
+    });
+
+    test_debug!(debug_poison_freed, |a| {
+        let ptr = a.alloc(layout!(32, 8));
+        ::core::ptr::write_bytes(ptr, 0, 32);
+        a.dealloc(ptr, layout!(32, 8));
+        let bytes = ::core::slice::from_raw_parts(ptr, 32);
+        assert!(bytes.iter().all(|&b| b == 0x6B));
+    });
+
+    test_debug!(debug_quarantine, |a| {
+        // Blocks leave the quarantine and are reused by the backend.
+        let mut seen = vec![];
+        for _ in 0..1000 {
+            let ptr = a.alloc(layout!(512, 8));
+            assert!(!ptr.is_null());
+            seen.push(ptr as usize);
+            a.dealloc(ptr, layout!(512, 8));
+        }
+        seen.sort();
+        seen.dedup();
+        assert!(seen.len() <= 65);
+    });
+
+    test_debug!(#[should_panic(expected = "double free")] debug_double_free, |a| {
+        let ptr = a.alloc(layout!(16, 8));
+        a.dealloc(ptr, layout!(16, 8));
+        a.dealloc(ptr, layout!(16, 8));
+    });
+
+    test_debug!(#[should_panic(expected = "allocated with size 16")] debug_layout_mismatch, |a| {
+        let ptr = a.alloc(layout!(16, 8));
+        a.dealloc(ptr, layout!(32, 8));
+    });
+
+    test_debug!(#[should_panic(expected = "heap overflow")] debug_overflow, |a| {
+        let ptr = a.alloc(layout!(16, 8));
+        *ptr.add(16) = 0;
+        a.dealloc(ptr, layout!(16, 8));
+    });
+
+    test_debug!(#[should_panic(expected = "heap underflow")] debug_underflow, |a| {
+        let ptr = a.alloc(layout!(16, 8));
+        *ptr.sub(1) = 0;
+        a.dealloc(ptr, layout!(16, 8));
+    });
+
+    test_debug!(#[should_panic(expected = "use after free")] debug_use_after_free, |a| {
+        let ptr = a.alloc(layout!(16, 8));
+        a.dealloc(ptr, layout!(16, 8));
+        *ptr = 0;
+
+        // Push the block out of the quarantine.
+        for _ in 0..64 {
+            let other = a.alloc(layout!(16, 8));
+            a.dealloc(other, layout!(16, 8));
+        }
+    });
 }
 
 mod linked_list {
@@ -0,0 +1,135 @@
+mod symbols;
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use aarch64::affinity;
+
+use crate::console::kprintln;
+use crate::param::{IO_BASE, KERNEL_BASE};
+use crate::traps::TrapFrame;
+
+pub use self::symbols::{lookup, Symbol};
+
+/// The most frames a backtrace shows.
+pub const MAX_FRAMES: usize = 32;
+
+/// The cores, by bit, that printed the backtrace of a kernel exception they
+/// are about to panic on, and so leave out the panic's own backtrace.
+static FROM_TRAP: AtomicUsize = AtomicUsize::new(0);
+
+/// A frame of a backtrace: the address code returns to, or faulted at.
+#[derive(Debug, Copy, Clone)]
+pub struct Frame {
+    pub pc: usize,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match lookup(self.pc) {
+            Some(symbol) => write!(
+                f,
+                "{:#018x} {}+{:#x}",
+                self.pc,
+                symbol.name,
+                self.pc - symbol.addr
+            ),
+            None => write!(f, "{:#018x} <unknown>", self.pc),
+        }
+    }
+}
+
+/// An iterator over the frames of a kernel stack, following the chain of
+/// frame records (`x29`, `lr`) the kernel is built to keep.
+///
+/// The walk stops at the first frame record that is not in the kernel's
+/// linear map of RAM or that is not above the one before it, since the
+/// stack grows down, and after `MAX_FRAMES` frames.
+pub struct Frames {
+    pc: Option<usize>,
+    fp: usize,
+    count: usize,
+}
+
+impl Frames {
+    /// Returns the frames of the code that faulted at `pc` with the frame
+    /// pointer `fp`.
+    pub fn new(pc: usize, fp: usize) -> Frames {
+        Frames { pc: Some(pc), fp, count: 0 }
+    }
+
+    /// Returns the frames of the kernel code that took the exception of `tf`.
+    pub fn from_trap_frame(tf: &TrapFrame) -> Frames {
+        Frames::new(tf.elr as usize, tf.x[29] as usize)
+    }
+
+    /// Returns the frames of the caller.
+    #[inline(always)]
+    pub fn current() -> Frames {
+        let fp: usize;
+        unsafe { asm!("mov $0, x29" : "=r"(fp) ::: "volatile") };
+        Frames { pc: None, fp, count: 0 }
+    }
+
+    /// Returns `true` if a frame record at `fp` is in the linear map of RAM.
+    fn is_valid(fp: usize) -> bool {
+        fp >= KERNEL_BASE && fp < IO_BASE - 16 && fp % 16 == 0
+    }
+}
+
+impl Iterator for Frames {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        if self.count == MAX_FRAMES {
+            return None;
+        }
+        if let Some(pc) = self.pc.take() {
+            self.count += 1;
+            return Some(Frame { pc });
+        }
+        if !Frames::is_valid(self.fp) {
+            return None;
+        }
+
+        let (next, lr) = unsafe {
+            let record = self.fp as *const usize;
+            (*record, *record.add(1))
+        };
+        if lr == 0 {
+            return None;
+        }
+
+        // Stop at the next frame unless it is further up the stack.
+        self.fp = if next > self.fp { next } else { 0 };
+        self.count += 1;
+        // Point into the call instruction, which may be the last of its
+        // function, rather than to the instruction after it.
+        Some(Frame { pc: lr - 4 })
+    }
+}
+
+/// Prints `frames`, one per line.
+pub fn print(frames: Frames) {
+    kprintln!("backtrace:");
+    for (i, frame) in frames.enumerate() {
+        kprintln!("  #{:<2} {}", i, frame);
+    }
+}
+
+/// Prints the backtrace of the kernel code that took the exception of `tf`,
+/// which the current core panics on next, in place of the panic's.
+pub fn print_trap(tf: &TrapFrame) {
+    print(Frames::from_trap_frame(tf));
+    FROM_TRAP.fetch_or(1 << affinity(), Ordering::Relaxed);
+}
+
+/// Prints the backtrace of the caller, a panic, unless the current core
+/// printed the one of the exception it panics on already.
+#[inline(always)]
+pub fn backtrace() {
+    let bit = 1 << affinity();
+    if FROM_TRAP.fetch_and(!bit, Ordering::Relaxed) & bit == 0 {
+        print(Frames::current())
+    }
+}
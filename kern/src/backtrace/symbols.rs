@@ -0,0 +1,71 @@
+use core::{mem, slice, str};
+
+/// The magic number the symbol table starts with.
+const MAGIC: &[u8; 8] = b"KSYMTAB\0";
+
+/// A symbol table entry, as laid out by `bin/gen-ksyms.py`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct Entry {
+    addr: u64,
+    size: u64,
+    name: u32,
+    len: u32,
+}
+
+/// A function of the kernel.
+#[derive(Debug, Copy, Clone)]
+pub struct Symbol {
+    pub name: &'static str,
+    pub addr: usize,
+    pub size: usize,
+}
+
+/// The kernel's symbol table, embedded into the `.ksyms` section after
+/// linking. Empty if the build did not embed one.
+fn table() -> (&'static [Entry], &'static [u8]) {
+    extern "C" {
+        static __ksyms_beg: u8;
+        static __ksyms_end: u8;
+    }
+
+    unsafe {
+        let beg = &__ksyms_beg as *const u8;
+        let len = &__ksyms_end as *const u8 as usize - beg as usize;
+        let section = slice::from_raw_parts(beg, len);
+        if len < 16 || &section[..8] != MAGIC {
+            return (&[], &[]);
+        }
+
+        let count = *(beg.add(8) as *const u64) as usize;
+        let entries_len = count * mem::size_of::<Entry>();
+        if 16 + entries_len > len {
+            return (&[], &[]);
+        }
+        let entries = slice::from_raw_parts(beg.add(16) as *const Entry, count);
+        (entries, &section[16 + entries_len..])
+    }
+}
+
+/// Returns the function `addr` lies in, if the symbol table has it.
+pub fn lookup(addr: usize) -> Option<Symbol> {
+    let (entries, strings) = table();
+    let i = match entries.binary_search_by_key(&(addr as u64), |entry| entry.addr) {
+        Ok(i) => i,
+        Err(0) => return None,
+        Err(i) => i - 1,
+    };
+
+    // Symbols of assembly routines have no size: they extend to the next.
+    let entry = entries[i];
+    if entry.size != 0 && addr as u64 >= entry.addr + entry.size {
+        return None;
+    }
+
+    let name = strings.get(entry.name as usize..(entry.name + entry.len) as usize)?;
+    Some(Symbol {
+        name: str::from_utf8(name).unwrap_or("<invalid>"),
+        addr: entry.addr as usize,
+        size: entry.size as usize,
+    })
+}
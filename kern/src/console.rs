@@ -1,4 +1,5 @@
 use core::fmt;
+use core::sync::atomic::spin_loop_hint;
 use pi::uart::MiniUart;
 use shim::io;
 
@@ -31,6 +32,11 @@ impl Console {
     }
 
 
+    /// Returns `true` if there is a byte ready to read from the UART device.
+    pub fn has_byte(&mut self) -> bool {
+        self.inner().has_byte()
+    }
+
     /// Reads a byte from the UART device, blocking until a byte is available.
     pub fn read_byte(&mut self) -> u8 {
         self.inner().read_byte()
@@ -68,6 +74,14 @@ impl fmt::Write for Console {
 /// Global `Console` singleton.
 pub static CONSOLE: Mutex<Console> = Mutex::new(Console::new());
 
+/// Blocks until the console has a byte ready to read. The console is only
+/// locked to poll it, so other cores can print meanwhile.
+pub fn wait_for_byte() {
+    while !CONSOLE.lock().has_byte() {
+        spin_loop_hint();
+    }
+}
+
 /// Internal function called by the `kprint[ln]!` macros.
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
@@ -1,6 +1,6 @@
 pub mod sd;
 
-use alloc::rc::Rc;
+use alloc::sync::Arc;
 use core::fmt::{self, Debug};
 use shim::io;
 use shim::ioerr;
@@ -10,16 +10,14 @@ pub use fat32::traits;
 use fat32::vfat::{Dir, Entry, File, VFat, VFatHandle};
 
 use self::sd::Sd;
-use crate::mutex::Mutex;
+use crate::mutex::{Mutex, Once};
 
 #[derive(Clone)]
-pub struct PiVFatHandle(Rc<Mutex<VFat<Self>>>);
+pub struct PiVFatHandle(Arc<Mutex<VFat<Self>>>);
 
-// These impls are *unsound*. We should use `Arc` instead of `Rc` to implement
-// `Sync` and `Send` trait for `PiVFatHandle`. However, `Arc` uses atomic memory
-// access, which requires MMU to be initialized on ARM architecture. Since we
-// have enabled only one core of the board, these unsound impls will not cause
-// any immediate harm for now. We will fix this in the future.
+// `VFat` holds a `Box<dyn BlockDevice>`, which is not `Send`, so these cannot
+// be derived. The block device is only ever reached through the `Mutex`,
+// though, which lets only one core at a time reach it.
 unsafe impl Send for PiVFatHandle {}
 unsafe impl Sync for PiVFatHandle {}
 
@@ -31,14 +29,14 @@ impl Debug for PiVFatHandle {
 
 impl VFatHandle for PiVFatHandle {
     fn new(val: VFat<PiVFatHandle>) -> Self {
-        PiVFatHandle(Rc::new(Mutex::new(val)))
+        PiVFatHandle(Arc::new(Mutex::new(val)))
     }
 
     fn lock<R>(&self, f: impl FnOnce(&mut VFat<PiVFatHandle>) -> R) -> R {
         f(&mut self.0.lock())
     }
 }
-pub struct FileSystem(Mutex<Option<PiVFatHandle>>);
+pub struct FileSystem(Once<PiVFatHandle>);
 
 impl FileSystem {
     /// Returns an uninitialized `FileSystem`.
@@ -46,7 +44,7 @@ impl FileSystem {
     /// The file system must be initialized by calling `initialize()` before the
     /// first memory allocation. Failure to do will result in panics.
     pub const fn uninitialized() -> Self {
-        FileSystem(Mutex::new(None))
+        FileSystem(Once::new())
     }
 
     /// Initializes the file system.
@@ -61,7 +59,7 @@ impl FileSystem {
         match sd {
             Ok(_) => {
                 let vfat = VFat::from(sd.unwrap()).unwrap();
-                *self.0.lock() = Some(PiVFatHandle::from(vfat))
+                self.0.call_once(|| PiVFatHandle::from(vfat));
             }, 
             Err(e) => {
                 panic!("oops {:?}", e)
@@ -82,6 +80,6 @@ impl fat32::traits::FileSystem for &FileSystem {
     type Entry = Entry<PiVFatHandle>;
 
     fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry> {
-        self.0.lock().as_ref().unwrap().open(path)
+        self.0.get().expect("file system uninitialized").open(path)
     }
 }
@@ -1,3 +1,4 @@
+use core::ptr::{read_volatile, write_volatile};
 use core::time::Duration;
 use shim::io;
 use shim::ioerr;
@@ -5,6 +6,10 @@ use shim::ioerr;
 use fat32::traits::BlockDevice;
 use pi::timer::spin_sleep;
 
+use crate::VMM;
+
+// `libsd` reaches the EMMC controller at its physical address, so it is only
+// called through `VMM.with_io_identity()`.
 extern "C" {
     /// A global representing the last SD controller error that occured.
     static sd_err: i64;
@@ -30,6 +35,71 @@ extern "C" {
     fn sd_readsector(n: i32, buffer: *mut u8) -> i32;
 }
 
+/// The EMMC controller's registers, used directly to write sectors since
+/// `libsd` only reads.
+mod emmc {
+    use pi::common::IO_BASE;
+
+    pub const BASE: usize = IO_BASE + 0x300000;
+
+    pub const BLKSIZECNT: usize = 0x04;
+    pub const ARG1: usize = 0x08;
+    pub const CMDTM: usize = 0x0C;
+    pub const DATA: usize = 0x20;
+    pub const STATUS: usize = 0x24;
+    pub const INTERRUPT: usize = 0x30;
+
+    pub const SR_CMD_INHIBIT: u32 = 0x0000_0001;
+    pub const SR_DAT_INHIBIT: u32 = 0x0000_0002;
+
+    pub const INT_CMD_DONE: u32 = 0x0000_0001;
+    pub const INT_DATA_DONE: u32 = 0x0000_0002;
+    pub const INT_WRITE_RDY: u32 = 0x0000_0010;
+    pub const INT_CMD_TIMEOUT: u32 = 0x0001_0000;
+    pub const INT_DATA_TIMEOUT: u32 = 0x0010_0000;
+    pub const INT_ERROR_MASK: u32 = 0x017E_8000;
+
+    /// CMD24, WRITE_SINGLE_BLOCK, with a 48-bit response and a data transfer.
+    pub const CMD_WRITE_SINGLE: u32 = 0x1822_0000;
+}
+
+/// How long the EMMC controller is polled before a write times out.
+const EMMC_TIMEOUT_MICROS: u32 = 500_000;
+
+fn emmc_read(reg: usize) -> u32 {
+    unsafe { read_volatile((emmc::BASE + reg) as *const u32) }
+}
+
+fn emmc_write(reg: usize, value: u32) {
+    unsafe { write_volatile((emmc::BASE + reg) as *mut u32, value) }
+}
+
+/// Polls the EMMC controller until `done` holds for its `reg` register.
+fn emmc_wait<F: Fn(u32) -> bool>(reg: usize, done: F) -> io::Result<()> {
+    for _ in 0..EMMC_TIMEOUT_MICROS {
+        if done(emmc_read(reg)) {
+            return Ok(());
+        }
+        wait_micros(1);
+    }
+    Err(io::Error::new(io::ErrorKind::TimedOut, "Timeout"))
+}
+
+/// Waits for the `mask` interrupt of the EMMC controller and acknowledges it.
+fn emmc_wait_interrupt(mask: u32) -> io::Result<()> {
+    emmc_wait(emmc::INTERRUPT, |int| int & (mask | emmc::INT_ERROR_MASK) != 0)?;
+
+    let int = emmc_read(emmc::INTERRUPT);
+    emmc_write(emmc::INTERRUPT, int);
+    if int & (emmc::INT_CMD_TIMEOUT | emmc::INT_DATA_TIMEOUT) != 0 {
+        Err(io::Error::new(io::ErrorKind::TimedOut, "Timeout"))
+    } else if int & emmc::INT_ERROR_MASK != 0 {
+        Err(io::Error::new(io::ErrorKind::Other, "driver problem"))
+    } else {
+        Ok(())
+    }
+}
+
 // FIXME: Define a `#[no_mangle]` `wait_micros` function for use by `libsd`.
 // The `wait_micros` C signature is: `void wait_micros(unsigned int);`
 #[no_mangle]
@@ -49,7 +119,7 @@ impl Sd {
     /// with atomic memory access, but we can't use it yet since we haven't
     /// written the memory management unit (MMU).
     pub unsafe fn new() -> Result<Sd, io::Error> {
-        let sd = unsafe { sd_init() };
+        let sd = VMM.with_io_identity(|| unsafe { sd_init() });
         if sd == 0 {
             Ok(Sd {})
         } else {
@@ -80,7 +150,8 @@ impl BlockDevice for Sd {
             Err(io::Error::new(io::ErrorKind::InvalidInput, "buf > 2^31 - 1"))
         } else { 
             //lets get bytes read from sd_readsector
-            let bytes_read = unsafe { sd_readsector(n as i32, buf.as_mut_ptr())};
+            let bytes_read =
+                VMM.with_io_identity(|| unsafe { sd_readsector(n as i32, buf.as_mut_ptr()) });
 
             if bytes_read == 0 {
                 match unsafe { sd_err } {
@@ -97,7 +168,38 @@ impl BlockDevice for Sd {
         }
     }
 
-    fn write_sector(&mut self, _n: u64, _buf: &[u8]) -> io::Result<usize> {
-        unimplemented!("SD card and file system are read only")
+    /// Writes the first 512 bytes of `buf` to sector `n` of the SD card. On
+    /// success, 512 is returned.
+    ///
+    /// `libsd` only reads, so the sector is written by driving the EMMC
+    /// controller directly with CMD24. The card is assumed to be an SDHC or
+    /// SDXC card, which is addressed in sectors rather than bytes.
+    ///
+    /// # Errors
+    ///
+    /// Errors are returned as in `read_sector()`.
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() < 512 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "buf < 512"));
+        } else if n > (2u64.pow(31) - 1) as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "buf > 2^31 - 1"));
+        }
+
+        emmc_wait(emmc::STATUS, |status| status & emmc::SR_DAT_INHIBIT == 0)?;
+        emmc_write(emmc::BLKSIZECNT, (1 << 16) | 512);
+
+        emmc_wait(emmc::STATUS, |status| status & emmc::SR_CMD_INHIBIT == 0)?;
+        emmc_write(emmc::INTERRUPT, emmc_read(emmc::INTERRUPT));
+        emmc_write(emmc::ARG1, n as u32);
+        emmc_write(emmc::CMDTM, emmc::CMD_WRITE_SINGLE);
+        emmc_wait_interrupt(emmc::INT_CMD_DONE)?;
+
+        emmc_wait_interrupt(emmc::INT_WRITE_RDY)?;
+        for word in buf[..512].chunks(4) {
+            emmc_write(emmc::DATA, u32::from_le_bytes([word[0], word[1], word[2], word[3]]));
+        }
+        emmc_wait_interrupt(emmc::INT_DATA_DONE)?;
+
+        Ok(512)
     }
 }
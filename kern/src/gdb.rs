@@ -0,0 +1,397 @@
+use alloc::vec::Vec;
+
+use aarch64::*;
+
+use crate::console::CONSOLE;
+use crate::mutex::Mutex;
+use crate::traps::{step, TrapFrame};
+use crate::vm::linear_alias;
+use crate::SCHEDULER;
+
+/// The encoding of `brk #0`, which software breakpoints are patched with.
+const BRK: u32 = 0xd420_0000;
+
+/// The most software breakpoints set at a time.
+const MAX_BREAKPOINTS: usize = 32;
+
+/// The largest packet exchanged, as announced to the debugger.
+const PACKET_SIZE: usize = 4096;
+
+/// The signal stops are reported with: `SIGTRAP`.
+const SIGTRAP: u8 = 5;
+
+/// The numbers of registers in the debugger's AArch64 register set.
+const REG_SP: usize = 31;
+const REG_PC: usize = 32;
+const REG_CPSR: usize = 33;
+const REG_V0: usize = 34;
+const REG_FPSR: usize = 66;
+const REG_FPCR: usize = 67;
+
+/// A software breakpoint: the address of the patched instruction and the
+/// instruction it replaced.
+#[derive(Debug, Copy, Clone)]
+struct Breakpoint {
+    addr: usize,
+    insn: u32,
+}
+
+/// The state of the stub kept between stops.
+struct State {
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    breakpoints: [None; MAX_BREAKPOINTS],
+});
+
+/// Why the stub was entered.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Stop {
+    /// A `brk` instruction was executed.
+    Breakpoint,
+    /// A single step completed.
+    Step,
+}
+
+/// Serves a debugger speaking the GDB remote serial protocol over the UART
+/// until it continues or steps the code that stopped with the trap frame
+/// `tf`, which is the kernel's if the stop was taken from EL1 and a user
+/// process's otherwise.
+///
+/// A `brk` that is not one of the debugger's breakpoints is stepped over,
+/// so that continuing from a breakpoint compiled into the code does not stop
+/// at it again.
+///
+/// Memory is accessed through the kernel's linear map of the frames the
+/// current translation tables map it to. Breakpoints can thus be set in
+/// read-only code, but a breakpoint in a page shared copy-on-write applies to
+/// every process sharing it.
+pub fn stop(reason: Stop, tf: &mut TrapFrame) {
+    step::disarm(tf);
+    let mut state = STATE.lock();
+
+    if reason == Stop::Breakpoint && state.find(tf.elr as usize).is_none() {
+        tf.elr += 4;
+    }
+
+    send_packet(&stop_reply());
+
+    let mut packet = Vec::with_capacity(PACKET_SIZE);
+    loop {
+        read_packet(&mut packet);
+        let reply = match packet.split_first() {
+            Some((b'?', _)) => Some(stop_reply()),
+            Some((b'g', _)) => read_registers(tf),
+            Some((b'G', args)) => ok(write_registers(tf, args)),
+            Some((b'p', args)) => hex_u64(args).map(|n| read_register(tf, n as usize)),
+            Some((b'P', args)) => ok(write_register(tf, args)),
+            Some((b'm', args)) => read_memory(args),
+            Some((b'M', args)) => ok(write_memory(args)),
+            Some((b'Z', args)) if args.starts_with(b"0,") => ok(state.insert(&args[2..])),
+            Some((b'z', args)) if args.starts_with(b"0,") => ok(state.remove(&args[2..])),
+            Some((b'c', args)) => {
+                if let Some(addr) = hex_u64(args) {
+                    tf.elr = addr;
+                }
+                return;
+            }
+            Some((b's', args)) => {
+                if let Some(addr) = hex_u64(args) {
+                    tf.elr = addr;
+                }
+                step::arm(tf);
+                return;
+            }
+            Some((b'D', _)) => {
+                state.clear();
+                send_packet(b"OK");
+                return;
+            }
+            Some((b'k', _)) => {
+                state.clear();
+                if tf.is_user() && SCHEDULER.kill(tf).is_some() {
+                    SCHEDULER.switch_to(tf);
+                }
+                return;
+            }
+            Some((b'H', _)) => Some(b"OK".to_vec()),
+            Some((b'q', query)) if query.starts_with(b"Supported") => {
+                Some(format_bytes(format_args!("PacketSize={:x}", PACKET_SIZE)))
+            }
+            Some((b'q', query)) if query.starts_with(b"Attached") => Some(b"1".to_vec()),
+            _ => Some(Vec::new()),
+        };
+
+        match reply {
+            Some(reply) => send_packet(&reply),
+            None => send_packet(b"E01"),
+        }
+    }
+}
+
+impl State {
+    fn find(&self, addr: usize) -> Option<usize> {
+        self.breakpoints
+            .iter()
+            .position(|bp| bp.map(|bp| bp.addr) == Some(addr))
+    }
+
+    /// Sets the breakpoint of a `Z0` packet's `addr,kind` arguments.
+    fn insert(&mut self, args: &[u8]) -> Option<()> {
+        let addr = hex_u64(args.split(|&b| b == b',').next()?)? as usize;
+        if self.find(addr).is_some() {
+            return Some(());
+        }
+
+        let slot = self.breakpoints.iter().position(Option::is_none)?;
+        let insn = read_u32(addr)?;
+        write_u32(addr, BRK)?;
+        self.breakpoints[slot] = Some(Breakpoint { addr, insn });
+        Some(())
+    }
+
+    /// Removes the breakpoint of a `z0` packet's `addr,kind` arguments.
+    fn remove(&mut self, args: &[u8]) -> Option<()> {
+        let addr = hex_u64(args.split(|&b| b == b',').next()?)? as usize;
+        let slot = self.find(addr)?;
+        let bp = self.breakpoints[slot].take()?;
+        write_u32(bp.addr, bp.insn)
+    }
+
+    /// Removes every breakpoint.
+    fn clear(&mut self) {
+        for slot in self.breakpoints.iter_mut() {
+            if let Some(bp) = slot.take() {
+                write_u32(bp.addr, bp.insn);
+            }
+        }
+    }
+}
+
+fn stop_reply() -> Vec<u8> {
+    format_bytes(format_args!("S{:02x}", SIGTRAP))
+}
+
+fn ok(result: Option<()>) -> Option<Vec<u8>> {
+    result.map(|_| b"OK".to_vec())
+}
+
+fn read_registers(tf: &TrapFrame) -> Option<Vec<u8>> {
+    let mut reply = Vec::new();
+    for n in 0..=REG_FPCR {
+        reply.extend_from_slice(&read_register(tf, n));
+    }
+    Some(reply)
+}
+
+/// Returns register `n` in the debugger's numbering, hex encoded in target
+/// byte order. `FPSR` and `FPCR` are not saved and read as zero.
+fn read_register(tf: &TrapFrame, n: usize) -> Vec<u8> {
+    let mut reply = Vec::new();
+    match n {
+        0..=29 => push_hex(&mut reply, &tf.x[n].to_le_bytes()),
+        30 => push_hex(&mut reply, &tf.lr.to_le_bytes()),
+        REG_SP => push_hex(&mut reply, &tf.stack_pointer().to_le_bytes()),
+        REG_PC => push_hex(&mut reply, &tf.elr.to_le_bytes()),
+        REG_CPSR => push_hex(&mut reply, &(tf.spsr as u32).to_le_bytes()),
+        REG_V0..=65 => push_hex(&mut reply, &tf.q[n - REG_V0].to_le_bytes()),
+        REG_FPSR | REG_FPCR => push_hex(&mut reply, &0u32.to_le_bytes()),
+        _ => {}
+    }
+    reply
+}
+
+/// Writes the registers of a `G` packet, in the order `g` reads them.
+fn write_registers(tf: &mut TrapFrame, args: &[u8]) -> Option<()> {
+    let mut rest = args;
+    for n in 0..=REG_FPCR {
+        let len = register_size(n)? * 2;
+        if rest.len() < len {
+            break;
+        }
+        set_register(tf, n, &rest[..len])?;
+        rest = &rest[len..];
+    }
+    Some(())
+}
+
+/// Writes the register of a `P` packet's `n=value` arguments.
+fn write_register(tf: &mut TrapFrame, args: &[u8]) -> Option<()> {
+    let mut parts = args.splitn(2, |&b| b == b'=');
+    let n = hex_u64(parts.next()?)? as usize;
+    set_register(tf, n, parts.next()?)
+}
+
+/// Returns the size of register `n` in bytes.
+fn register_size(n: usize) -> Option<usize> {
+    match n {
+        0..=REG_PC => Some(8),
+        REG_CPSR | REG_FPSR | REG_FPCR => Some(4),
+        REG_V0..=65 => Some(16),
+        _ => None,
+    }
+}
+
+/// Sets register `n` to the hex encoded `value`. The kernel's stack pointer,
+/// `FPSR` and `FPCR` are read-only, and writes to them are ignored.
+fn set_register(tf: &mut TrapFrame, n: usize, value: &[u8]) -> Option<()> {
+    let mut bytes = [0u8; 16];
+    let size = register_size(n)?;
+    if value.len() != size * 2 {
+        return None;
+    }
+    for (i, byte) in bytes[..size].iter_mut().enumerate() {
+        *byte = hex_byte(&value[i * 2..i * 2 + 2])?;
+    }
+
+    let mut word = [0u8; 8];
+    word.copy_from_slice(&bytes[..8]);
+    let word = u64::from_le_bytes(word);
+    match n {
+        0..=29 => tf.x[n] = word,
+        30 => tf.lr = word,
+        REG_SP if tf.is_user() => tf.sp = word,
+        REG_PC => tf.elr = word,
+        REG_CPSR => tf.spsr = (tf.spsr & !0xffff_ffff) | (word & 0xffff_ffff),
+        REG_V0..=65 => tf.q[n - REG_V0] = u128::from_le_bytes(bytes),
+        _ => {}
+    }
+    Some(())
+}
+
+/// Reads the memory of an `m` packet's `addr,length` arguments.
+fn read_memory(args: &[u8]) -> Option<Vec<u8>> {
+    let mut parts = args.splitn(2, |&b| b == b',');
+    let addr = hex_u64(parts.next()?)? as usize;
+    let len = hex_u64(parts.next()?)? as usize;
+
+    let mut reply = Vec::with_capacity(len * 2);
+    for i in 0..len.min(PACKET_SIZE / 2) {
+        let byte = unsafe { *(linear_alias(addr + i)? as *const u8) };
+        push_hex(&mut reply, &[byte]);
+    }
+    Some(reply)
+}
+
+/// Writes the memory of an `M` packet's `addr,length:bytes` arguments.
+fn write_memory(args: &[u8]) -> Option<()> {
+    let mut parts = args.splitn(2, |&b| b == b':');
+    let mut header = parts.next()?.splitn(2, |&b| b == b',');
+    let addr = hex_u64(header.next()?)? as usize;
+    let len = hex_u64(header.next()?)? as usize;
+    let data = parts.next()?;
+    if data.len() != len * 2 {
+        return None;
+    }
+
+    for i in 0..len {
+        let byte = hex_byte(&data[i * 2..i * 2 + 2])?;
+        unsafe { *(linear_alias(addr + i)? as *mut u8) = byte };
+        sync_icache((addr + i) as u64);
+    }
+    Some(())
+}
+
+fn read_u32(addr: usize) -> Option<u32> {
+    if addr % 4 != 0 {
+        return None;
+    }
+    Some(unsafe { *(linear_alias(addr)? as *const u32) })
+}
+
+fn write_u32(addr: usize, value: u32) -> Option<()> {
+    if addr % 4 != 0 {
+        return None;
+    }
+    unsafe { *(linear_alias(addr)? as *mut u32) = value };
+    sync_icache(addr as u64);
+    Some(())
+}
+
+/// Reads a packet into `packet`, acknowledging it, and retrying until one
+/// arrives with a correct checksum. Bytes outside of packets are skipped.
+fn read_packet(packet: &mut Vec<u8>) {
+    let mut console = CONSOLE.lock();
+    loop {
+        while console.read_byte() != b'$' {}
+
+        packet.clear();
+        let mut sum: u8 = 0;
+        loop {
+            match console.read_byte() {
+                b'#' => break,
+                byte => {
+                    sum = sum.wrapping_add(byte);
+                    packet.push(byte);
+                }
+            }
+        }
+
+        let checksum = [console.read_byte(), console.read_byte()];
+        if hex_byte(&checksum) == Some(sum) {
+            console.write_byte(b'+');
+            return;
+        }
+        console.write_byte(b'-');
+    }
+}
+
+/// Sends `data` as a packet until the debugger acknowledges it.
+fn send_packet(data: &[u8]) {
+    let mut console = CONSOLE.lock();
+    let sum = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    let mut checksum = Vec::with_capacity(2);
+    push_hex(&mut checksum, &[sum]);
+
+    loop {
+        console.write_byte(b'$');
+        for &byte in data {
+            console.write_byte(byte);
+        }
+        console.write_byte(b'#');
+        console.write_byte(checksum[0]);
+        console.write_byte(checksum[1]);
+
+        match console.read_byte() {
+            b'+' => return,
+            _ => continue,
+        }
+    }
+}
+
+fn format_bytes(args: core::fmt::Arguments) -> Vec<u8> {
+    alloc::fmt::format(args).into_bytes()
+}
+
+fn push_hex(out: &mut Vec<u8>, bytes: &[u8]) {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    for &byte in bytes {
+        out.push(DIGITS[(byte >> 4) as usize]);
+        out.push(DIGITS[(byte & 0xf) as usize]);
+    }
+}
+
+fn hex_digit(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn hex_byte(digits: &[u8]) -> Option<u8> {
+    Some(hex_digit(digits[0])? << 4 | hex_digit(digits[1])?)
+}
+
+/// Parses a big endian hex number, as packets encode addresses and lengths.
+/// Returns `None` if `digits` is empty or not hex.
+fn hex_u64(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || digits.len() > 16 {
+        return None;
+    }
+    digits
+        .iter()
+        .try_fold(0u64, |n, &d| Some(n << 4 | hex_digit(d)? as u64))
+}
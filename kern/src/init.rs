@@ -1,12 +1,13 @@
 use aarch64::*;
 
 use core::mem::zeroed;
-use core::ptr::write_volatile;
+use core::ptr::{read_volatile, write_volatile};
 
+mod mmu;
 mod oom;
 mod panic;
 
-use crate::kmain;
+use crate::{kmain, kmain2};
 use crate::param::*;
 
 global_asm!(include_str!("init/vectors.s"));
@@ -18,14 +19,23 @@ global_asm!(include_str!("init/vectors.s"));
 //
 // so, no debug build support!
 //
+// The kernel is linked in the upper half but runs at its physical load
+// address until `kinit()` enables the MMU: code before that point may only
+// take addresses PC-relative, which gives their physical addresses.
+//
 
+// Every core runs on its own `KERN_STACK_SIZE` bytes of stack, core `n`'s
+// ending at `KERN_STACK_BASE - n * KERN_STACK_SIZE`. Core 0 boots the kernel;
+// the others are woken up by it through the spin table once it is running.
 #[no_mangle]
 pub unsafe extern "C" fn _start() -> ! {
-    if MPIDR_EL1.get_value(MPIDR_EL1::Aff0) == 0 {
-        SP.set(KERN_STACK_BASE);
+    let core = MPIDR_EL1.get_value(MPIDR_EL1::Aff0) as usize;
+    SP.set(KERN_STACK_BASE - core * KERN_STACK_SIZE);
+    if core == 0 {
         kinit()
+    } else {
+        kinit2()
     }
-    unreachable!()
 }
 
 unsafe fn zeros_bss() {
@@ -61,9 +71,6 @@ unsafe fn switch_to_el2() {
 
 #[no_mangle]
 unsafe fn switch_to_el1() {
-    extern "C" {
-        static mut vectors: u64;
-    }
     if current_el() == 2 {
         // set the stack-pointer for EL1
         // -
@@ -84,11 +91,6 @@ unsafe fn switch_to_el1() {
         // Set SCTLR to known state (A53: 4.3.30)
         SCTLR_EL1.set(SCTLR_EL1::RES1);
 
-        // set up exception handlers
-        // FIXME: load `vectors` addr into appropriate register (guide: 10.4)
-        VBAR_EL1.set(((&mut vectors) as *mut u64) as u64);
-        
-
         // change execution level to EL1 (ref: C5.2.19)
         SPSR_EL2.set(
             (SPSR_EL2::M & 0b0101) // EL1h
@@ -110,5 +112,81 @@ unsafe fn kinit() -> ! {
     zeros_bss();
     switch_to_el2();
     switch_to_el1();
+    mmu::enable();
+
+    // continue at the upper-half alias of `kinit_high()`, with the stack
+    // moved to the upper half too
+    asm!("mov sp, $0
+          br $1"
+         :: "r"(KERNEL_BASE + KERN_STACK_BASE), "r"(KERNEL_BASE | kinit_high as usize)
+         :: "volatile");
+    unreachable!()
+}
+
+#[no_mangle]
+unsafe fn kinit_high() -> ! {
+    extern "C" {
+        static mut vectors: u64;
+    }
+
+    // set up exception handlers at their upper-half address (guide: 10.4)
+    VBAR_EL1.set(((&mut vectors) as *mut u64) as u64);
+
     kmain();
 }
+
+#[no_mangle]
+unsafe fn kinit2() -> ! {
+    switch_to_el2();
+    switch_to_el1();
+    mmu::load();
+
+    let core = MPIDR_EL1.get_value(MPIDR_EL1::Aff0) as usize;
+    asm!("mov sp, $0
+          br $1"
+         :: "r"(KERNEL_BASE + KERN_STACK_BASE - core * KERN_STACK_SIZE),
+            "r"(KERNEL_BASE | kinit2_high as usize)
+         :: "volatile");
+    unreachable!()
+}
+
+#[no_mangle]
+unsafe fn kinit2_high() -> ! {
+    extern "C" {
+        static mut vectors: u64;
+    }
+
+    VBAR_EL1.set(((&mut vectors) as *mut u64) as u64);
+
+    // tell core 0 that this core is up
+    let spinning = SPINNING_BASE.add(affinity());
+    write_volatile(spinning, 0);
+    clean_dcache(spinning as u64);
+
+    kmain2();
+}
+
+/// Wakes up the other cores, which the firmware keeps spinning on their
+/// entries of the spin table, and waits until all of them have started.
+///
+/// A core jumps to the physical address written to its entry; it reads the
+/// table with its caches off, so the writes are cleaned to memory first.
+pub unsafe fn initialize_app_cores() {
+    let start = _start as usize - KERNEL_BASE;
+    for core in 1..NCORES {
+        let spinning = SPINNING_BASE.add(core);
+        write_volatile(spinning, start);
+        clean_dcache(spinning as u64);
+    }
+    sev();
+
+    for core in 1..NCORES {
+        let spinning = SPINNING_BASE.add(core);
+        loop {
+            clean_dcache(spinning as u64);
+            if read_volatile(spinning) == 0 {
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! The page tables the kernel boots with.
+//!
+//! The kernel is linked in the upper half but loaded at its physical address,
+//! so the MMU is enabled before any code relying on link-time addresses
+//! runs. The boot tables map the low 4GB of physical memory with blocks, both
+//! at their physical addresses in the lower half, where the kernel runs until
+//! it jumps to the upper half, and at `KERNEL_BASE` in the upper half. They
+//! are replaced by `VMManager::setup()`.
+
+use aarch64::vmsa::*;
+use aarch64::*;
+
+use crate::param::*;
+use crate::vm::{tcr, MAIR};
+
+/// The number of descriptors in a translation table.
+const ENTRIES: usize = PAGE_SIZE / 8;
+
+/// The number of virtual address bits resolved by each level of tables.
+const LEVEL_BITS: usize = PAGE_SHIFT - 3;
+
+/// The level of the root table for the `2^(64 - KERNEL_MASK_BITS)` bytes of
+/// the kernel's address space.
+const ROOT_LEVEL: usize = 4 - (64 - KERNEL_MASK_BITS - PAGE_SHIFT + LEVEL_BITS - 1) / LEVEL_BITS;
+
+/// The number of bytes mapped by a descriptor of the root table, and by one
+/// of the table below it.
+const ROOT_BLOCK: usize = 1 << (PAGE_SHIFT + (3 - ROOT_LEVEL) * LEVEL_BITS);
+const NEXT_BLOCK: usize = ROOT_BLOCK >> LEVEL_BITS;
+
+#[repr(C)]
+#[cfg_attr(not(feature = "granule-4k"), repr(align(65536)))]
+#[cfg_attr(feature = "granule-4k", repr(align(4096)))]
+struct Table([u64; ENTRIES]);
+
+/// The root table, and the table splitting the root block that holds both
+/// RAM and the peripherals at `IO_BASE_PHYS`. Both live in `.bss`. The local
+/// peripherals at `LOCAL_BASE_PHYS` start a root block of their own.
+static mut ROOT: Table = Table([0; ENTRIES]);
+static mut SPLIT: Table = Table([0; ENTRIES]);
+
+/// Returns a descriptor of `level` mapping the block of physical memory at
+/// `addr` for EL1: normal memory below `IO_BASE_PHYS`, and nGnRE device
+/// memory from there.
+fn block(addr: usize, level: usize) -> u64 {
+    let (attr, sh) = if addr < IO_BASE_PHYS {
+        (EntryAttr::Mem, EntrySh::ISh)
+    } else {
+        (EntryAttr::Dev, EntrySh::OSh)
+    };
+    // Below the last level, a block descriptor has type 0.
+    let kind = if level == 3 { PageType::Page } else { EntryType::Block };
+
+    let mut entry = RawL3Entry::new(addr as u64);
+    entry
+        .set_value(1, RawL3Entry::AF)
+        .set_value(sh, RawL3Entry::SH)
+        .set_value(EntryPerm::KERN_RW, RawL3Entry::AP)
+        .set_value(attr, RawL3Entry::ATTR)
+        .set_value(1, RawL3Entry::UXN)
+        .set_value(kind, RawL3Entry::TYPE)
+        .set_value(EntryValid::Valid, RawL3Entry::VALID);
+    entry.get()
+}
+
+/// Fills the boot tables and enables the MMU with them in both `TTBR0` and
+/// `TTBR1`.
+///
+/// Must be called at EL1 with the MMU disabled. The address of `SPLIT` is
+/// taken PC-relative, so it is the table's physical address here.
+pub unsafe fn enable() {
+    fill();
+    load();
+}
+
+/// Fills the boot tables. The caches are still off, so the tables are in
+/// memory for the other cores to walk before they enable theirs.
+unsafe fn fill() {
+    let root_entries = (1 << (64 - KERNEL_MASK_BITS)) / ROOT_BLOCK;
+    for i in 0..root_entries {
+        let addr = i * ROOT_BLOCK;
+        if addr >= LOCAL_BASE_PHYS_END {
+            break;
+        }
+
+        ROOT.0[i] = if addr < IO_BASE_PHYS && IO_BASE_PHYS < addr + ROOT_BLOCK {
+            for j in 0..ENTRIES {
+                SPLIT.0[j] = block(addr + j * NEXT_BLOCK, ROOT_LEVEL + 1);
+            }
+
+            let mut entry = RawL3Entry::new(&SPLIT as *const Table as u64);
+            entry
+                .set_value(EntryType::Table, RawL3Entry::TYPE)
+                .set_value(EntryValid::Valid, RawL3Entry::VALID);
+            entry.get()
+        } else {
+            block(addr, ROOT_LEVEL)
+        };
+    }
+
+}
+
+/// Enables the MMU with the boot tables, which core 0 filled already.
+///
+/// Must be called at EL1 with the MMU disabled.
+pub unsafe fn load() {
+    let root = &ROOT as *const Table as u64;
+    MAIR_EL1.set(MAIR);
+    TCR_EL1.set(tcr(KERNEL_MASK_BITS));
+    isb();
+
+    TTBR0_EL1.set(root);
+    TTBR1_EL1.set(root);
+    tlb_invalidate_all();
+
+    SCTLR_EL1.set(SCTLR_EL1.get() | SCTLR_EL1::I | SCTLR_EL1::C | SCTLR_EL1::M);
+    asm!("dsb sy");
+    isb();
+}
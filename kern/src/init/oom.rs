@@ -1,6 +1,17 @@
 use core::alloc::Layout;
 
+use crate::console::kprintln;
+use crate::ALLOCATOR;
+
 #[alloc_error_handler]
-pub fn oom(_layout: Layout) -> ! {
-    panic!("OOM");
+pub fn oom(layout: Layout) -> ! {
+    kprintln!("{}", ALLOCATOR.stats());
+    for stats in ALLOCATOR.slabs().iter() {
+        kprintln!("{}", stats);
+    }
+    panic!(
+        "out of memory: failed to allocate {} bytes aligned to {}",
+        layout.size(),
+        layout.align()
+    );
 }
@@ -1,19 +1,27 @@
 use core::panic::PanicInfo;
-use crate::console::{kprint, kprintln, CONSOLE};
+use crate::backtrace;
+use crate::console::{kprintln, CONSOLE};
 
 #[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
+fn panic(info: &PanicInfo) -> ! {
+    // The core may have panicked while printing.
+    unsafe { CONSOLE.force_unlock() };
+
     kprintln!("-----PANIC-----");
-    if let Some(location) = _info.location() {
+    if let Some(location) = info.location() {
         kprintln!("FILE: {:?}", location.file());
         kprintln!("LINE: {:?}", location.line());
         kprintln!("COL: {:?}", location.column());
     }
     kprintln!("");
-    if let pload = _info.payload().downcast_ref::<&str>().unwrap() {
-        kprintln!("{:?}", pload);
-    } else {
-        kprintln!("panic occured");
+    match info.message() {
+        Some(message) => kprintln!("{}", message),
+        None => match info.payload().downcast_ref::<&str>() {
+            Some(payload) => kprintln!("{}", payload),
+            None => kprintln!("panic occured"),
+        },
     }
+    kprintln!("");
+    backtrace::backtrace();
     loop {}
 }
@@ -32,6 +32,10 @@ context_save:
     stp q4, q5, [SP, #-32]!
     stp q2, q3, [SP, #-32]!
     stp q0, q1, [SP, #-32]!
+
+    mrs x1, TTBR0_EL1
+    mrs x2, TTBR1_EL1
+    stp x1, x2, [SP, #-16]!
     
     mrs x1, SP_EL0
     mrs x2, TPIDR_EL0
@@ -67,6 +71,12 @@ context_restore:
     msr SP_EL0, x1
     msr TPIDR_EL0, x2
 
+    ldp x1, x2, [SP], #16
+    msr TTBR0_EL1, x1
+    msr TTBR1_EL1, x2
+    // TLB entries of user pages are tagged with the ASID in TTBR0, so
+    // switching address spaces needs no invalidation.
+    isb
     
     ldp q0, q1, [SP], #32
     ldp q2, q3, [SP], #32
@@ -4,6 +4,7 @@
 #![feature(asm)]
 #![feature(global_asm)]
 #![feature(optin_builtin_traits)]
+#![feature(panic_info_message)]
 #![feature(ptr_internals)]
 #![feature(raw_vec_internals)]
 #![cfg_attr(not(test), no_std)]
@@ -23,8 +24,11 @@ mod init;
 extern crate alloc;
 
 pub mod allocator;
+pub mod backtrace;
 pub mod console;
 pub mod fs;
+#[cfg(feature = "gdb")]
+pub mod gdb;
 pub mod mutex;
 pub mod shell;
 pub mod param;
@@ -52,27 +56,48 @@ use aarch64::brk;
 
 use process::GlobalScheduler;
 use traps::irq::Irq;
-use vm::VMManager;
+use vm::{FrameAllocator, SwapManager, VMManager};
 
 #[cfg_attr(not(test), global_allocator)]
 pub static ALLOCATOR: Allocator = Allocator::uninitialized();
 pub static FILESYSTEM: FileSystem = FileSystem::uninitialized();
 pub static SCHEDULER: GlobalScheduler = GlobalScheduler::uninitialized();
+pub static FRAMES: FrameAllocator = FrameAllocator::uninitialized();
 pub static VMM: VMManager = VMManager::uninitialized();
+pub static SWAP: SwapManager = SwapManager::uninitialized();
 pub static IRQ: Irq = Irq::uninitialized();
+pub static FIQ: Irq = Irq::uninitialized();
 
 fn kmain() -> ! {
     unsafe {
         ALLOCATOR.initialize();
+        FRAMES.initialize();
+        VMM.initialize();
+        VMM.setup();
         FILESYSTEM.initialize();
+        SWAP.initialize();
+        IRQ.initialize();
+        FIQ.initialize();
+        SCHEDULER.initialize();
     }
     for tag in Atags::get() {
         kprintln!("{:?}", tag);
     }
     unsafe { kprintln!("Current EL: {:?}", aarch64::current_el()) };
-    loop {
-        kprintln!("Welcome to cs3210!");
-        shell::shell("> ");
-        unsafe{ asm!("svc 3" :::: "volatile"); }
+    kprintln!("Welcome to cs3210!");
+
+    #[cfg(not(test))]
+    unsafe {
+        init::initialize_app_cores();
     }
+
+    // The shell's `exit` hands the machine over to the user processes.
+    shell::shell("> ");
+    SCHEDULER.start()
+}
+
+/// The entry of the other cores, once they run in the upper half at EL1.
+fn kmain2() -> ! {
+    VMM.setup();
+    SCHEDULER.start()
 }
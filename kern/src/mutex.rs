@@ -1,12 +1,36 @@
 use core::fmt;
-use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::sync::atomic::{spin_loop_hint, AtomicUsize, Ordering};
 use core::cell::UnsafeCell;
 use core::ops::{DerefMut, Deref, Drop};
 
+use aarch64::{affinity, cli, sti, DAIF};
+
+mod once;
+mod rwlock;
+
+pub use self::once::Once;
+pub use self::rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// The owner of a lock nobody holds.
+const NO_OWNER: usize = usize::max_value();
+
+/// A ticket spin lock.
+///
+/// Cores are granted the lock in the order they asked for it. The lock is
+/// not reentrant: a core locking a mutex it holds already deadlocks, which
+/// debug builds detect and panic on. They also report cores waiting for a
+/// lock for longer than `DEADLOCK_TIMEOUT`.
+///
+/// Exclusive accesses only work on cacheable memory, so no lock may be taken
+/// before the MMU is enabled.
 #[repr(align(32))]
 pub struct Mutex<T> {
     data: UnsafeCell<T>,
-    lock: AtomicBool,
+    /// The ticket the next core to lock the mutex takes.
+    next: AtomicUsize,
+    /// The ticket of the core that holds, or is next to hold, the lock.
+    serving: AtomicUsize,
+    /// The core holding the lock, or `NO_OWNER`.
     owner: AtomicUsize
 }
 
@@ -23,42 +47,59 @@ unsafe impl<'a, T: Sync> Sync for MutexGuard<'a, T> { }
 impl<T> Mutex<T> {
     pub const fn new(val: T) -> Mutex<T> {
         Mutex {
-            lock: AtomicBool::new(false),
-            owner: AtomicUsize::new(usize::max_value()),
+            next: AtomicUsize::new(0),
+            serving: AtomicUsize::new(0),
+            owner: AtomicUsize::new(NO_OWNER),
             data: UnsafeCell::new(val)
         }
     }
 }
 
 impl<T> Mutex<T> {
-    // Once MMU/cache is enabled, do the right thing here. For now, we don't
-    // need any real synchronization.
+    /// Acquires the lock if no core holds or waits for it.
     pub fn try_lock(&self) -> Option<MutexGuard<T>> {
-        let this = 0;
-        if !self.lock.load(Ordering::Relaxed) || self.owner.load(Ordering::Relaxed) == this {
-            self.lock.store(true, Ordering::Relaxed);
-            self.owner.store(this, Ordering::Relaxed);
-            Some(MutexGuard { lock: &self })
-        } else {
-            None
+        let ticket = self.serving.load(Ordering::Relaxed);
+        let next = ticket.wrapping_add(1);
+        match self.next.compare_exchange(ticket, next, Ordering::Acquire, Ordering::Relaxed) {
+            Ok(_) => {
+                self.owner.store(affinity(), Ordering::Relaxed);
+                Some(MutexGuard { lock: &self })
+            }
+            Err(_) => None
         }
     }
 
-    // Once MMU/cache is enabled, do the right thing here. For now, we don't
-    // need any real synchronization.
+    /// Takes a ticket and spins until it is served.
     #[inline(never)]
     pub fn lock(&self) -> MutexGuard<T> {
-        // Wait until we can "aquire" the lock, then "acquire" it.
-        loop {
-            match self.try_lock() {
-                Some(guard) => return guard,
-                None => continue
-            }
+        let this = affinity();
+        if cfg!(debug_assertions) && self.owner.load(Ordering::Relaxed) == this {
+            panic!("deadlock: core {} locked a mutex it holds", this);
+        }
+
+        let ticket = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut spin = Spin::new();
+        while self.serving.load(Ordering::Acquire) != ticket {
+            spin.wait(&self.owner);
         }
+        self.owner.store(this, Ordering::Relaxed);
+        MutexGuard { lock: &self }
     }
 
     fn unlock(&self) {
-        self.lock.store(false, Ordering::Relaxed);
+        self.owner.store(NO_OWNER, Ordering::Relaxed);
+        let serving = self.serving.load(Ordering::Relaxed);
+        self.serving.store(serving.wrapping_add(1), Ordering::Release);
+    }
+
+    /// Releases the lock if the current core holds it, without a guard.
+    ///
+    /// Only for the panic handler, which must get at the console even if the
+    /// core panicked while holding it. The guard must never be used again.
+    pub unsafe fn force_unlock(&self) {
+        if self.owner.load(Ordering::Relaxed) == affinity() {
+            self.unlock();
+        }
     }
 }
 
@@ -90,3 +131,155 @@ impl<T: fmt::Debug> fmt::Debug for Mutex<T> {
         }
     }
 }
+
+/// A `Mutex` that masks IRQs on the core holding it, for data that irq
+/// handlers share with code running with IRQs unmasked: a handler taking the
+/// lock on a core that holds it already would spin forever.
+///
+/// IRQs are masked before the lock is taken and are unmasked again, if they
+/// were unmasked before, once the guard is dropped.
+pub struct IrqMutex<T>(Mutex<T>);
+
+pub struct IrqMutexGuard<'a, T: 'a> {
+    guard: Option<MutexGuard<'a, T>>,
+    /// Whether IRQs were masked when the lock was taken.
+    masked: bool
+}
+
+impl<T> IrqMutex<T> {
+    pub const fn new(val: T) -> IrqMutex<T> {
+        IrqMutex(Mutex::new(val))
+    }
+
+    pub fn try_lock(&self) -> Option<IrqMutexGuard<T>> {
+        let masked = irqs_masked();
+        unsafe { cli() };
+        match self.0.try_lock() {
+            Some(guard) => Some(IrqMutexGuard { guard: Some(guard), masked }),
+            None => {
+                if !masked {
+                    unsafe { sti() };
+                }
+                None
+            }
+        }
+    }
+
+    pub fn lock(&self) -> IrqMutexGuard<T> {
+        let masked = irqs_masked();
+        unsafe { cli() };
+        IrqMutexGuard { guard: Some(self.0.lock()), masked }
+    }
+}
+
+impl<'a, T: 'a> Deref for IrqMutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard.as_ref().unwrap()
+    }
+}
+
+impl<'a, T: 'a> DerefMut for IrqMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard.as_mut().unwrap()
+    }
+}
+
+impl<'a, T: 'a> Drop for IrqMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        // Unlock before IRQs are unmasked.
+        self.guard.take();
+        if !self.masked {
+            unsafe { sti() };
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for IrqMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f.debug_struct("IrqMutex").field("data", &&*guard).finish(),
+            None => f.debug_struct("IrqMutex").field("data", &"<locked>").finish()
+        }
+    }
+}
+
+/// Returns whether IRQs are masked on the current core.
+fn irqs_masked() -> bool {
+    unsafe { DAIF.get() & DAIF::I != 0 }
+}
+
+/// How long a core may wait for a lock in debug builds before it is
+/// reported as possibly deadlocked, and then again every so long.
+#[cfg(debug_assertions)]
+const DEADLOCK_TIMEOUT: core::time::Duration = core::time::Duration::from_secs(5);
+
+/// The waiting of a core for a lock, which debug builds report every
+/// `DEADLOCK_TIMEOUT` with the core that holds the lock, and go on with.
+struct Spin {
+    #[cfg(debug_assertions)]
+    spins: usize,
+    #[cfg(debug_assertions)]
+    since: core::time::Duration,
+    /// How long the core waits before it is reported next.
+    #[cfg(debug_assertions)]
+    report_after: core::time::Duration,
+}
+
+impl Spin {
+    fn new() -> Spin {
+        Spin {
+            #[cfg(debug_assertions)]
+            spins: 0,
+            #[cfg(debug_assertions)]
+            since: pi::generic_timer::current_time(),
+            #[cfg(debug_assertions)]
+            report_after: DEADLOCK_TIMEOUT,
+        }
+    }
+
+    /// Spins once more waiting for a lock that `owner` holds.
+    fn wait(&mut self, owner: &AtomicUsize) {
+        spin_loop_hint();
+
+        #[cfg(debug_assertions)]
+        {
+            // Reading the counter is slow: only do it every so often.
+            self.spins = self.spins.wrapping_add(1);
+            if self.spins % (1 << 16) == 0 {
+                let waited = pi::generic_timer::current_time() - self.since;
+                if waited > self.report_after {
+                    self.report_after = waited + DEADLOCK_TIMEOUT;
+                    report(owner, waited);
+                }
+            }
+        }
+
+        #[cfg(not(debug_assertions))]
+        let _ = owner;
+    }
+}
+
+/// Reports that the current core waited `waited` for a lock `owner` holds.
+///
+/// Nothing is reported while the console is locked, as it is when the lock
+/// waited for is the console's.
+#[cfg(debug_assertions)]
+fn report(owner: &AtomicUsize, waited: core::time::Duration) {
+    use core::fmt::Write;
+
+    let owner = match owner.load(Ordering::Relaxed) {
+        NO_OWNER => None,
+        core => Some(core),
+    };
+    if let Some(mut console) = crate::console::CONSOLE.try_lock() {
+        let _ = writeln!(
+            console,
+            "deadlock? core {} waited {:?} for a lock held by core {:?}",
+            affinity(),
+            waited,
+            owner
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use aarch64::affinity;
+
+use super::{Spin, NO_OWNER};
+
+const INCOMPLETE: usize = 0;
+const RUNNING: usize = 1;
+const COMPLETE: usize = 2;
+
+/// A value that is initialized exactly once, by the first core to ask for it,
+/// and read without locking from then on.
+///
+/// Cores asking for the value while it is being initialized spin until it
+/// is. Debug builds panic if the initializing core asks for it itself.
+pub struct Once<T> {
+    data: UnsafeCell<Option<T>>,
+    state: AtomicUsize,
+    /// The core running the initializer, or `NO_OWNER`.
+    owner: AtomicUsize,
+}
+
+unsafe impl<T: Send> Send for Once<T> {}
+unsafe impl<T: Send + Sync> Sync for Once<T> {}
+
+impl<T> Once<T> {
+    pub const fn new() -> Once<T> {
+        Once {
+            data: UnsafeCell::new(None),
+            state: AtomicUsize::new(INCOMPLETE),
+            owner: AtomicUsize::new(NO_OWNER),
+        }
+    }
+
+    /// Returns the value, initializing it with `f` if no core did yet.
+    pub fn call_once<F: FnOnce() -> T>(&self, f: F) -> &T {
+        if self
+            .state
+            .compare_exchange(INCOMPLETE, RUNNING, Ordering::Acquire, Ordering::Acquire)
+            .is_ok()
+        {
+            self.owner.store(affinity(), Ordering::Relaxed);
+            unsafe { *self.data.get() = Some(f()) };
+            self.owner.store(NO_OWNER, Ordering::Relaxed);
+            self.state.store(COMPLETE, Ordering::Release);
+        }
+        self.wait()
+    }
+
+    /// Returns the value if it is initialized.
+    pub fn get(&self) -> Option<&T> {
+        match self.state.load(Ordering::Acquire) {
+            COMPLETE => unsafe { (*self.data.get()).as_ref() },
+            _ => None,
+        }
+    }
+
+    /// Spins until the value is initialized, and returns it.
+    fn wait(&self) -> &T {
+        let this = affinity();
+        if cfg!(debug_assertions) && self.owner.load(Ordering::Relaxed) == this {
+            panic!("deadlock: core {} waited for a `Once` it initializes", this);
+        }
+
+        let mut spin = Spin::new();
+        loop {
+            if let Some(value) = self.get() {
+                return value;
+            }
+            spin.wait(&self.owner);
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Once<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.get() {
+            Some(value) => f.debug_struct("Once").field("data", value).finish(),
+            None => f.debug_struct("Once").field("data", &"<uninitialized>").finish(),
+        }
+    }
+}
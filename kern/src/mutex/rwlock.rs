@@ -0,0 +1,162 @@
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ops::{Deref, DerefMut, Drop};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use aarch64::affinity;
+
+use super::{Spin, NO_OWNER};
+
+/// Set in the state while a writer holds the lock.
+const WRITER: usize = 1 << (usize::max_value().count_ones() - 1);
+/// Set in the state while a writer waits for the readers to leave. New
+/// readers wait too, so that writers are not starved.
+const PENDING: usize = WRITER >> 1;
+/// The number of readers holding the lock.
+const READERS: usize = PENDING - 1;
+
+/// A reader-writer spin lock: any number of readers or a single writer hold
+/// it at a time.
+///
+/// Like `Mutex`, it is not reentrant. Debug builds panic on a core locking
+/// it while it holds it for writing, and report waiting for it for too long.
+pub struct RwLock<T> {
+    data: UnsafeCell<T>,
+    state: AtomicUsize,
+    /// The core holding the lock for writing, or `NO_OWNER`.
+    owner: AtomicUsize,
+}
+
+unsafe impl<T: Send> Send for RwLock<T> {}
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+pub struct RwLockReadGuard<'a, T: 'a> {
+    lock: &'a RwLock<T>,
+}
+
+pub struct RwLockWriteGuard<'a, T: 'a> {
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T> !Send for RwLockReadGuard<'a, T> {}
+impl<'a, T> !Send for RwLockWriteGuard<'a, T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(val: T) -> RwLock<T> {
+        RwLock {
+            data: UnsafeCell::new(val),
+            state: AtomicUsize::new(0),
+            owner: AtomicUsize::new(NO_OWNER),
+        }
+    }
+
+    /// Acquires the lock for reading if no writer holds or waits for it.
+    pub fn try_read(&self) -> Option<RwLockReadGuard<T>> {
+        let state = self.state.load(Ordering::Relaxed);
+        if state & (WRITER | PENDING) != 0 || state & READERS == READERS {
+            return None;
+        }
+
+        match self.state.compare_exchange(state, state + 1, Ordering::Acquire, Ordering::Relaxed) {
+            Ok(_) => Some(RwLockReadGuard { lock: self }),
+            Err(_) => None,
+        }
+    }
+
+    /// Acquires the lock for reading, spinning while a writer holds or
+    /// waits for it.
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        self.check_owner();
+        let mut spin = Spin::new();
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            spin.wait(&self.owner);
+        }
+    }
+
+    /// Acquires the lock for writing if nobody holds it.
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<T>> {
+        let state = self.state.load(Ordering::Relaxed);
+        if state & !PENDING != 0 {
+            return None;
+        }
+
+        match self.state.compare_exchange(state, WRITER, Ordering::Acquire, Ordering::Relaxed) {
+            Ok(_) => {
+                self.owner.store(affinity(), Ordering::Relaxed);
+                Some(RwLockWriteGuard { lock: self })
+            }
+            Err(_) => None,
+        }
+    }
+
+    /// Acquires the lock for writing, spinning until the readers and the
+    /// writer holding it leave. New readers are held off meanwhile.
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        self.check_owner();
+        let mut spin = Spin::new();
+        loop {
+            if let Some(guard) = self.try_write() {
+                return guard;
+            }
+            self.state.fetch_or(PENDING, Ordering::Relaxed);
+            spin.wait(&self.owner);
+        }
+    }
+
+    /// Panics in debug builds if the current core holds the lock for
+    /// writing, which it would wait for forever.
+    fn check_owner(&self) {
+        let this = affinity();
+        if cfg!(debug_assertions) && self.owner.load(Ordering::Relaxed) == this {
+            panic!("deadlock: core {} locked a rwlock it holds", this);
+        }
+    }
+}
+
+impl<'a, T: 'a> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: 'a> Drop for RwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.state.fetch_sub(1, Ordering::Release);
+    }
+}
+
+impl<'a, T: 'a> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: 'a> DerefMut for RwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T: 'a> Drop for RwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.owner.store(NO_OWNER, Ordering::Relaxed);
+        // Keep `PENDING`, which another writer may have set meanwhile.
+        self.lock.state.fetch_and(!WRITER, Ordering::Release);
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_read() {
+            Some(guard) => f.debug_struct("RwLock").field("data", &&*guard).finish(),
+            None => f.debug_struct("RwLock").field("data", &"<locked>").finish(),
+        }
+    }
+}
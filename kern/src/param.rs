@@ -7,22 +7,49 @@ use core::time::Duration;
 pub use pi::common::*;
 
 pub const PAGE_ALIGN: usize = 16;
-pub const PAGE_SIZE: usize = 64 * 1024;
+/// log2 of the page size: the translation granule is 64KB, or 4KB with the
+/// `granule-4k` feature.
+#[cfg(not(feature = "granule-4k"))]
+pub const PAGE_SHIFT: usize = 16;
+#[cfg(feature = "granule-4k")]
+pub const PAGE_SHIFT: usize = 12;
+pub const PAGE_SIZE: usize = 1 << PAGE_SHIFT;
 pub const PAGE_MASK: usize = !(PAGE_SIZE - 1);
 
-pub const USER_MASK_BITS: usize = 34;
+/// `T0SZ`: user processes are translated by `TTBR0` in the lower
+/// `2^(64 - USER_MASK_BITS)` bytes of the address space.
+pub const USER_MASK_BITS: usize = 25;
+/// `T1SZ`: the kernel is translated by `TTBR1` in the upper
+/// `2^(64 - KERNEL_MASK_BITS)` bytes of the address space.
 pub const KERNEL_MASK_BITS: usize = 32;
 
-pub const USER_IMG_BASE: usize = 0xffff_ffff_c000_0000;
-const_assert_eq!(
-    USER_IMG_BASE,
-    ((1 << USER_MASK_BITS) - 1) << (64 - USER_MASK_BITS)
-);
-pub const USER_STACK_BASE: usize = core::usize::MAX & PAGE_MASK; 
-pub const USER_MAX_VM_SIZE: usize = 0x4000_0000;
-const_assert_eq!(USER_IMG_BASE.wrapping_add(USER_MAX_VM_SIZE), 0);
+// Physical memory is mapped from the bottom of the kernel's upper half.
+const_assert_eq!(KERNEL_BASE, !((1 << (64 - KERNEL_MASK_BITS)) - 1));
+
+pub const USER_IMG_BASE: usize = 0x40_0000;
+pub const USER_MAX_VM_SIZE: usize = 1 << (64 - USER_MASK_BITS);
+pub const USER_STACK_BASE: usize = USER_MAX_VM_SIZE - PAGE_SIZE;
+/// The largest size the user stack may grow to, downwards from the top of
+/// the address space.
+pub const USER_STACK_MAX_SIZE: usize = 16 * PAGE_SIZE;
+/// The physical address of the top of the boot stack; it is used at
+/// `KERNEL_BASE + KERN_STACK_BASE` once the kernel runs in the upper half.
 pub const KERN_STACK_BASE: usize = 0x80_000;
+/// The size of each core's kernel stack. Core `n`'s stack ends at
+/// `KERN_STACK_BASE - n * KERN_STACK_SIZE`.
+pub const KERN_STACK_SIZE: usize = 0x10_000;
+/// The size of the kernel heap, right after the kernel image. The rest of
+/// RAM is handed out in page frames by `FRAMES`.
+pub const KERNEL_HEAP_SIZE: usize = 64 * 1024 * 1024;
+/// The preallocated file on the FAT volume that user pages are swapped out
+/// to. Swapping is disabled if it does not exist.
+pub const SWAP_FILE: &str = "/swapfile";
+/// Whether ELF segments that are both writable and executable may be loaded.
+/// Such executables are refused by default (W^X).
+pub const USER_ALLOW_WX: bool = false;
 
-/// The `tick` time.
-// FIXME: When you're ready, change this to something more reasonable.
-pub const TICK: Duration = Duration::from_secs(2);
+/// The `tick` time: the time slice a process runs for before it is
+/// preempted.
+pub const TICK: Duration = Duration::from_millis(10);
+/// The programs the scheduler starts with, as paths on the FAT volume.
+pub const INIT_PROGRAMS: &[&str] = &["/fib", "/fib", "/sleep"];
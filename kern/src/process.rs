@@ -1,3 +1,4 @@
+mod elf;
 mod process;
 mod scheduler;
 mod stack;
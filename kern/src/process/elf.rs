@@ -0,0 +1,111 @@
+//! Just enough of the ELF64 format to load statically linked AArch64
+//! executables.
+
+use core::convert::TryInto;
+
+use kernel_api::{OsError, OsResult};
+
+use crate::vm::PagePerm;
+
+/// The first four bytes of every ELF file.
+pub const MAGIC: [u8; 4] = *b"\x7fELF";
+
+const CLASS_64: u8 = 2;
+const DATA_LSB: u8 = 1;
+const TYPE_EXEC: u16 = 2;
+const MACHINE_AARCH64: u16 = 183;
+
+/// Program header type of a loadable segment.
+pub const PT_LOAD: u32 = 1;
+
+const PF_X: u32 = 1 << 0;
+const PF_W: u32 = 1 << 1;
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+/// The fields of the ELF file header needed to load an executable.
+#[derive(Debug)]
+pub struct Header {
+    pub entry: u64,
+    pub phoff: u64,
+    pub phnum: u16,
+}
+
+impl Header {
+    /// The size of the ELF64 file header in bytes.
+    pub const SIZE: usize = 64;
+
+    /// Parses the file header in `buf`.
+    ///
+    /// Returns `OsError::InvalidArgument` if `buf` is not the header of a
+    /// little-endian ELF64 executable for AArch64.
+    pub fn parse(buf: &[u8; Header::SIZE]) -> OsResult<Header> {
+        if buf[..4] != MAGIC
+            || buf[4] != CLASS_64
+            || buf[5] != DATA_LSB
+            || u16_at(buf, 16) != TYPE_EXEC
+            || u16_at(buf, 18) != MACHINE_AARCH64
+            || u16_at(buf, 54) as usize != ProgramHeader::SIZE
+        {
+            return Err(OsError::InvalidArgument);
+        }
+
+        Ok(Header {
+            entry: u64_at(buf, 24),
+            phoff: u64_at(buf, 32),
+            phnum: u16_at(buf, 56),
+        })
+    }
+}
+
+/// An ELF64 program header.
+#[derive(Debug)]
+pub struct ProgramHeader {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+}
+
+impl ProgramHeader {
+    /// The size of an ELF64 program header in bytes.
+    pub const SIZE: usize = 56;
+
+    /// Parses the program header in `buf`.
+    pub fn parse(buf: &[u8; ProgramHeader::SIZE]) -> ProgramHeader {
+        ProgramHeader {
+            kind: u32_at(buf, 0),
+            flags: u32_at(buf, 4),
+            offset: u64_at(buf, 8),
+            vaddr: u64_at(buf, 16),
+            filesz: u64_at(buf, 32),
+            memsz: u64_at(buf, 40),
+        }
+    }
+
+    /// Returns the page permission of the segment. Every segment is readable.
+    ///
+    /// Returns `OsError::NoAccess` if the segment is both writable and
+    /// executable and `allow_wx` is `false`.
+    pub fn perm(&self, allow_wx: bool) -> OsResult<PagePerm> {
+        match (self.flags & PF_W != 0, self.flags & PF_X != 0) {
+            (true, true) if allow_wx => Ok(PagePerm::RWX),
+            (true, true) => Err(OsError::NoAccess),
+            (true, false) => Ok(PagePerm::RW),
+            (false, true) => Ok(PagePerm::RX),
+            (false, false) => Ok(PagePerm::RO),
+        }
+    }
+}
@@ -1,14 +1,15 @@
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 use shim::io;
 use shim::path::Path;
 
 use aarch64;
 
 use crate::param::*;
-use crate::process::{Stack, State};
+use crate::process::{elf, Stack, State};
 use crate::traps::TrapFrame;
 use crate::vm::*;
-use kernel_api::{OsError, OsResult};
+use kernel_api::{OsError, OsResult, AT_ENTRY, AT_NULL, AT_PAGESZ, AT_RANDOM};
 
 /// Type alias for the type of a process ID.
 pub type Id = u64;
@@ -21,7 +22,13 @@ pub struct Process {
     /// The memory allocation used for the process's stack.
     pub stack: Stack,
     /// The page table describing the Virtual Memory of the process
-    // pub vmap: Box<UserPageTable>,
+    pub vmap: Box<UserPageTable>,
+    /// The regions of virtual memory the process may access.
+    pub regions: Vec<Region>,
+    /// The start of the heap, right after the loaded image.
+    pub heap_base: VirtualAddr,
+    /// The current program break: the end of the heap.
+    pub brk: VirtualAddr,
     /// The scheduling state of the process.
     pub state: State,
 }
@@ -31,63 +38,310 @@ impl Process {
     /// stack of the default size, and a state of `Ready`.
     ///
     /// If enough memory could not be allocated to start the process, returns
-    /// `None`. Otherwise returns `Some` of the new `Process`.
+    /// `OsError::NoMemory`, and if no ASID is free for its page table,
+    /// `OsError::NoVmSpace`. Otherwise returns `Ok` of the new `Process`.
     pub fn new() -> OsResult<Process> {
-        let stack = Stack::new();
-        Some(
-            Process {
-
-            }
-        )
+        let stack = Stack::new().ok_or(OsError::NoMemory)?;
+        Ok(Process {
+            context: Box::new(TrapFrame::default()),
+            stack,
+            vmap: Box::new(UserPageTable::new()?),
+            regions: Vec::new(),
+            heap_base: Process::get_image_base(),
+            brk: Process::get_image_base(),
+            state: State::Ready,
+        })
     }
 
     /// Load a program stored in the given path by calling `do_load()` method.
+    /// Lay out `argv` and `envp` on the stack by calling `init_stack()`.
     /// Set trapframe `context` corresponding to the its page table.
-    /// `sp` - the address of stack top
-    /// `elr` - the address of image base.
-    /// `ttbr0` - the base address of kernel page table
-    /// `ttbr1` - the base address of user page table
-    /// `spsr` - `F`, `A`, `D` bit should be set.
-    ///
-    /// Returns Os Error if do_load fails.
-    pub fn load<P: AsRef<Path>>(pn: P) -> OsResult<Process> {
+    /// `sp` - the stack pointer returned by `init_stack()`
+    /// `elr` - the entry point, set by `do_load()`.
+    /// `ttbr0` - the base address and ASID of user page table
+    /// `ttbr1` - the base address of kernel page table
+    /// `spsr` - `A`, `D` bit should be set; IRQs and FIQs stay unmasked.
+    /// `x0`, `x1`, `x2` - `argc`, `argv` and `envp`.
+    ///
+    /// Returns Os Error if do_load or init_stack fails.
+    pub fn load<P: AsRef<Path>>(pn: P, argv: &[&str], envp: &[&str]) -> OsResult<Process> {
         use crate::VMM;
 
         let mut p = Process::do_load(pn)?;
+        let (sp, argv_va, envp_va) = p.init_stack(argv, envp)?;
 
-        //FIXME: Set trapframe for the process.
+        p.context.sp = sp.as_u64();
+        p.context.ttbr0 = p.vmap.ttbr();
+        p.context.ttbr1 = VMM.get_baddr().as_u64();
+        p.context.spsr |= aarch64::SPSR_EL1::A | aarch64::SPSR_EL1::D;
+        p.context.x[0] = argv.len() as u64;
+        p.context.x[1] = argv_va.as_u64();
+        p.context.x[2] = envp_va.as_u64();
 
         Ok(p)
     }
 
+    /// Creates a child of this process for the `fork` system call.
+    ///
+    /// `tf` is the trap frame of this process at the time of the call. The
+    /// child resumes from the same point with `0` in `x0`, and shares this
+    /// process's pages copy-on-write.
+    ///
+    /// Returns `OsError::NoMemory` if the child's kernel stack or page tables
+    /// could not be allocated, and `OsError::NoVmSpace` if no ASID is free.
+    pub fn fork(&mut self, tf: &TrapFrame) -> OsResult<Process> {
+        let stack = Stack::new().ok_or(OsError::NoMemory)?;
+        let vmap = Box::new(self.vmap.fork()?);
+
+        let mut context = Box::new(*tf);
+        context.ttbr0 = vmap.ttbr();
+        context.x[0] = 0;
+        context.x[7] = OsError::Ok as u64;
+
+        Ok(Process {
+            context,
+            stack,
+            vmap,
+            regions: self.regions.clone(),
+            heap_base: self.heap_base,
+            brk: self.brk,
+            state: State::Ready,
+        })
+    }
+
     /// Creates a process and open a file with given path.
-    /// Allocates one page for stack with read/write permission, and N pages with read/write/execute
-    /// permission to load file's contents.
+    /// Allocates one page for stack with read/write permission, and loads the
+    /// file's contents: an ELF executable with `load_elf()`, anything else as
+    /// a flat binary with `load_flat()`. Sets `elr` to the entry point.
+    /// Reserves a region of `USER_STACK_MAX_SIZE` bytes for the stack, which
+    /// grows downwards on demand, and starts the heap right after the image.
     fn do_load<P: AsRef<Path>>(pn: P) -> OsResult<Process> {
-        unimplemented!();
+        use crate::FILESYSTEM;
+        use fat32::traits::{Entry, FileSystem};
+
+        let mut p = Process::new()?;
+        p.vmap.alloc(Process::get_stack_base(), PagePerm::RW)?;
+
+        let mut file = FILESYSTEM.open(pn)?.into_file().ok_or(OsError::NoEntry)?;
+        let (entry, image_end) = p.load_image(&mut file)?;
+        p.context.elr = entry.as_u64();
+
+        p.regions.push(Region::new(Process::get_stack_limit(), USER_STACK_MAX_SIZE, PagePerm::RW));
+
+        p.heap_base = image_end;
+        p.brk = image_end;
+
+        Ok(p)
+    }
+
+    /// Loads the executable in `file`. Returns its entry point and the
+    /// page-aligned end of the image.
+    fn load_image<F: fat32::traits::File>(&mut self, file: &mut F) -> OsResult<(VirtualAddr, VirtualAddr)> {
+        use shim::io::{Read, Seek, SeekFrom};
+
+        let mut header = [0u8; elf::Header::SIZE];
+        if file.size() >= header.len() as u64 {
+            file.read_exact(&mut header)?;
+            if header[..4] == elf::MAGIC {
+                return self.load_elf(file, &elf::Header::parse(&header)?);
+            }
+            file.seek(SeekFrom::Start(0))?;
+        }
+
+        let end = self.load_flat(file)?;
+        Ok((Process::get_image_base(), end))
+    }
+
+    /// Loads `file` as a flat binary at `USER_IMG_BASE`, in `RWX` pages since
+    /// a flat binary does not say where its code and data are. Returns the
+    /// page-aligned end of the image.
+    fn load_flat<F: fat32::traits::File>(&mut self, file: &mut F) -> OsResult<VirtualAddr> {
+        use shim::io::Read;
+
+        let size = file.size() as usize;
+        let mut va = Process::get_image_base();
+        let mut loaded = 0;
+        while loaded < size {
+            let page = self.vmap.alloc(va, PagePerm::RWX)?;
+            let len = core::cmp::min(PAGE_SIZE, size - loaded);
+            file.read_exact(&mut page[..len])?;
+            loaded += len;
+            va += VirtualAddr::from(PAGE_SIZE);
+        }
+
+        let image_size = va.as_usize() - USER_IMG_BASE;
+        self.regions.push(Region::new(Process::get_image_base(), image_size, PagePerm::RWX));
+        Ok(va)
+    }
+
+    /// Loads the `PT_LOAD` segments of the ELF executable in `file`, whose
+    /// file header is `header`, each with the permission of its flags. Returns
+    /// the entry point and the page-aligned end of the image.
+    ///
+    /// Returns `OsError::NoAccess` if a segment is both writable and
+    /// executable, unless `USER_ALLOW_WX` is set, and
+    /// `OsError::InvalidArgument` if a segment lies outside the area between
+    /// `USER_IMG_BASE` and the stack or shares a page with another segment.
+    fn load_elf<F: fat32::traits::File>(
+        &mut self,
+        file: &mut F,
+        header: &elf::Header,
+    ) -> OsResult<(VirtualAddr, VirtualAddr)> {
+        use shim::io::{Read, Seek, SeekFrom};
+
+        let mut image_end = USER_IMG_BASE;
+        for i in 0..header.phnum as u64 {
+            let mut buf = [0u8; elf::ProgramHeader::SIZE];
+            file.seek(SeekFrom::Start(header.phoff + i * buf.len() as u64))?;
+            file.read_exact(&mut buf)?;
+
+            let segment = elf::ProgramHeader::parse(&buf);
+            if segment.kind != elf::PT_LOAD || segment.memsz == 0 {
+                continue;
+            }
+
+            let perm = segment.perm(USER_ALLOW_WX)?;
+            let vaddr = segment.vaddr as usize;
+            let limit = Process::get_stack_limit().as_usize();
+            let end = vaddr
+                .checked_add(segment.memsz as usize)
+                .filter(|&end| vaddr >= USER_IMG_BASE && end <= limit)
+                .filter(|_| segment.filesz <= segment.memsz)
+                .ok_or(OsError::InvalidArgument)?;
+
+            let start = vaddr & PAGE_MASK;
+            let end = page_round_up(end);
+            if self.regions.iter().any(|r| r.overlaps(start.into(), end - start)) {
+                return Err(OsError::InvalidArgument);
+            }
+
+            if segment.filesz > 0 {
+                file.seek(SeekFrom::Start(segment.offset))?;
+            }
+            let data_end = vaddr + segment.filesz as usize;
+            for page_va in (start..end).step_by(PAGE_SIZE) {
+                let page = self.vmap.alloc(page_va.into(), perm)?;
+                let from = core::cmp::max(page_va, vaddr);
+                let to = core::cmp::min(page_va + PAGE_SIZE, data_end);
+                if from < to {
+                    file.read_exact(&mut page[from - page_va..to - page_va])?;
+                }
+            }
+
+            self.regions.push(Region::new(start.into(), end - start, perm));
+            image_end = core::cmp::max(image_end, end);
+        }
+
+        Ok((VirtualAddr::from(header.entry), VirtualAddr::from(image_end)))
+    }
+
+    /// Lays out `argv`, `envp` and the auxiliary vector on the stack page,
+    /// below `get_stack_top()`, in a System V-like format. Returns the new
+    /// stack pointer and the addresses of the `argv` and `envp` arrays.
+    ///
+    /// From the returned stack pointer upwards, the stack holds:
+    ///
+    /// ```text
+    /// sp -> argc
+    ///       argv[0], ..., argv[argc - 1], NULL
+    ///       envp[0], ..., NULL
+    ///       AT_PAGESZ, PAGE_SIZE
+    ///       AT_ENTRY, image base
+    ///       AT_RANDOM, address of the random bytes
+    ///       AT_NULL, 0
+    ///       (padding to 16 bytes)
+    ///       16 random bytes
+    ///       NUL-terminated argument and environment strings
+    /// ```
+    ///
+    /// Returns `OsError::InvalidArgument` if all of it does not fit in the
+    /// stack page.
+    fn init_stack(
+        &mut self,
+        argv: &[&str],
+        envp: &[&str],
+    ) -> OsResult<(VirtualAddr, VirtualAddr, VirtualAddr)> {
+        fn push(page: &mut [u8], cursor: &mut usize, bytes: &[u8]) -> OsResult<()> {
+            *cursor = cursor
+                .checked_sub(bytes.len())
+                .ok_or(OsError::InvalidArgument)?;
+            page[*cursor..*cursor + bytes.len()].copy_from_slice(bytes);
+            Ok(())
+        }
+
+        let base = Process::get_stack_base().as_u64();
+        let pa = self
+            .resolve(Process::get_stack_base(), true)
+            .ok_or(OsError::BadAddress)?;
+        let ptr = pa.to_virtual().as_usize() as *mut u8;
+        let page = unsafe { core::slice::from_raw_parts_mut(ptr, PAGE_SIZE) };
+
+        // `cursor` is an offset into the stack page and grows downwards.
+        let mut cursor = (Process::get_stack_top().as_u64() - base) as usize;
+
+        let mut strings = Vec::with_capacity(argv.len() + envp.len());
+        for s in argv.iter().chain(envp.iter()) {
+            push(page, &mut cursor, &[0])?;
+            push(page, &mut cursor, s.as_bytes())?;
+            strings.push(base + cursor as u64);
+        }
+        push(page, &mut cursor, &random_bytes())?;
+        let random = base + cursor as u64;
+
+        let (argv_ptrs, envp_ptrs) = strings.split_at(argv.len());
+        let mut table: Vec<u64> = Vec::new();
+        table.push(argv.len() as u64);
+        table.extend_from_slice(argv_ptrs);
+        table.push(0);
+        table.extend_from_slice(envp_ptrs);
+        table.push(0);
+        table.extend_from_slice(&[
+            AT_PAGESZ, PAGE_SIZE as u64,
+            AT_ENTRY, Process::get_image_base().as_u64(),
+            AT_RANDOM, random,
+            AT_NULL, 0,
+        ]);
+
+        let size = table.len() * core::mem::size_of::<u64>();
+        cursor = cursor.checked_sub(size).ok_or(OsError::InvalidArgument)? & !(PAGE_ALIGN - 1);
+        for (i, word) in table.iter().enumerate() {
+            let offset = cursor + i * core::mem::size_of::<u64>();
+            page[offset..offset + 8].copy_from_slice(&word.to_le_bytes());
+        }
+
+        let sp = base + cursor as u64;
+        let argv_va = sp + 8;
+        let envp_va = argv_va + (argv.len() as u64 + 1) * 8;
+        Ok((sp.into(), argv_va.into(), envp_va.into()))
     }
 
     /// Returns the highest `VirtualAddr` that is supported by this system.
     pub fn get_max_va() -> VirtualAddr {
-        unimplemented!();
+        VirtualAddr::from(USER_MAX_VM_SIZE - 1)
     }
 
     /// Returns the `VirtualAddr` represents the base address of the user
     /// memory space.
     pub fn get_image_base() -> VirtualAddr {
-        unimplemented!();
+        VirtualAddr::from(USER_IMG_BASE)
     }
 
     /// Returns the `VirtualAddr` represents the base address of the user
     /// process's stack.
     pub fn get_stack_base() -> VirtualAddr {
-        unimplemented!();
+        VirtualAddr::from(USER_STACK_BASE)
+    }
+
+    /// Returns the lowest `VirtualAddr` the user process's stack may grow down
+    /// to.
+    pub fn get_stack_limit() -> VirtualAddr {
+        VirtualAddr::from(USER_STACK_BASE + PAGE_SIZE - USER_STACK_MAX_SIZE)
     }
 
     /// Returns the `VirtualAddr` represents the top of the user process's
     /// stack.
     pub fn get_stack_top() -> VirtualAddr {
-        unimplemented!();
+        VirtualAddr::from(Process::get_max_va().as_usize() & !(PAGE_ALIGN - 1))
     }
 
     /// Returns `true` if this process is ready to be scheduled.
@@ -105,6 +359,283 @@ impl Process {
     ///
     /// Returns `false` in all other cases.
     pub fn is_ready(&mut self) -> bool {
-        unimplemented!("Process::is_ready()")
+        let mut state = core::mem::replace(&mut self.state, State::Ready);
+        let ready = match state {
+            State::Ready => true,
+            State::Waiting(ref mut poll) => poll(self),
+            _ => false,
+        };
+
+        if !ready {
+            self.state = state;
+        }
+        ready
+    }
+
+    /// Returns the permission of the region containing the user virtual
+    /// address `va`, or `None` if `va` is outside every region.
+    pub fn region_perm(&self, va: VirtualAddr) -> Option<PagePerm> {
+        self.regions
+            .iter()
+            .find(|region| region.contains(va))
+            .map(|region| region.perm())
+    }
+
+    /// Resolves a translation fault at the user virtual address `va`.
+    ///
+    /// If `va` lies in one of the process's regions and its page is not yet
+    /// mapped, a zeroed page is mapped with the region's permission. This is
+    /// how the stack grows downwards, up to `USER_STACK_MAX_SIZE` bytes. A
+    /// page that was swapped out is read back in instead.
+    ///
+    /// Returns `OsError::BadAddress` if `va` is outside every region, and
+    /// `OsError::NoMemory` if no page could be allocated.
+    pub fn handle_fault(&mut self, va: VirtualAddr) -> OsResult<()> {
+        let perm = self.region_perm(va).ok_or(OsError::BadAddress)?;
+        self.vmap.handle_fault(va, perm)
+    }
+
+    /// Handles a write to the mapped but read-only page containing the user
+    /// virtual address `va`.
+    ///
+    /// Succeeds only if the page is shared copy-on-write and its region is
+    /// writable. Returns `OsError::NoAccess` if the region is not writable,
+    /// and `OsError::BadAddress` if the page is not copy-on-write.
+    pub fn handle_write_fault(&mut self, va: VirtualAddr) -> OsResult<()> {
+        match self.region_perm(va) {
+            Some(perm) if perm.is_writable() => self.vmap.handle_cow(va),
+            _ => Err(OsError::NoAccess),
+        }
+    }
+
+    /// Moves the program break to `addr`, growing or shrinking the heap.
+    /// New heap memory is `RW`; pages the heap no longer covers are unmapped.
+    /// If `addr` is null, the break is left where it is.
+    ///
+    /// Returns the new program break, or `OsError::NoVmSpace` if `addr` lies
+    /// below the start of the heap or the heap would run into another region.
+    pub fn set_brk(&mut self, addr: VirtualAddr) -> OsResult<VirtualAddr> {
+        let addr = addr.as_usize();
+        if addr == 0 {
+            return Ok(self.brk);
+        }
+
+        let base = self.heap_base.as_usize();
+        if addr < base || addr > Process::get_stack_limit().as_usize() {
+            return Err(OsError::NoVmSpace);
+        }
+
+        let old_end = page_round_up(self.brk.as_usize());
+        let new_end = page_round_up(addr);
+        if new_end > old_end {
+            let grown = new_end - old_end;
+            if self.regions.iter().any(|r| r.overlaps(old_end.into(), grown)) {
+                return Err(OsError::NoVmSpace);
+            }
+
+            // Extend the region the heap ends with, unless it was `mprotect`ed.
+            let last = self.regions.iter_mut().find(|r| {
+                r.perm() == PagePerm::RW && r.start().as_usize().wrapping_add(r.len()) == old_end
+            });
+            match last {
+                Some(region) => {
+                    let len = region.len() + grown;
+                    region.set_len(len);
+                }
+                None => self.regions.push(Region::new(old_end.into(), grown, PagePerm::RW)),
+            }
+        } else if new_end < old_end {
+            self.unmap_range(new_end, old_end - new_end);
+        }
+
+        self.brk = VirtualAddr::from(addr);
+        Ok(self.brk)
+    }
+
+    /// Reserves a region of at least `len` bytes with permission `perm`
+    /// between the heap and the stack. Its pages are mapped on demand.
+    ///
+    /// `hint`, if it is not null, is the preferred start of the region. It is
+    /// used if it is page aligned and the region fits there; otherwise the
+    /// highest free range below the stack is chosen.
+    ///
+    /// Returns the start of the region. Returns `OsError::InvalidArgument` if
+    /// `len` is zero, and `OsError::NoVmSpace` if no free range is large
+    /// enough.
+    pub fn mmap(&mut self, hint: VirtualAddr, len: usize, perm: PagePerm) -> OsResult<VirtualAddr> {
+        let (low, high) = self.mmap_bounds();
+        if len == 0 {
+            return Err(OsError::InvalidArgument);
+        }
+        if len > high - low {
+            return Err(OsError::NoVmSpace);
+        }
+
+        let len = page_round_up(len);
+        let hint = hint.as_usize();
+        let is_free = |regions: &[Region], start: usize| {
+            !regions.iter().any(|r| r.overlaps(VirtualAddr::from(start), len))
+        };
+
+        let start = if hint != 0
+            && hint & !PAGE_MASK == 0
+            && hint >= low
+            && hint <= high
+            && high - hint >= len
+            && is_free(&self.regions, hint)
+        {
+            hint
+        } else {
+            let mut start = high - len;
+            loop {
+                let start_va = VirtualAddr::from(start);
+                match self.regions.iter().find(|r| r.overlaps(start_va, len)) {
+                    None => break start,
+                    Some(r) if r.start().as_usize() >= low + len => {
+                        start = r.start().as_usize() - len
+                    }
+                    Some(_) => return Err(OsError::NoVmSpace),
+                }
+            }
+        };
+
+        self.regions.push(Region::new(VirtualAddr::from(start), len, perm));
+        Ok(VirtualAddr::from(start))
+    }
+
+    /// Removes the pages in the `len` bytes starting at `addr` from the
+    /// regions created by `mmap()`, unmapping them. Regions that are only
+    /// partly covered are split.
+    ///
+    /// Returns `OsError::InvalidArgument` if `addr` is not page aligned, `len`
+    /// is zero, or the range reaches outside the area `mmap()` allocates from.
+    pub fn munmap(&mut self, addr: VirtualAddr, len: usize) -> OsResult<()> {
+        let (low, high) = self.mmap_bounds();
+        let start = addr.as_usize();
+        if start & !PAGE_MASK != 0 || len == 0 || start < low || start > high || len > high - start {
+            return Err(OsError::InvalidArgument);
+        }
+
+        self.unmap_range(start, page_round_up(len));
+        Ok(())
+    }
+
+    /// Changes the permission of the pages in the `len` bytes starting at
+    /// `addr` to `perm`, splitting regions that are only partly covered.
+    ///
+    /// Returns `OsError::InvalidArgument` if `addr` is not page aligned or
+    /// `len` is zero, and `OsError::BadAddress` if any page in the range is
+    /// not part of a region.
+    pub fn mprotect(&mut self, addr: VirtualAddr, len: usize, perm: PagePerm) -> OsResult<()> {
+        let start = addr.as_usize();
+        if start & !PAGE_MASK != 0 || len == 0 {
+            return Err(OsError::InvalidArgument);
+        }
+        if start < USER_IMG_BASE || start.checked_add(len - 1).is_none() {
+            return Err(OsError::BadAddress);
+        }
+
+        let len = page_round_up(len);
+        let pages = (0..len / PAGE_SIZE).map(|i| VirtualAddr::from(start + i * PAGE_SIZE));
+        for page in pages.clone() {
+            if !self.regions.iter().any(|r| r.contains(page)) {
+                return Err(OsError::BadAddress);
+            }
+        }
+
+        for page in pages {
+            self.vmap.protect(page, perm);
+        }
+        self.update_regions(start, len, Some(perm));
+
+        Ok(())
+    }
+
+    /// Unmaps the pages in the `len` bytes starting at `start` and removes
+    /// them from the process's regions.
+    fn unmap_range(&mut self, start: usize, len: usize) {
+        for i in 0..len / PAGE_SIZE {
+            self.vmap.dealloc(VirtualAddr::from(start + i * PAGE_SIZE));
+        }
+        self.update_regions(start, len, None);
+    }
+
+    /// Cuts the `len` bytes starting at `start` out of every region they
+    /// overlap. If `perm` is set, the cut out parts are added back as regions
+    /// with permission `perm`.
+    ///
+    /// `start` and `len` must be page aligned, and `len` non-zero.
+    fn update_regions(&mut self, start: usize, len: usize, perm: Option<PagePerm>) {
+        // Regions may end at the very top of the address space, so work with
+        // the last address of each range instead of its end.
+        let last = start + (len - 1);
+        let mut regions = Vec::with_capacity(self.regions.len() + 2);
+        for r in self.regions.drain(..) {
+            if !r.overlaps(start.into(), len) {
+                regions.push(r);
+                continue;
+            }
+
+            let r_start = r.start().as_usize();
+            let r_last = r_start + (r.len() - 1);
+            if r_start < start {
+                regions.push(Region::new(r.start(), start - r_start, r.perm()));
+            }
+            if r_last > last {
+                regions.push(Region::new((last + 1).into(), r_last - last, r.perm()));
+            }
+            if let Some(perm) = perm {
+                let cut_start = core::cmp::max(r_start, start);
+                let cut_last = core::cmp::min(r_last, last);
+                regions.push(Region::new(cut_start.into(), cut_last - cut_start + 1, perm));
+            }
+        }
+        self.regions = regions;
+    }
+
+    /// Returns the bounds of the area `mmap()` allocates from: everything
+    /// between the page after the program break and the lowest address the
+    /// stack may grow down to.
+    fn mmap_bounds(&self) -> (usize, usize) {
+        (page_round_up(self.brk.as_usize()), Process::get_stack_limit().as_usize())
+    }
+
+    /// Translates the user virtual address `va` like
+    /// `UserPageTable::translate()`, first mapping the page on demand if it
+    /// lies in one of the process's regions, or copying it if `write` is set
+    /// and the page is shared copy-on-write.
+    pub fn resolve(&mut self, va: VirtualAddr, write: bool) -> Option<PhysicalAddr> {
+        if let Some(pa) = self.vmap.translate(va, write) {
+            return Some(pa);
+        }
+
+        if write && self.vmap.translate(va, false).is_some() {
+            self.handle_write_fault(va).ok()?;
+        } else {
+            self.handle_fault(va).ok()?;
+        }
+        self.vmap.translate(va, write)
+    }
+}
+
+/// Rounds `addr` up to the next multiple of `PAGE_SIZE`.
+fn page_round_up(addr: usize) -> usize {
+    (addr + PAGE_SIZE - 1) & PAGE_MASK
+}
+
+/// Returns 16 bytes for the `AT_RANDOM` auxiliary vector entry.
+///
+/// The bytes are derived from the system timer and are not suitable for
+/// cryptographic use.
+fn random_bytes() -> [u8; 16] {
+    let mut state = pi::timer::current_time().as_nanos() as u64 | 1;
+    let mut bytes = [0u8; 16];
+    for chunk in bytes.chunks_mut(8) {
+        // xorshift64
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        chunk.copy_from_slice(&state.to_le_bytes());
     }
+    bytes
 }
@@ -1,42 +1,87 @@
 use alloc::boxed::Box;
 use alloc::collections::vec_deque::VecDeque;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
 use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use aarch64::*;
+use pi::generic_timer::{GenericTimer, Kind};
+use pi::local_interrupt::{LocalController, LocalInterrupt};
 
-use crate::mutex::Mutex;
-use crate::param::{PAGE_MASK, PAGE_SIZE, TICK, USER_IMG_BASE};
+use crate::console::kprintln;
+use crate::mutex::{IrqMutex, Mutex, Once};
+use crate::param::{INIT_PROGRAMS, KERNEL_BASE, KERN_STACK_BASE, KERN_STACK_SIZE, NCORES, PAGE_MASK, PAGE_SIZE, TICK, USER_IMG_BASE};
 use crate::process::{Id, Process, State};
 use crate::traps::TrapFrame;
-use crate::VMM;
-use run_shell;
+use crate::{IRQ, SCHEDULER, VMM};
+
+/// The mailbox of the local interrupt controller that reschedule IPIs are
+/// sent through.
+const IPI_MAILBOX: usize = 0;
 
 /// Process scheduler for the entire machine.
 #[derive(Debug)]
-pub struct GlobalScheduler(Mutex<Option<Scheduler>>);
+pub struct GlobalScheduler {
+    inner: Once<Scheduler>,
+    /// Set once core 0 starts scheduling, which the other cores wait for.
+    started: AtomicBool,
+}
 
 impl GlobalScheduler {
     /// Returns an uninitialized wrapper around a local scheduler.
     pub const fn uninitialized() -> GlobalScheduler {
-        GlobalScheduler(Mutex::new(None))
+        GlobalScheduler {
+            inner: Once::new(),
+            started: AtomicBool::new(false),
+        }
     }
 
-    /// Enter a critical region and execute the provided closure with the
-    /// internal scheduler.
-    pub fn critical<F, R>(&self, f: F) -> R
+    /// Returns the initialized scheduler.
+    fn scheduler(&self) -> &Scheduler {
+        self.inner.get().expect("scheduler uninitialized")
+    }
+
+    /// Runs `f` with the process running on the current core, whose saved
+    /// trap frame belongs to the same process as `tf`, with the core's queue
+    /// locked. Returns `None` if no such process is in the queue.
+    pub fn with_running<F, R>(&self, tf: &TrapFrame, f: F) -> Option<R>
     where
-        F: FnOnce(&mut Scheduler) -> R,
+        F: FnOnce(&mut Process) -> R,
     {
-        let mut guard = self.0.lock();
-        f(guard.as_mut().expect("scheduler uninitialized"))
+        self.scheduler().queues[affinity()]
+            .lock()
+            .iter_mut()
+            .find(|process| process.context.tpidr == tf.tpidr)
+            .map(f)
+    }
+
+    /// Calls `f` with every process and the core whose queue it is in,
+    /// locking one queue at a time. The running process of each core comes
+    /// first.
+    pub fn for_each<F>(&self, mut f: F)
+    where
+        F: FnMut(usize, &Process),
+    {
+        for (core, queue) in self.scheduler().queues.iter().enumerate() {
+            for process in queue.lock().iter() {
+                f(core, process);
+            }
+        }
     }
 
 
     /// Adds a process to the scheduler's queue and returns that process's ID.
     /// For more details, see the documentation on `Scheduler::add()`.
+    ///
+    /// If the process is queued on another core, that core is sent a
+    /// reschedule IPI, which wakes it if it is idle.
     pub fn add(&self, process: Process) -> Option<Id> {
-
-        self.critical(move |scheduler| scheduler.add(process))
+        let (id, core) = self.scheduler().add(process)?;
+        if core != affinity() {
+            send_reschedule(core);
+        }
+        Some(id)
     }
 
     /// Performs a context switch using `tf` by setting the state of the current
@@ -44,17 +89,28 @@ impl GlobalScheduler {
     /// restoring the next process's trap frame into `tf`. For more details, see
     /// the documentation on `Scheduler::schedule_out()` and `Scheduler::switch_to()`.
     pub fn switch(&self, new_state: State, tf: &mut TrapFrame) -> Id {
-        self.critical(|scheduler| scheduler.schedule_out(new_state, tf));
+        self.scheduler().schedule_out(affinity(), new_state, tf);
         self.switch_to(tf)
     }
 
+    /// Restores the next process of the current core into `tf`, and arms the
+    /// core's timer for a full `TICK`. Until a process is ready, the core
+    /// idles, waking every `TICK` to poll waiting processes and whenever it
+    /// is sent a reschedule IPI.
     pub fn switch_to(&self, tf: &mut TrapFrame) -> Id {
+        let core = affinity();
+        let mut timer = GenericTimer::new(Kind::Physical);
         loop {
-            let rtn = self.critical(|scheduler| scheduler.switch_to(tf));
+            let rtn = self.scheduler().switch_to(core, tf);
+            timer.tick_in(TICK);
             if let Some(id) = rtn {
                 return id;
             }
-            aarch64::wfe();
+
+            // The timer and IPIs wake the core even though IRQs are masked,
+            // and stay pending until they are handled here.
+            wfi();
+            LocalController::new(core).clear_mailbox(IPI_MAILBOX, !0);
         }
     }
 
@@ -62,41 +118,77 @@ impl GlobalScheduler {
     /// For more details, see the documentaion on `Scheduler::kill()`.
     #[must_use]
     pub fn kill(&self, tf: &mut TrapFrame) -> Option<Id> {
-        self.critical(|scheduler| scheduler.kill(tf))
+        self.scheduler().kill(affinity(), tf)
     }
 
-    /// Starts executing processes in user space using timer interrupt based
-    /// preemptive scheduling. This method should not return under normal conditions.
+    /// Starts executing processes in user space on the current core using
+    /// timer interrupt based preemptive scheduling. This method should not
+    /// return under normal conditions.
+    ///
+    /// Every `TICK`, the core's generic timer interrupts the running process,
+    /// which is scheduled out as `Ready` in favor of the next ready process.
+    /// Core 0 registers the handlers of the timer and of reschedule IPIs;
+    /// the other cores wait for it to do so.
     pub fn start(&self) -> ! {
-        *self.0.lock() = Some(Scheduler::new());
-        let mut p = Process::new().unwrap();
-        p.trap_frame.elr = run_shell as u64;
-        p.trap_frame.sp = p.stack.top().as_u64();
-        p.trap_frame.spsr = 0x0;
-        let tf = p.trap_frame.clone();
-
-        unsafe {
-            asm!(
-                "mov SP, $0
+        let core = affinity();
+        if core == 0 {
+            IRQ.register(
+                LocalInterrupt::CntPns,
+                Box::new(|tf| {
+                    SCHEDULER.switch(State::Ready, tf);
+                }),
+            );
+            IRQ.register(
+                LocalInterrupt::mailbox(IPI_MAILBOX),
+                Box::new(|tf| {
+                    LocalController::new(affinity()).clear_mailbox(IPI_MAILBOX, !0);
+                    SCHEDULER.switch(State::Ready, tf);
+                }),
+            );
+            self.started.store(true, Ordering::Release);
+            sev();
+        } else {
+            while !self.started.load(Ordering::Acquire) {
+                wfe();
+            }
+        }
 
-                bl context_restore
+        let mut local = LocalController::new(core);
+        local.enable_timer(LocalInterrupt::CntPns);
+        local.enable_mailbox(IPI_MAILBOX);
 
-                adr x0, _start
-                mov SP, x0
-                mov x0, xzr
-                mov lr, xzr
+        let mut tf = TrapFrame::default();
+        self.switch_to(&mut tf);
 
-                eret"
-                :: "r"(tf)
-                :: "volatile");
-        }
-        loop {
+        // Restore the first process's trap frame from the stack, reset the
+        // stack, and return to the process. `context_restore` leaves `x28`,
+        // `x29` and `lr` alone, which are cleared instead.
+        unsafe {
+            asm!("mov sp, $0
+                  bl context_restore
+                  mov sp, x28
+                  mov x28, xzr
+                  mov x29, xzr
+                  mov lr, xzr
+                  eret"
+                 :: "r"(&tf), "{x28}"(KERNEL_BASE + KERN_STACK_BASE - core * KERN_STACK_SIZE)
+                 :: "volatile");
         }
+        unreachable!()
     }
 
-    /// Initializes the scheduler and add userspace processes to the Scheduler
+    /// Initializes the scheduler and adds the processes of `INIT_PROGRAMS`
+    /// to it. Programs that fail to load are reported and skipped.
     pub unsafe fn initialize(&self) {
-        unimplemented!("GlobalScheduler::initialize()")
+        self.inner.call_once(Scheduler::new);
+        for &program in INIT_PROGRAMS.iter() {
+            match Process::load(program, &[program], &[]) {
+                Ok(process) => {
+                    self.add(process);
+                }
+                Err(e) => kprintln!("failed to load {}: {:?}", program, e),
+            }
+        }
     }
 
     // The following method may be useful for testing Phase 3:
@@ -117,59 +209,173 @@ impl GlobalScheduler {
     // }
 }
 
+/// Sends a reschedule IPI to `core`.
+fn send_reschedule(core: usize) {
+    LocalController::new(affinity()).send(core, IPI_MAILBOX, 1);
+}
+
+/// The run queues of the cores.
+///
+/// Every core runs the processes of its own queue, the one it runs at the
+/// front. New processes go to the shortest queue. A core that has no ready
+/// process steals one from the back of the longest queue of another core.
+///
+/// Every queue has its own lock, so cores only wait for each other to add
+/// processes to or steal them from the same queue.
 #[derive(Debug)]
 pub struct Scheduler {
-    processes: VecDeque<Process>,
-    last_id: Option<Id>,
+    queues: Vec<IrqMutex<VecDeque<Process>>>,
+    last_id: Mutex<Option<Id>>,
 }
 
 impl Scheduler {
-    /// Returns a new `Scheduler` with an empty queue.
+    /// Returns a new `Scheduler` with empty queues.
     fn new() -> Scheduler {
         Scheduler {
-            processes: VecDeque::new(),
-            last_id: None,
+            queues: (0..NCORES).map(|_| IrqMutex::new(VecDeque::new())).collect(),
+            last_id: Mutex::new(None),
+        }
     }
 
-    /// Adds a process to the scheduler's queue and returns that process's ID if
-    /// a new process can be scheduled. The process ID is newly allocated for
-    /// the process and saved in its `trap_frame`. If no further processes can
-    /// be scheduled, returns `None`.
+    /// Adds a process to the shortest queue and returns that process's ID
+    /// and the core of the queue, if a new process can be scheduled. The
+    /// process ID is newly allocated for the process and saved in its
+    /// `trap_frame`. If no further processes can be scheduled, returns
+    /// `None`.
     ///
     /// It is the caller's responsibility to ensure that the first time `switch`
     /// is called, that process is executing on the CPU.
-    fn add(&mut self, mut process: Process) -> Option<Id> {
-        unimplemented!("Scheduler::add()")
+    fn add(&self, mut process: Process) -> Option<(Id, usize)> {
+        let id = {
+            let mut last_id = self.last_id.lock();
+            let id = match *last_id {
+                Some(last_id) => last_id.checked_add(1)?,
+                None => 0,
+            };
+            *last_id = Some(id);
+            id
+        };
+        let core = (0..NCORES).min_by_key(|&core| self.queues[core].lock().len())?;
+
+        process.context.tpidr = id;
+        self.queues[core].lock().push_back(process);
+        Some((id, core))
     }
 
-    /// Finds the currently running process, sets the current process's state
-    /// to `new_state`, prepares the context switch on `tf` by saving `tf`
-    /// into the current process, and push the current process back to the
-    /// end of `processes` queue.
+    /// Finds the process running on `core`, sets its state to `new_state`,
+    /// prepares the context switch on `tf` by saving `tf` into the process,
+    /// and push the process back to the end of the core's queue.
     ///
-    /// If the `processes` queue is empty or there is no current process,
-    /// returns `false`. Otherwise, returns `true`.
-    fn schedule_out(&mut self, new_state: State, tf: &mut TrapFrame) -> bool {
-        unimplemented!("Scheduler::schedule_out()")
+    /// If the queue is empty or there is no current process, returns
+    /// `false`. Otherwise, returns `true`.
+    fn schedule_out(&self, core: usize, new_state: State, tf: &mut TrapFrame) -> bool {
+        schedule_out(&mut self.queues[core].lock(), new_state, tf)
     }
 
-    /// Finds the next process to switch to, brings the next process to the
-    /// front of the `processes` queue, changes the next process's state to
-    /// `Running`, and performs context switch by restoring the next process`s
-    /// trap frame into `tf`.
+    /// Finds the next process for `core` to switch to, in its own queue or
+    /// else stolen from another core's, brings it to the front of the core's
+    /// queue, changes its state to `Running`, and performs context switch by
+    /// restoring its trap frame into `tf`.
     ///
     /// If there is no process to switch to, returns `None`. Otherwise, returns
     /// `Some` of the next process`s process ID.
-    fn switch_to(&mut self, tf: &mut TrapFrame) -> Option<Id> {
-        unimplemented!("Scheduler::switch_to()")
+    ///
+    /// The `ttbr0` of the restored trap frame carries the ASID of the next
+    /// process's page table, so the switch invalidates no TLB entries.
+    fn switch_to(&self, core: usize, tf: &mut TrapFrame) -> Option<Id> {
+        {
+            let mut queue = self.queues[core].lock();
+            if let Some(index) = queue.iter_mut().position(|process| process.is_ready()) {
+                let process = queue.remove(index)?;
+                return Some(run(&mut queue, process, tf));
+            }
+        }
+        self.steal(core, tf)
     }
 
-    /// Kills currently running process by scheduling out the current process
-    /// as `Dead` state. Removes the dead process from the queue, drop the
-    /// dead process's instance, and returns the dead process's process ID.
-    fn kill(&mut self, tf: &mut TrapFrame) -> Option<Id> {
-        unimplemented!("Scheduler::kill()")
+    /// Takes a ready process from the back of the queue of another core than
+    /// `core`, trying the longest queues first, and runs it on `core` as
+    /// `switch_to` does.
+    ///
+    /// Both queues are locked while the process moves, the queue of the
+    /// lower core first, so that two cores stealing from each other cannot
+    /// deadlock.
+    fn steal(&self, core: usize, tf: &mut TrapFrame) -> Option<Id> {
+        let mut victims: Vec<usize> = (0..NCORES).filter(|&victim| victim != core).collect();
+        victims.sort_by_key(|&victim| Reverse(self.queues[victim].lock().len()));
+
+        for victim in victims {
+            let (low, high) = if core < victim { (core, victim) } else { (victim, core) };
+            // Guards drop in reverse order, so IRQs are only unmasked once
+            // both queues are unlocked.
+            let mut first = self.queues[low].lock();
+            let mut second = self.queues[high].lock();
+            let (queue, victim_queue) = if core < victim {
+                (&mut *first, &mut *second)
+            } else {
+                (&mut *second, &mut *first)
+            };
+
+            if let Some(index) = victim_queue.iter_mut().rposition(|process| process.is_ready()) {
+                let process = victim_queue.remove(index)?;
+                return Some(run(queue, process, tf));
+            }
+        }
+        None
     }
+
+    /// Kills the process running on `core` by scheduling it out as `Dead`
+    /// state. Removes the dead process from the queue, drop the dead
+    /// process's instance, and returns the dead process's process ID.
+    ///
+    /// The process is dropped once the queue is unlocked: freeing its pages
+    /// waits for the swap, which may be writing to the SD card.
+    fn kill(&self, core: usize, tf: &mut TrapFrame) -> Option<Id> {
+        let process = {
+            let mut queue = self.queues[core].lock();
+            if !schedule_out(&mut queue, State::Dead, tf) {
+                return None;
+            }
+            queue.pop_back()?
+        };
+
+        let id = process.context.tpidr;
+        drop(process);
+        Some(id)
+    }
+}
+
+/// Finds the running process of `queue`, the one of `tf`, sets its state to
+/// `new_state`, saves `tf` into it and moves it to the end of the queue.
+/// Returns `false` if there is no such process.
+fn schedule_out(queue: &mut VecDeque<Process>, new_state: State, tf: &mut TrapFrame) -> bool {
+    let index = queue.iter().position(|process| {
+        process.context.tpidr == tf.tpidr
+            && match process.state {
+                State::Running => true,
+                _ => false,
+            }
+    });
+
+    match index.and_then(|index| queue.remove(index)) {
+        Some(mut process) => {
+            process.state = new_state;
+            *process.context = *tf;
+            queue.push_back(process);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Puts `process` at the front of `queue` as `Running`, restores its trap
+/// frame into `tf` and returns its ID.
+fn run(queue: &mut VecDeque<Process>, mut process: Process, tf: &mut TrapFrame) -> Id {
+    process.state = State::Running;
+    *tf = *process.context;
+    let id = process.context.tpidr;
+    queue.push_front(process);
+    id
 }
 
 pub extern "C" fn  test_user_process() -> ! {
@@ -1,11 +1,16 @@
-use core::alloc::{GlobalAlloc, Layout};
 use core::fmt;
 use core::ptr::Unique;
 
-use crate::vm::PhysicalAddr;
-use crate::ALLOCATOR;
+use crate::param::PAGE_SIZE;
+use crate::vm::VirtualAddr;
+use crate::{FRAMES, VMM};
 
 /// A process stack. The default size is 1MiB with an alignment of 16 bytes.
+/// Stacks are allocated in page frames.
+///
+/// The page below the stack is unmapped from the kernel page table while the
+/// stack is alive, so that overflowing the stack faults instead of
+/// corrupting the memory below it.
 pub struct Stack {
     ptr: Unique<[u8; Stack::SIZE]>,
 }
@@ -17,18 +22,18 @@ impl Stack {
     /// The default stack alignment is 16 bytes.
     pub const ALIGN: usize = 16;
 
-    /// The default layout for a stack.
-    fn layout() -> Layout {
-        unsafe { Layout::from_size_align_unchecked(Self::SIZE, Self::ALIGN) }
-    }
+    /// The number of page frames of a stack and the guard page below it.
+    const FRAMES: usize = Self::SIZE / PAGE_SIZE + 1;
 
     /// Returns a newly allocated process stack, zeroed out, if one could be
     /// successfully allocated. If there is no memory, or memory allocation
     /// fails for some other reason, returns `None`.
     pub fn new() -> Option<Stack> {
         let raw_ptr = unsafe {
-            let raw_ptr: *mut u8 = ALLOCATOR.alloc(Stack::layout());
-            assert!(!raw_ptr.is_null());
+            let guard = FRAMES.alloc(Self::FRAMES)?.to_virtual();
+            VMM.unmap(guard);
+
+            let raw_ptr = (guard.as_usize() + PAGE_SIZE) as *mut u8;
             raw_ptr.write_bytes(0, Self::SIZE);
             raw_ptr
         };
@@ -42,20 +47,30 @@ impl Stack {
         self.ptr.as_ptr() as _
     }
 
-    /// Returns the physical address of top of the stack.
-    pub fn top(&self) -> PhysicalAddr {
+    /// Returns the address of the guard page below the stack.
+    unsafe fn guard(&self) -> *mut u8 {
+        self.as_mut_ptr().sub(PAGE_SIZE)
+    }
+
+    /// Returns the virtual address of top of the stack.
+    pub fn top(&self) -> VirtualAddr {
         unsafe { self.as_mut_ptr().add(Self::SIZE).into() }
     }
 
-    /// Returns the physical address of bottom of the stack.
-    pub fn bottom(&self) -> PhysicalAddr {
+    /// Returns the virtual address of bottom of the stack.
+    pub fn bottom(&self) -> VirtualAddr {
         unsafe { self.as_mut_ptr().into() }
     }
 }
 
 impl Drop for Stack {
     fn drop(&mut self) {
-        unsafe { ALLOCATOR.dealloc(self.as_mut_ptr(), Self::layout()) }
+        unsafe {
+            // The guard page's frame may be reused as soon as it is freed.
+            let guard = VirtualAddr::from(self.guard());
+            VMM.remap(guard);
+            FRAMES.dealloc(guard.to_physical(), Self::FRAMES)
+        }
     }
 }
 
@@ -8,12 +8,21 @@ use pi::atags::Atags;
 use fat32::traits::FileSystem;
 use fat32::traits::{Dir, Entry};
 
-use crate::console::{kprint, kprintln, CONSOLE};
+use aarch64::brk;
+
+use crate::console::{kprint, kprintln, wait_for_byte, CONSOLE};
+use crate::traps::TrapFrame;
 use crate::ALLOCATOR;
 use crate::FILESYSTEM;
+use crate::{FRAMES, IRQ, SWAP};
 use fat32::traits::Metadata;
 use alloc::string::String;
 
+mod debug;
+mod disasm;
+
+use self::debug::Action;
+
 /// Error type for `Command` parse failures.
 #[derive(Debug)]
 enum Error {
@@ -56,6 +65,18 @@ impl<'a> Command<'a> {
 /// Starts a shell using `prefix` as the prefix for each line. This function
 /// never returns.
 pub fn shell(prefix: &str) {
+    run(prefix, None)
+}
+
+/// Starts the debug shell on the trap frame `tf` of the code that trapped,
+/// which offers commands to inspect and change its registers, memory and
+/// processes besides the shell's own. Returns when the code is resumed or
+/// stepped.
+pub fn debug(tf: &mut TrapFrame) {
+    run("debug> ", Some(tf))
+}
+
+fn run(prefix: &str, mut tf: Option<&mut TrapFrame>) {
     let mut exit = false;
     let mut working_dir = PathBuf::from("/");
     loop {
@@ -63,14 +84,14 @@ pub fn shell(prefix: &str) {
         let mut buf_mem = [0u8; 512];
         let mut buffer = StackVec::new(&mut buf_mem);
         //loop {
-            let mut x = CONSOLE.lock();
             kprint!("\r{}", prefix);
         //    if x.inner().has_byte() { break; }
         //}
         loop {
             if exit == true { break; }
-            let mut x = CONSOLE.lock();
-            let byte = x.read_byte();
+            // Don't hold the console while waiting for a key.
+            wait_for_byte();
+            let byte = CONSOLE.lock().read_byte();
 
             if byte == b'\r' || byte == b'\n' {
                 let mut input_mem: [&str; 64] = [""; 64];
@@ -86,7 +107,15 @@ pub fn shell(prefix: &str) {
                     Err(Error::Empty) => {
                     },
                     Ok(command) => {
-                        if command.path() == "echo" {
+                        let action = match tf {
+                            Some(ref mut tf) => debug::execute(&command.args, tf),
+                            None => Action::Unknown,
+                        };
+
+                        if action == Action::Resume {
+                            exit = true;
+                        } else if action == Action::Done {
+                        } else if command.path() == "echo" {
                             let num_args = command.args.len();
 
                             if num_args > 1 {
@@ -103,6 +132,36 @@ pub fn shell(prefix: &str) {
                             ls(&command.args[1..], &working_dir);
                         } else if command.path() == "cat" {
                             cat(&command.args[1..], &working_dir);
+                        } else if command.path() == "frames" {
+                            kprintln!("{}", FRAMES.stats());
+                        } else if command.path() == "meminfo" {
+                            kprintln!("{}", ALLOCATOR.stats());
+                            for stats in ALLOCATOR.slabs().iter() {
+                                kprintln!("{}", stats);
+                            }
+                            kprintln!("frames: {}", FRAMES.stats());
+                        } else if command.path() == "heap" {
+                            ALLOCATOR.dump_live();
+                        } else if command.path() == "slabs" {
+                            if command.args.len() > 1 && command.args[1] == "reclaim" {
+                                kprintln!("reclaimed {} bytes", ALLOCATOR.reclaim());
+                            }
+                            for stats in ALLOCATOR.slabs().iter() {
+                                kprintln!("{}", stats);
+                            }
+                        } else if command.path() == "irq" {
+                            let (stats, max_depth) = IRQ.stats();
+                            for (source, stats) in stats.iter() {
+                                kprintln!("{:?}: {}", source, stats);
+                            }
+                            kprintln!("deepest nesting: {}", max_depth);
+                        } else if command.path() == "swap" {
+                            match SWAP.stats() {
+                                Some(stats) => kprintln!("{}", stats),
+                                None => kprintln!("swapping disabled"),
+                            }
+                        } else if command.path() == "brk" {
+                            brk!(0);
                         } else if command.path() == "exit" {
                             exit = true;
                         } else {
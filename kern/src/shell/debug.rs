@@ -0,0 +1,230 @@
+use crate::backtrace;
+use crate::console::{kprint, kprintln};
+use crate::shell::disasm::Instruction;
+use crate::traps::{step, TrapFrame};
+use crate::vm::linear_alias;
+use crate::SCHEDULER;
+
+/// The most bytes `hexdump` dumps at once.
+const MAX_DUMP: usize = 4096;
+
+/// What the debug shell does after a command.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Action {
+    /// The command was handled: read the next one.
+    Done,
+    /// Leave the debug shell and return to the trapped code.
+    Resume,
+    /// The command is not a debug command.
+    Unknown,
+}
+
+/// Executes the debug command `args` on the trap frame `tf` of the code that
+/// trapped.
+pub fn execute(args: &[&str], tf: &mut TrapFrame) -> Action {
+    match args[0] {
+        "regs" => {
+            if args.get(1) == Some(&"q") {
+                kprint!("{:#}", tf);
+            } else {
+                kprint!("{}", tf);
+            }
+        }
+        "reg" => match args.len() {
+            2 => match read_register(tf, args[1]) {
+                Some(value) => kprintln!("{} = {:#x}", args[1], value),
+                None => kprintln!("reg: no register {}", args[1]),
+            },
+            3 => match parse(args[2]) {
+                Some(value) if write_register(tf, args[1], value) => {}
+                Some(_) => kprintln!("reg: no writable register {}", args[1]),
+                None => kprintln!("reg: invalid value {}", args[2]),
+            },
+            _ => kprintln!("usage: reg <name> [value]"),
+        },
+        "hexdump" => match (args.get(1).and_then(|a| parse(a)), args.get(2)) {
+            (Some(addr), None) => hexdump(addr as usize, 64),
+            (Some(addr), Some(len)) => match parse(len) {
+                Some(len) => hexdump(addr as usize, (len as usize).min(MAX_DUMP)),
+                None => kprintln!("hexdump: invalid length {}", len),
+            },
+            (None, _) => kprintln!("usage: hexdump <addr> [len]"),
+        },
+        "disasm" => {
+            let count = args.get(2).and_then(|a| parse(a)).unwrap_or(9) as usize;
+            match args.get(1) {
+                Some(addr) => match parse(addr) {
+                    Some(addr) => disasm(addr as usize, count, tf.elr as usize),
+                    None => kprintln!("disasm: invalid address {}", addr),
+                },
+                // Center on the instruction the trapped code resumes at.
+                None => {
+                    let elr = tf.elr as usize;
+                    disasm(elr.saturating_sub((count / 2).saturating_mul(4)), count, elr)
+                }
+            }
+        }
+        "ps" => {
+            // Print once the queues are unlocked again.
+            let mut rows = alloc::vec::Vec::new();
+            SCHEDULER.for_each(|core, process| {
+                let current = process.context.tpidr == tf.tpidr && tf.is_user();
+                // The saved context of the process that trapped is stale.
+                let elr = if current { tf.elr } else { process.context.elr };
+                let state = alloc::format!("{:?}", process.state);
+                rows.push((current, process.context.tpidr, core, state, elr));
+            });
+
+            kprintln!("  {:>4} {:>4} {:<16} {:>18}", "pid", "core", "state", "elr");
+            for (current, id, core, state, elr) in rows {
+                kprintln!(
+                    "{} {:>4} {:>4} {:<16} {:#018x}",
+                    if current { "*" } else { " " },
+                    id,
+                    core,
+                    state,
+                    elr
+                );
+            }
+        }
+        "resume" | "continue" | "exit" => return Action::Resume,
+        "step" => {
+            step::arm(tf);
+            return Action::Resume;
+        }
+        "help" => {
+            kprintln!("regs [q]              print the registers, with q0..q31 if q");
+            kprintln!("reg <name> [value]    print or set x0..x30, lr, elr, spsr, sp or q0..q31");
+            kprintln!("hexdump <addr> [len]  dump len (64) bytes of memory");
+            kprintln!("disasm [addr] [n]     disassemble n (9) instructions, around elr by default");
+            kprintln!("ps                    list the processes");
+            kprintln!("step                  execute one instruction and return here");
+            kprintln!("resume                return to the trapped code");
+        }
+        _ => return Action::Unknown,
+    }
+    Action::Done
+}
+
+/// Parses a hex number prefixed with `0x`, or a decimal one.
+fn parse(s: &str) -> Option<u128> {
+    if s.starts_with("0x") {
+        u128::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+fn read_register(tf: &TrapFrame, name: &str) -> Option<u128> {
+    let value = match name {
+        "elr" => tf.elr,
+        "spsr" => tf.spsr,
+        "sp" => tf.stack_pointer(),
+        "lr" | "x30" => tf.lr,
+        _ if name.starts_with('x') => *tf.x.get(name[1..].parse::<usize>().ok()?)? as u64,
+        _ if name.starts_with('q') => return tf.q.get(name[1..].parse::<usize>().ok()?).cloned(),
+        _ => return None,
+    };
+    Some(value as u128)
+}
+
+/// Sets the register `name` of `tf` to `value`, truncated to its size.
+/// Returns `false` if there is no such register, or if it is the kernel's
+/// stack pointer, which cannot be changed.
+fn write_register(tf: &mut TrapFrame, name: &str, value: u128) -> bool {
+    let index = |prefix: char, len: usize| {
+        if !name.starts_with(prefix) {
+            return None;
+        }
+        name[1..].parse::<usize>().ok().filter(|&i| i < len)
+    };
+
+    match name {
+        "elr" => tf.elr = value as u64,
+        "spsr" => tf.spsr = value as u64,
+        "sp" if tf.is_user() => tf.sp = value as u64,
+        "lr" | "x30" => tf.lr = value as u64,
+        _ => match (index('x', tf.x.len()), index('q', tf.q.len())) {
+            (Some(i), _) => tf.x[i] = value as u64,
+            (_, Some(i)) => tf.q[i] = value,
+            _ => return false,
+        },
+    }
+    true
+}
+
+/// Prints `len` bytes at `addr`, 16 to a line, stopping at the first one
+/// that is not mapped. Ranges that wrap around the address space are
+/// rejected.
+fn hexdump(addr: usize, len: usize) {
+    let end = match addr.checked_add(len) {
+        Some(end) => end,
+        None => {
+            kprintln!("hexdump: {:#x} + {:#x} is out of range", addr, len);
+            return;
+        }
+    };
+
+    let mut line = [0u8; 16];
+    for start in (addr..end).step_by(16) {
+        let count = (end - start).min(16);
+        for i in 0..count {
+            match linear_alias(start + i) {
+                Some(alias) => line[i] = unsafe { *(alias as *const u8) },
+                None => {
+                    kprintln!("{:#018x}: not mapped", start + i);
+                    return;
+                }
+            }
+        }
+
+        kprint!("{:#018x}: ", start);
+        for i in 0..16 {
+            if i < count {
+                kprint!("{:02x} ", line[i]);
+            } else {
+                kprint!("   ");
+            }
+            if i == 7 {
+                kprint!(" ");
+            }
+        }
+        kprint!(" |");
+        for &byte in line[..count].iter() {
+            let c = if byte >= 0x20 && byte < 0x7f { byte as char } else { '.' };
+            kprint!("{}", c);
+        }
+        kprintln!("|");
+    }
+}
+
+/// Disassembles `count` instructions from `addr`, marking the one at `elr`
+/// and the start of each function the kernel's symbol table knows. Ranges
+/// that wrap around the address space are rejected.
+fn disasm(addr: usize, count: usize, elr: usize) {
+    let addr = addr & !0b11;
+    let end = match count.checked_mul(4).and_then(|len| addr.checked_add(len)) {
+        Some(end) => end,
+        None => {
+            kprintln!("disasm: {} instructions at {:#x} are out of range", count, addr);
+            return;
+        }
+    };
+
+    for pc in (addr..end).step_by(4) {
+        if let Some(symbol) = backtrace::lookup(pc).filter(|symbol| symbol.addr == pc) {
+            kprintln!("<{}>:", symbol.name);
+        }
+
+        let raw = match linear_alias(pc) {
+            Some(alias) => unsafe { *(alias as *const u32) },
+            None => {
+                kprintln!("{:#018x}: not mapped", pc);
+                return;
+            }
+        };
+        let mark = if pc == elr { "=>" } else { "  " };
+        let insn = Instruction { addr: pc as u64, raw };
+        kprintln!("{} {:#018x}: {:08x}  {}", mark, pc, raw, insn);
+    }
+}
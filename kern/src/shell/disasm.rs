@@ -0,0 +1,373 @@
+#[cfg(test)]
+mod tests;
+
+use core::fmt;
+
+/// An A64 instruction at an address, displayed as assembly.
+///
+/// The common integer, branch, load/store and system instructions are
+/// decoded, with their usual aliases (`mov`, `cmp`, `tst`, `ret`, ...).
+/// Anything else is shown as `.inst` and its encoding.
+#[derive(Debug, Copy, Clone)]
+pub struct Instruction {
+    pub addr: u64,
+    pub raw: u32,
+}
+
+const CONDITIONS: [&str; 16] = [
+    "eq", "ne", "hs", "lo", "mi", "pl", "vs", "vc", "hi", "ls", "ge", "lt", "gt", "le", "al", "nv",
+];
+
+const SHIFTS: [&str; 4] = ["lsl", "lsr", "asr", "ror"];
+
+/// Returns bits `hi..=lo` of `raw`.
+fn bits(raw: u32, hi: u32, lo: u32) -> u32 {
+    (raw >> lo) & ((1 << (hi - lo + 1)) - 1)
+}
+
+/// Sign-extends the `width` bit value `value`.
+fn sext(value: u32, width: u32) -> i64 {
+    let shift = 64 - width;
+    ((value as i64) << shift) >> shift
+}
+
+/// A general purpose register operand: 64-bit if `x`, with number 31 being
+/// the stack pointer if `sp` and the zero register otherwise.
+struct Reg {
+    n: u32,
+    x: bool,
+    sp: bool,
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.n, self.x, self.sp) {
+            (31, true, true) => write!(f, "sp"),
+            (31, false, true) => write!(f, "wsp"),
+            (31, true, false) => write!(f, "xzr"),
+            (31, false, false) => write!(f, "wzr"),
+            (n, true, _) => write!(f, "x{}", n),
+            (n, false, _) => write!(f, "w{}", n),
+        }
+    }
+}
+
+fn reg(n: u32, x: bool) -> Reg {
+    Reg { n, x, sp: false }
+}
+
+fn reg_sp(n: u32, x: bool) -> Reg {
+    Reg { n, x, sp: true }
+}
+
+/// Formats a signed immediate.
+struct Imm(i64);
+
+impl fmt::Display for Imm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 < 0 {
+            write!(f, "#-{:#x}", -self.0)
+        } else {
+            write!(f, "#{:#x}", self.0)
+        }
+    }
+}
+
+impl Instruction {
+    fn target(&self, offset: i64) -> u64 {
+        self.addr.wrapping_add(offset as u64)
+    }
+
+    fn system(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let raw = self.raw;
+        match raw {
+            0xd503201f => return write!(f, "nop"),
+            0xd503203f => return write!(f, "yield"),
+            0xd503205f => return write!(f, "wfe"),
+            0xd503207f => return write!(f, "wfi"),
+            0xd503209f => return write!(f, "sev"),
+            0xd50320bf => return write!(f, "sevl"),
+            0xd69f03e0 => return write!(f, "eret"),
+            0xd5033fdf => return write!(f, "isb"),
+            _ => {}
+        }
+
+        match raw & 0xfffff0ff {
+            0xd503309f => return write!(f, "dsb #{}", bits(raw, 11, 8)),
+            0xd50330bf => return write!(f, "dmb #{}", bits(raw, 11, 8)),
+            _ => {}
+        }
+
+        let name = match raw & 0xffe0001f {
+            0xd4000001 => Some("svc"),
+            0xd4000002 => Some("hvc"),
+            0xd4000003 => Some("smc"),
+            0xd4200000 => Some("brk"),
+            _ => None,
+        };
+        if let Some(name) = name {
+            return write!(f, "{} #{:#x}", name, bits(raw, 20, 5));
+        }
+
+        let sysreg = |f: &mut fmt::Formatter| {
+            write!(
+                f,
+                "s{}_{}_c{}_c{}_{}",
+                bits(raw, 19, 19) + 2,
+                bits(raw, 18, 16),
+                bits(raw, 15, 12),
+                bits(raw, 11, 8),
+                bits(raw, 7, 5)
+            )
+        };
+        match raw & 0xfff00000 {
+            0xd5300000 => {
+                write!(f, "mrs {}, ", reg(bits(raw, 4, 0), true))?;
+                sysreg(f)
+            }
+            0xd5100000 => {
+                write!(f, "msr ")?;
+                sysreg(f)?;
+                write!(f, ", {}", reg(bits(raw, 4, 0), true))
+            }
+            _ => write!(f, ".inst {:#010x}", raw),
+        }
+    }
+
+    fn branch(&self, f: &mut fmt::Formatter) -> Option<fmt::Result> {
+        let raw = self.raw;
+        let result = if raw & 0x7c000000 == 0x14000000 {
+            let name = if raw >> 31 == 1 { "bl" } else { "b" };
+            write!(f, "{} {:#x}", name, self.target(sext(bits(raw, 25, 0), 26) * 4))
+        } else if raw & 0xff000010 == 0x54000000 {
+            let target = self.target(sext(bits(raw, 23, 5), 19) * 4);
+            write!(f, "b.{} {:#x}", CONDITIONS[bits(raw, 3, 0) as usize], target)
+        } else if raw & 0x7e000000 == 0x34000000 {
+            let name = if bits(raw, 24, 24) == 1 { "cbnz" } else { "cbz" };
+            let rt = reg(bits(raw, 4, 0), raw >> 31 == 1);
+            let target = self.target(sext(bits(raw, 23, 5), 19) * 4);
+            write!(f, "{} {}, {:#x}", name, rt, target)
+        } else if raw & 0x7e000000 == 0x36000000 {
+            let name = if bits(raw, 24, 24) == 1 { "tbnz" } else { "tbz" };
+            let bit = (bits(raw, 31, 31) << 5) | bits(raw, 23, 19);
+            let rt = reg(bits(raw, 4, 0), bit >= 32);
+            let target = self.target(sext(bits(raw, 18, 5), 14) * 4);
+            write!(f, "{} {}, #{}, {:#x}", name, rt, bit, target)
+        } else if raw & 0xff9ffc1f == 0xd61f0000 {
+            let rn = bits(raw, 9, 5);
+            match bits(raw, 22, 21) {
+                0b00 => write!(f, "br {}", reg(rn, true)),
+                0b01 => write!(f, "blr {}", reg(rn, true)),
+                _ if rn == 30 => write!(f, "ret"),
+                _ => write!(f, "ret {}", reg(rn, true)),
+            }
+        } else {
+            return None;
+        };
+        Some(result)
+    }
+
+    fn data_processing(&self, f: &mut fmt::Formatter) -> Option<fmt::Result> {
+        let raw = self.raw;
+        let x = raw >> 31 == 1;
+        let (rd, rn, rm) = (bits(raw, 4, 0), bits(raw, 9, 5), bits(raw, 20, 16));
+
+        let result = if raw & 0x1f000000 == 0x10000000 {
+            let imm = sext((bits(raw, 23, 5) << 2) | bits(raw, 30, 29), 21);
+            if x {
+                let target = (self.addr & !0xfff).wrapping_add((imm << 12) as u64);
+                write!(f, "adrp {}, {:#x}", reg(rd, true), target)
+            } else {
+                write!(f, "adr {}, {:#x}", reg(rd, true), self.target(imm))
+            }
+        } else if raw & 0x1f000000 == 0x11000000 {
+            let (sub, flags) = (bits(raw, 30, 30) == 1, bits(raw, 29, 29) == 1);
+            let imm = (bits(raw, 21, 10) as i64) << (bits(raw, 22, 22) * 12);
+            if !sub && !flags && imm == 0 && (rd == 31 || rn == 31) {
+                write!(f, "mov {}, {}", reg_sp(rd, x), reg_sp(rn, x))
+            } else if flags && rd == 31 {
+                let name = if sub { "cmp" } else { "cmn" };
+                write!(f, "{} {}, {}", name, reg_sp(rn, x), Imm(imm))
+            } else {
+                let name = match (sub, flags) {
+                    (false, false) => "add",
+                    (false, true) => "adds",
+                    (true, false) => "sub",
+                    (true, true) => "subs",
+                };
+                let rd = if flags { reg(rd, x) } else { reg_sp(rd, x) };
+                write!(f, "{} {}, {}, {}", name, rd, reg_sp(rn, x), Imm(imm))
+            }
+        } else if raw & 0x1f800000 == 0x12800000 {
+            let shift = bits(raw, 22, 21) * 16;
+            let imm = bits(raw, 20, 5);
+            let name = match bits(raw, 30, 29) {
+                0b00 => "movn",
+                0b10 => "movz",
+                0b11 => "movk",
+                _ => return None,
+            };
+            write!(f, "{} {}, #{:#x}", name, reg(rd, x), imm)
+                .and_then(|_| if shift != 0 { write!(f, ", lsl #{}", shift) } else { Ok(()) })
+        } else if raw & 0x1f000000 == 0x0a000000 || raw & 0x1f200000 == 0x0b000000 {
+            let logical = raw & 0x1f000000 == 0x0a000000;
+            let opc = bits(raw, 30, 29);
+            let negate = logical && bits(raw, 21, 21) == 1;
+            let (shift, amount) = (bits(raw, 23, 22), bits(raw, 15, 10));
+
+            let (name, alias) = if logical {
+                match (opc, negate) {
+                    (0b00, false) => ("and", None),
+                    (0b00, true) => ("bic", None),
+                    (0b01, false) if rn == 31 && amount == 0 => ("orr", Some("mov")),
+                    (0b01, false) => ("orr", None),
+                    (0b01, true) if rn == 31 => ("orn", Some("mvn")),
+                    (0b01, true) => ("orn", None),
+                    (0b10, false) => ("eor", None),
+                    (0b10, true) => ("eon", None),
+                    (_, false) if rd == 31 => ("ands", Some("tst")),
+                    (_, false) => ("ands", None),
+                    (_, true) => ("bics", None),
+                }
+            } else {
+                match opc {
+                    0b00 => ("add", None),
+                    0b01 if rd == 31 => ("adds", Some("cmn")),
+                    0b01 => ("adds", None),
+                    0b10 if rn == 31 => ("sub", Some("neg")),
+                    0b10 => ("sub", None),
+                    _ if rd == 31 => ("subs", Some("cmp")),
+                    _ => ("subs", None),
+                }
+            };
+
+            let operands = match alias {
+                Some(alias @ "mov") | Some(alias @ "mvn") | Some(alias @ "neg") => {
+                    write!(f, "{} {}, {}", alias, reg(rd, x), reg(rm, x))
+                }
+                Some(alias) => write!(f, "{} {}, {}", alias, reg(rn, x), reg(rm, x)),
+                None => write!(f, "{} {}, {}, {}", name, reg(rd, x), reg(rn, x), reg(rm, x)),
+            };
+            operands.and_then(|_| {
+                if amount != 0 {
+                    write!(f, ", {} #{}", SHIFTS[shift as usize], amount)
+                } else {
+                    Ok(())
+                }
+            })
+        } else {
+            return None;
+        };
+        Some(result)
+    }
+
+    fn load_store(&self, f: &mut fmt::Formatter) -> Option<fmt::Result> {
+        let raw = self.raw;
+        let (rt, rn) = (bits(raw, 4, 0), bits(raw, 9, 5));
+        let simd = bits(raw, 26, 26) == 1;
+
+        // Pairs.
+        if raw & 0x3a000000 == 0x28000000 {
+            let load = bits(raw, 22, 22) == 1;
+            let opc = bits(raw, 31, 30);
+            let (name, scale) = match (opc, simd, load) {
+                (0b00, false, _) => (if load { "ldp" } else { "stp" }, 2),
+                (0b01, false, true) => ("ldpsw", 2),
+                (0b10, false, _) => (if load { "ldp" } else { "stp" }, 3),
+                (_, true, _) if opc < 3 => (if load { "ldp" } else { "stp" }, 2 + opc),
+                _ => return None,
+            };
+            let offset = sext(bits(raw, 21, 15), 7) << scale;
+            let imm = Imm(offset);
+            let rt2 = bits(raw, 14, 10);
+            let (first, second) = if simd {
+                let prefix = ["s", "d", "q"][opc as usize];
+                (
+                    alloc::format!("{}{}", prefix, rt),
+                    alloc::format!("{}{}", prefix, rt2),
+                )
+            } else {
+                let x = opc == 0b10 || name == "ldpsw";
+                (alloc::format!("{}", reg(rt, x)), alloc::format!("{}", reg(rt2, x)))
+            };
+            let base = reg_sp(rn, true);
+            let result = match bits(raw, 24, 23) {
+                0b01 => write!(f, "{} {}, {}, [{}], {}", name, first, second, base, imm),
+                0b11 => write!(f, "{} {}, {}, [{}, {}]!", name, first, second, base, imm),
+                _ if offset == 0 => write!(f, "{} {}, {}, [{}]", name, first, second, base),
+                _ => write!(f, "{} {}, {}, [{}, {}]", name, first, second, base, imm),
+            };
+            return Some(result);
+        }
+
+        // Loads of PC-relative literals.
+        if raw & 0x3b000000 == 0x18000000 && !simd {
+            let target = self.target(sext(bits(raw, 23, 5), 19) * 4);
+            let result = match bits(raw, 31, 30) {
+                0b00 => write!(f, "ldr {}, {:#x}", reg(rt, false), target),
+                0b01 => write!(f, "ldr {}, {:#x}", reg(rt, true), target),
+                0b10 => write!(f, "ldrsw {}, {:#x}", reg(rt, true), target),
+                _ => write!(f, "prfm {:#x}", target),
+            };
+            return Some(result);
+        }
+
+        // Single registers with an immediate offset.
+        let unsigned = raw & 0x3b000000 == 0x39000000;
+        let unscaled = raw & 0x3b200000 == 0x38000000;
+        if simd || !(unsigned || unscaled) {
+            return None;
+        }
+
+        let size = bits(raw, 31, 30);
+        let opc = bits(raw, 23, 22);
+        // The sign-extending loads are `ldrs*`, `ldurs*` and `ldtrs*`.
+        let (op, signed, x) = match (opc, size) {
+            (0b00, _) => ("st", "", size == 3),
+            (0b01, _) => ("ld", "", size == 3),
+            (0b10, 3) => return None,
+            (0b10, _) => ("ld", "s", true),
+            (_, 0) | (_, 1) => ("ld", "s", false),
+            _ => return None,
+        };
+        let suffix = match size {
+            0 => "b",
+            1 => "h",
+            2 if signed == "s" => "w",
+            _ => "",
+        };
+        let base = reg_sp(rn, true);
+        let rt = reg(rt, x);
+
+        let result = if unsigned {
+            match (bits(raw, 21, 10) as i64) << size {
+                0 => write!(f, "{}r{}{} {}, [{}]", op, signed, suffix, rt, base),
+                imm => write!(f, "{}r{}{} {}, [{}, {}]", op, signed, suffix, rt, base, Imm(imm)),
+            }
+        } else {
+            let imm = Imm(sext(bits(raw, 20, 12), 9));
+            match bits(raw, 11, 10) {
+                0b00 => write!(f, "{}ur{}{} {}, [{}, {}]", op, signed, suffix, rt, base, imm),
+                0b01 => write!(f, "{}r{}{} {}, [{}], {}", op, signed, suffix, rt, base, imm),
+                0b10 => write!(f, "{}tr{}{} {}, [{}, {}]", op, signed, suffix, rt, base, imm),
+                _ => write!(f, "{}r{}{} {}, [{}, {}]!", op, signed, suffix, rt, base, imm),
+            }
+        };
+        Some(result)
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(result) = self.branch(f) {
+            return result;
+        }
+        if let Some(result) = self.data_processing(f) {
+            return result;
+        }
+        if let Some(result) = self.load_store(f) {
+            return result;
+        }
+        self.system(f)
+    }
+}
@@ -0,0 +1,58 @@
+use super::Instruction;
+
+/// The address every instruction of `TABLE` is decoded at.
+const ADDR: u64 = 0x8_0000;
+
+/// Encodings, as an A64 assembler emits them, and their expected text.
+const TABLE: &[(u32, &str)] = &[
+    // Branch targets are relative to `ADDR`.
+    (0x14000010, "b 0x80040"),
+    (0x97fffffe, "bl 0x7fff8"),
+    (0x54000081, "b.ne 0x80010"),
+    (0xb4000101, "cbz x1, 0x80020"),
+    (0x372fffe3, "tbnz w3, #5, 0x7fffc"),
+    (0xd65f03c0, "ret"),
+    (0xd61f0200, "br x16"),
+    // `adrp` is relative to the page of `ADDR`.
+    (0xf0000000, "adrp x0, 0x83000"),
+    (0x10000081, "adr x1, 0x80010"),
+    (0x58000101, "ldr x1, 0x80020"),
+    // Aliases.
+    (0x910003fd, "mov x29, sp"),
+    (0xaa0103e0, "mov x0, x1"),
+    (0xf100405f, "cmp x2, #0x10"),
+    (0x6b02003f, "cmp w1, w2"),
+    (0xea01001f, "tst x0, x1"),
+    (0xcb0103e0, "neg x0, x1"),
+    (0x91008020, "add x0, x1, #0x20"),
+    (0xcb020c20, "sub x0, x1, x2, lsl #3"),
+    (0xd2a24680, "movz x0, #0x1234, lsl #16"),
+    // Pairs, pre- and post-indexed.
+    (0xa9bf7bfd, "stp x29, x30, [sp, #-0x10]!"),
+    (0xa8c17bfd, "ldp x29, x30, [sp], #0x10"),
+    (0xa90153f3, "stp x19, x20, [sp, #0x10]"),
+    // Single registers.
+    (0xf9400020, "ldr x0, [x1]"),
+    (0xb90007e0, "str w0, [sp, #0x4]"),
+    (0x385ffc62, "ldrb w2, [x3, #-0x1]!"),
+    (0xb9800820, "ldrsw x0, [x1, #0x8]"),
+    (0xb89fc020, "ldursw x0, [x1, #-0x4]"),
+    (0x39c00020, "ldrsb w0, [x1]"),
+    (0x78802462, "ldrsh x2, [x3], #0x2"),
+    // System instructions; system registers go by their encoding.
+    (0xd53800a0, "mrs x0, s3_0_c0_c0_5"),
+    (0xd518c001, "msr s3_0_c12_c0_0, x1"),
+    (0xd4000021, "svc #0x1"),
+    (0xd503201f, "nop"),
+    // Anything else.
+    (0x00000000, ".inst 0x00000000"),
+    (0x1e622820, ".inst 0x1e622820"),
+];
+
+#[test]
+fn decode() {
+    for &(raw, expected) in TABLE {
+        let text = format!("{}", Instruction { addr: ADDR, raw });
+        assert_eq!(text, expected, "decoding {:#010x}", raw);
+    }
+}
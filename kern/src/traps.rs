@@ -3,14 +3,25 @@ mod syndrome;
 mod syscall;
 
 pub mod irq;
+pub mod step;
 pub use self::frame::TrapFrame;
 
-use pi::interrupt::{Controller, Interrupt};
+use pi::generic_timer::current_time;
+use pi::interrupt;
 
-use self::syndrome::Syndrome;
+use aarch64::{affinity, FAR_EL1};
+
+use self::syndrome::{Fault, Syndrome};
 use self::syscall::handle_syscall;
+use crate::backtrace;
 use crate::console::kprintln;
+#[cfg(feature = "gdb")]
+use crate::gdb;
+use crate::process::State;
 use crate::shell;
+use crate::vm::{UserPageTable, VirtualAddr};
+use crate::{FIQ, IRQ, SCHEDULER};
+use kernel_api::OsError;
 
 #[repr(u16)]
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -43,31 +54,159 @@ pub struct Info {
 /// the trap frame for the exception.
 #[no_mangle]
 pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
-    kprintln!("kind: {:?}, src: {:?}, esr: {:?}", info.kind, info.source, esr);
     match info.kind {
         Kind::Synchronous => {
-            match Syndrome::from(esr) {
-                Syndrome::Brk(num) => {
-                    tf.elr = tf.elr+4;
-                    shell::shell("debug>");
-                    return
-                },
-                Syndrome::Svc(num) => {
-                    //handle_syscall(num, tf);
-                    kprintln!("svc handled");
-                    return
-                },
-                _ => {
-                    kprintln!("other sync");
-                    tf.elr = tf.elr+4;
-                    return
-                },
-            };
-        },
-        _ => {
-            kprintln!("other");
-            return
+            let syndrome = Syndrome::from(esr);
+            match syndrome {
+                #[cfg(feature = "gdb")]
+                Syndrome::Brk(_) => gdb::stop(gdb::Stop::Breakpoint, tf),
+                #[cfg(feature = "gdb")]
+                Syndrome::Step => gdb::stop(gdb::Stop::Step, tf),
+                #[cfg(not(feature = "gdb"))]
+                Syndrome::Brk(_) => {
+                    tf.elr += 4;
+                    shell::debug(tf);
+                }
+                #[cfg(not(feature = "gdb"))]
+                Syndrome::Step => {
+                    step::disarm(tf);
+                    shell::debug(tf);
+                }
+                Syndrome::Svc(num) if info.source == Source::LowerAArch64 => {
+                    handle_syscall(num, tf);
+                }
+                Syndrome::WfiWfe if info.source == Source::LowerAArch64 => {
+                    // A process waiting for an event gives up the rest of
+                    // its time slice instead.
+                    tf.elr += 4;
+                    SCHEDULER.switch(State::Ready, tf);
+                }
+                Syndrome::DataAbort { kind: Fault::Translation, .. }
+                | Syndrome::InstructionAbort { kind: Fault::Translation, .. }
+                | Syndrome::DataAbort { kind: Fault::Permission, .. }
+                | Syndrome::InstructionAbort { kind: Fault::Permission, .. }
+                | Syndrome::DataAbort { kind: Fault::AccessFlag, .. }
+                | Syndrome::InstructionAbort { kind: Fault::AccessFlag, .. }
+                    if info.source == Source::LowerAArch64 =>
+                {
+                    handle_user_fault(syndrome, tf);
+                }
+                Syndrome::Unknown
+                | Syndrome::WfiWfe
+                | Syndrome::SimdFp
+                | Syndrome::IllegalExecutionState
+                | Syndrome::Svc(_)
+                | Syndrome::Hvc(_)
+                | Syndrome::Smc(_)
+                | Syndrome::MsrMrsSystem
+                | Syndrome::InstructionAbort { .. }
+                | Syndrome::PCAlignmentFault
+                | Syndrome::DataAbort { .. }
+                | Syndrome::SpAlignmentFault
+                | Syndrome::TrappedFpu
+                | Syndrome::SError
+                | Syndrome::Breakpoint
+                | Syndrome::Step
+                | Syndrome::Watchpoint
+                | Syndrome::Other(_) => handle_fatal(info, syndrome, tf),
+            }
+        }
+        Kind::Irq => {
+            let taken = current_time();
+            for source in interrupt::pending(affinity()) {
+                IRQ.invoke(source, taken, tf);
+            }
         }
+        Kind::Fiq => {
+            let taken = current_time();
+            if let Some(int) = interrupt::Controller::new().fiq() {
+                FIQ.invoke(interrupt::Source::Gpu(int), taken, tf);
+            }
+        }
+        Kind::SError => handle_fatal(info, Syndrome::SError, tf),
     }
+}
 
+/// Handles an exception nothing can recover from: the process that caused
+/// it is killed, or, if the kernel caused it, the kernel panics.
+fn handle_fatal(info: Info, syndrome: Syndrome, tf: &mut TrapFrame) {
+    let far = if syndrome.has_far() {
+        Some(unsafe { FAR_EL1.get() })
+    } else {
+        None
+    };
+
+    match info.source {
+        Source::LowerAArch64 | Source::LowerAArch32 => {
+            match far {
+                Some(far) => kprintln!(
+                    "process {}: {} at {:#x} (elr: {:#x}), killed",
+                    tf.tpidr, syndrome, far, tf.elr
+                ),
+                None => kprintln!(
+                    "process {}: {} (elr: {:#x}), killed",
+                    tf.tpidr, syndrome, tf.elr
+                ),
+            }
+            kprintln!("{:#}", tf);
+            if SCHEDULER.kill(tf).is_some() {
+                SCHEDULER.switch_to(tf);
+            }
+        }
+        Source::CurrentSpEl0 | Source::CurrentSpElx => {
+            kprintln!("kernel {:?} exception: {}", info.kind, syndrome);
+            if let Some(far) = far {
+                kprintln!("faulting address: {:#x}", far);
+            }
+            kprintln!("kernel sp: {:#x}", tf.stack_pointer());
+            kprintln!("{:#}", tf);
+            backtrace::print_trap(tf);
+            panic!("kernel {:?} exception at {:#x}: {}", info.kind, tf.elr, syndrome);
+        }
+    }
+}
+
+/// Handles a translation, access flag or permission fault taken from user
+/// space.
+///
+/// A translation fault maps the faulting page on demand, or swaps it back
+/// in; an access flag fault marks the page accessed for the swap's clock; a
+/// write permission fault copies a page shared copy-on-write. Any other
+/// access the page's permission forbids is reported and the process is
+/// killed.
+fn handle_user_fault(syndrome: Syndrome, tf: &mut TrapFrame) {
+    let far = unsafe { FAR_EL1.get() };
+    let va = VirtualAddr::from(far);
+
+    // Only the lookup is done with the core's queue locked: resolving the
+    // fault may read from or write to the swap file.
+    let found = SCHEDULER.with_running(tf, |process| {
+        (&mut *process.vmap as *mut UserPageTable, process.region_perm(va))
+    });
+    let result = found.ok_or(OsError::Unknown).and_then(|(vmap, perm)| {
+        // The page table is boxed, so it stays put when its process moves in
+        // the queue, and only the process's own traps, which are taken on
+        // this core until it is scheduled out, change it.
+        let vmap = unsafe { &mut *vmap };
+        match syndrome {
+            Syndrome::DataAbort { kind: Fault::Permission, .. } => match perm {
+                Some(perm) if perm.is_writable() => vmap.handle_cow(va),
+                _ => Err(OsError::NoAccess),
+            },
+            Syndrome::InstructionAbort { kind: Fault::Permission, .. } => Err(OsError::NoAccess),
+            Syndrome::DataAbort { kind: Fault::AccessFlag, .. }
+            | Syndrome::InstructionAbort { kind: Fault::AccessFlag, .. } => vmap.mark_accessed(va),
+            _ => vmap.handle_fault(va, perm.ok_or(OsError::BadAddress)?),
+        }
+    });
+
+    if let Err(e) = result {
+        kprintln!(
+            "process {}: {:?} at {:#x} (elr: {:#x}): {:?}, killed",
+            tf.tpidr, syndrome, far, tf.elr, e
+        );
+        if SCHEDULER.kill(tf).is_some() {
+            SCHEDULER.switch_to(tf);
+        }
+    }
 }
@@ -1,4 +1,7 @@
 use core::fmt;
+use core::mem;
+
+use aarch64::SPSR_EL1;
 
 #[repr(C)]
 #[derive(Default, Copy, Clone, Debug)]
@@ -7,9 +10,58 @@ pub struct TrapFrame {
     pub spsr: u64,
     pub sp: u64, 
     pub tpidr: u64,
+    pub ttbr0: u64,
+    pub ttbr1: u64,
     pub q: [u128; 32],
     pub x: [u64; 30],
     pub lr: u64,
     pub xzr: u64,
 }
 
+
+impl TrapFrame {
+    /// Returns `true` if this is the trap frame of a user process, which
+    /// trapped from EL0.
+    pub fn is_user(&self) -> bool {
+        self.spsr & SPSR_EL1::M == 0
+    }
+
+    /// Returns the stack pointer of the code that trapped: `SP_EL0` for a
+    /// user process. The kernel's trap frames are pushed on the stack the
+    /// exception was taken on, whose pointer was right above them.
+    pub fn stack_pointer(&self) -> u64 {
+        if self.is_user() {
+            self.sp
+        } else {
+            self as *const TrapFrame as u64 + mem::size_of::<TrapFrame>() as u64
+        }
+    }
+}
+
+/// A dump of the general purpose and system registers, four to a line. The
+/// alternate form (`{:#}`) dumps q0..q31 too.
+impl fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "elr:   {:#018x}  spsr:  {:#018x}  sp:    {:#018x}  tpidr: {:#018x}",
+            self.elr, self.spsr, self.sp, self.tpidr
+        )?;
+        writeln!(f, "ttbr0: {:#018x}  ttbr1: {:#018x}", self.ttbr0, self.ttbr1)?;
+        for (i, x) in self.x.iter().enumerate() {
+            write!(f, "x{:<4} {:#018x}", i, x)?;
+            if i % 4 == 3 {
+                writeln!(f)?;
+            } else {
+                write!(f, "  ")?;
+            }
+        }
+        writeln!(f, "lr:   {:#018x}", self.lr)?;
+        if f.alternate() {
+            for (i, q) in self.q.iter().enumerate() {
+                writeln!(f, "q{:<4} {:#034x}", i, q)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,12 +1,90 @@
 use alloc::boxed::Box;
-use pi::interrupt::Interrupt;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::time::Duration;
+
+use aarch64::{affinity, cli, sti};
+use pi::generic_timer::current_time;
+use pi::interrupt::{Controller, Interrupt, Source};
+use pi::local_interrupt::{LocalController, LocalInterrupt};
 
 use crate::mutex::Mutex;
+use crate::param::NCORES;
 use crate::traps::TrapFrame;
+use crate::FIQ;
+
+pub type IrqHandler = Box<dyn Fn(&mut TrapFrame) + Send + Sync>;
+
+/// The priority of an irq handler. Handlers of priority `MASKED` run with
+/// IRQs masked; handlers of a higher priority run with IRQs unmasked and may
+/// be preempted by handlers of a higher priority still.
+pub type Priority = u8;
+
+/// The priority of handlers that are never preempted.
+pub const MASKED: Priority = 0;
+
+/// The statistics of the interrupts of one source.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct IrqStats {
+    /// The number of interrupts handled.
+    pub count: u64,
+    /// The number of those that preempted another handler.
+    pub nested: u64,
+    /// The total and the longest time from taking an interrupt to calling
+    /// its handler.
+    pub total_latency: Duration,
+    pub max_latency: Duration,
+    /// The longest time a handler ran, including the handlers that
+    /// preempted it.
+    pub max_time: Duration,
+}
+
+impl fmt::Display for IrqStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let average = match self.count {
+            0 => Duration::from_secs(0),
+            count => self.total_latency / count as u32,
+        };
+        write!(
+            f,
+            "{} handled ({} nested), latency {:?} average, {:?} max, longest handler {:?}",
+            self.count, self.nested, average, self.max_latency, self.max_time
+        )
+    }
+}
 
-pub type IrqHandler = Box<dyn FnMut(&mut TrapFrame) + Send>;
-pub type IrqHandlers = [Option<IrqHandler>; Interrupt::MAX];
+/// The handler of a source, and its statistics.
+struct Entry {
+    handler: Option<Arc<dyn Fn(&mut TrapFrame) + Send + Sync>>,
+    priority: Priority,
+    registered: bool,
+    stats: IrqStats,
+}
 
+pub struct IrqHandlers {
+    /// The entry of every source, by `Source::to_index()`.
+    entries: Vec<Entry>,
+    /// The number of handlers running on each core: more than one if they
+    /// nest.
+    depth: [usize; NCORES],
+    max_depth: usize,
+    /// The sources disabled at their controller while handlers of a higher
+    /// priority run, by `Source::to_index()`.
+    masked: u128,
+}
+
+/// A table of irq handlers, one per interrupt source.
+///
+/// Handlers registered with a priority above `MASKED` nest: while one runs,
+/// every source with a handler of the same or a lower priority is disabled
+/// at its interrupt controller and IRQs are unmasked, so that only sources
+/// of a higher priority preempt it. The trap frame of the preempted handler
+/// is saved on the kernel stack like any other. A nesting handler must thus
+/// not take locks that handlers of a higher priority take too.
+///
+/// Handlers run on whichever core takes their interrupt, possibly on several
+/// cores at once.
 pub struct Irq(Mutex<Option<IrqHandlers>>);
 
 impl Irq {
@@ -15,18 +93,179 @@ impl Irq {
     }
 
     pub fn initialize(&self) {
-        *self.0.lock() = Some([None, None, None, None, None, None, None, None]);
+        let entries = (0..Source::MAX)
+            .map(|_| Entry {
+                handler: None,
+                priority: MASKED,
+                registered: false,
+                stats: IrqStats::default(),
+            })
+            .collect();
+        *self.0.lock() = Some(IrqHandlers {
+            entries,
+            depth: [0; NCORES],
+            max_depth: 0,
+            masked: 0,
+        });
     }
 
-    /// Register an irq handler for an interrupt.
+    /// Register an irq handler for an interrupt: an `Interrupt` of the GPU
+    /// or a `LocalInterrupt` of the core. The handler runs with IRQs masked.
     /// The caller should assure that `initialize()` has been called before calling this function.
-    pub fn register(&self, int: Interrupt, handler: IrqHandler) {
-        unimplemented!("Irq::register()")
+    pub fn register<S: Into<Source>>(&self, source: S, handler: IrqHandler) {
+        self.register_with_priority(source, MASKED, handler)
+    }
+
+    /// Register an irq handler of `priority` for an interrupt. A handler of
+    /// a priority above `MASKED` may be preempted by the handlers of sources
+    /// of a higher priority.
+    ///
+    /// The source must stay enabled at its controller for as long as the
+    /// handler is registered, since it is enabled again after handlers of a
+    /// higher priority ran.
+    pub fn register_with_priority<S: Into<Source>>(
+        &self,
+        source: S,
+        priority: Priority,
+        handler: IrqHandler,
+    ) {
+        let mut handlers = self.0.lock();
+        let handlers = handlers.as_mut().expect("irq uninitialized");
+        let entry = &mut handlers.entries[Source::to_index(source.into())];
+        entry.handler = Some(Arc::from(handler));
+        entry.priority = priority;
+        entry.registered = true;
     }
 
-    /// Executes an irq handler for the givven interrupt.
+    /// Executes an irq handler for the givven interrupt, which was taken at
+    /// `taken`, as returned by `generic_timer::current_time()`.
     /// The caller should assure that `initialize()` has been called before calling this function.
-    pub fn invoke(&self, int: Interrupt, tf: &mut TrapFrame) {
-        unimplemented!("Irq::register()")
+    ///
+    /// Interrupts without a handler are ignored.
+    pub fn invoke(&self, source: Source, taken: Duration, tf: &mut TrapFrame) {
+        let index = Source::to_index(source);
+        let (handler, priority, masked, start) = {
+            let mut handlers = self.0.lock();
+            let handlers = handlers.as_mut().expect("irq uninitialized");
+            let core = affinity();
+            let depth = handlers.depth[core];
+            let entry = &mut handlers.entries[index];
+            let handler = match entry.handler.clone() {
+                Some(handler) => handler,
+                None => return,
+            };
+            let priority = entry.priority;
+
+            let start = current_time();
+            let latency = start.checked_sub(taken).unwrap_or_default();
+            entry.stats.count += 1;
+            if depth > 0 {
+                entry.stats.nested += 1;
+            }
+            entry.stats.total_latency += latency;
+            entry.stats.max_latency = entry.stats.max_latency.max(latency);
+
+            handlers.depth[core] += 1;
+            handlers.max_depth = handlers.max_depth.max(handlers.depth[core]);
+
+            let mut masked = 0;
+            if priority != MASKED {
+                for (i, entry) in handlers.entries.iter().enumerate() {
+                    if entry.registered && entry.priority <= priority {
+                        masked |= 1 << i;
+                    }
+                }
+                masked &= !handlers.masked;
+                handlers.masked |= masked;
+            }
+            (handler, priority, masked, start)
+        };
+
+        if priority != MASKED {
+            set_enabled(masked, false);
+            unsafe { sti() };
+        }
+        handler(tf);
+        if priority != MASKED {
+            unsafe { cli() };
+            set_enabled(masked, true);
+        }
+
+        let mut handlers = self.0.lock();
+        let handlers = handlers.as_mut().expect("irq uninitialized");
+        handlers.masked &= !masked;
+        handlers.depth[affinity()] -= 1;
+        let entry = &mut handlers.entries[index];
+        let time = current_time().checked_sub(start).unwrap_or_default();
+        entry.stats.max_time = entry.stats.max_time.max(time);
+    }
+
+    /// Returns the statistics of every source that was handled, and the
+    /// deepest the handlers nested.
+    pub fn stats(&self) -> (Vec<(Source, IrqStats)>, usize) {
+        let handlers = self.0.lock();
+        let handlers = handlers.as_ref().expect("irq uninitialized");
+        let stats = handlers
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.stats.count > 0)
+            .filter_map(|(i, entry)| Source::from_index(i).map(|source| (source, entry.stats)))
+            .collect();
+        (stats, handlers.max_depth)
     }
 }
+
+/// Enables or disables the sources in `sources`, by `Source::to_index()`,
+/// at their interrupt controller. Local sources other than the timers,
+/// mailboxes and performance monitors cannot be disabled and are left alone.
+fn set_enabled(sources: u128, enabled: bool) {
+    if sources == 0 {
+        return;
+    }
+
+    let mut controller = Controller::new();
+    let mut local = LocalController::new(affinity());
+    for i in (0..Source::MAX).filter(|&i| sources & (1 << i) != 0) {
+        match Source::from_index(i) {
+            Some(Source::Gpu(int)) if enabled => controller.enable(int),
+            Some(Source::Gpu(int)) => controller.disable(int),
+            Some(Source::Local(int)) => match int {
+                LocalInterrupt::CntPs
+                | LocalInterrupt::CntPns
+                | LocalInterrupt::CntHp
+                | LocalInterrupt::CntV if enabled => local.enable_timer(int),
+                LocalInterrupt::CntPs
+                | LocalInterrupt::CntPns
+                | LocalInterrupt::CntHp
+                | LocalInterrupt::CntV => local.disable_timer(int),
+                LocalInterrupt::Mailbox0
+                | LocalInterrupt::Mailbox1
+                | LocalInterrupt::Mailbox2
+                | LocalInterrupt::Mailbox3 => {
+                    let mailbox = int as usize - LocalInterrupt::Mailbox0 as usize;
+                    if enabled {
+                        local.enable_mailbox(mailbox)
+                    } else {
+                        local.disable_mailbox(mailbox)
+                    }
+                }
+                LocalInterrupt::Pmu if enabled => local.enable_pmu(),
+                LocalInterrupt::Pmu => local.disable_pmu(),
+                _ => {}
+            },
+            None => {}
+        }
+    }
+}
+
+/// Routes the interrupt `int` to FIQ instead of IRQ, and registers `handler`
+/// for it in `FIQ`, the handler table of FIQs. Only one interrupt can be
+/// routed to FIQ: the one routed there before is disabled.
+///
+/// FIQs are taken from user space only, like IRQs, but are dispatched
+/// without looking up the pending interrupts first.
+pub fn route_to_fiq(int: Interrupt, handler: IrqHandler) {
+    FIQ.register(int, handler);
+    Controller::new().enable_fiq(int);
+}
@@ -0,0 +1,50 @@
+use aarch64::*;
+
+use crate::mutex::Mutex;
+use crate::traps::TrapFrame;
+
+/// The `SPSR` bits a single step changes: `D`, `I` and `F`.
+const MASK: u64 = SPSR_EL1::D | SPSR_EL1::I | SPSR_EL1::F;
+
+/// The `MASK` bits of the stepped code's `SPSR` while a single step is in
+/// progress.
+static SAVED: Mutex<Option<u64>> = Mutex::new(None);
+
+/// Arms a single step of the code of `tf`: once `tf` is restored, one
+/// instruction executes and a `Syndrome::Step` exception is taken.
+/// Interrupts are masked for the step, so that it steps the instruction at
+/// `tf.elr` and not a handler.
+pub fn arm(tf: &mut TrapFrame) {
+    let mut mdscr = MDSCR_EL1::SS;
+    *SAVED.lock() = Some(tf.spsr & MASK);
+    if !tf.is_user() {
+        // Software step of EL1 code needs kernel debug enabled and debug
+        // exceptions unmasked.
+        mdscr |= MDSCR_EL1::KDE;
+        tf.spsr &= !SPSR_EL1::D;
+    }
+    tf.spsr |= SPSR_EL1::SS | SPSR_EL1::I | SPSR_EL1::F;
+
+    unsafe {
+        // The OS lock is set on reset and disables software step.
+        OSLAR_EL1.set(0);
+        MDSCR_EL1.set(MDSCR_EL1.get() | mdscr);
+        isb();
+    }
+}
+
+/// Disarms the single step of the code of `tf`, which completed, restoring
+/// the interrupt masks it ran with. Returns `false` if no step was armed.
+pub fn disarm(tf: &mut TrapFrame) -> bool {
+    let saved = match SAVED.lock().take() {
+        Some(saved) => saved,
+        None => return false,
+    };
+
+    unsafe {
+        MDSCR_EL1.set(MDSCR_EL1.get() & !(MDSCR_EL1::SS | MDSCR_EL1::KDE));
+        isb();
+    }
+    tf.spsr = (tf.spsr & !MASK) | saved;
+    true
+}
@@ -1,5 +1,8 @@
+use core::fmt;
+
 use aarch64::ESR_EL1;
 
+/// The fault status of an instruction or data abort (ref: D13.2.37).
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum Fault {
     AddressSize,
@@ -8,9 +11,49 @@ pub enum Fault {
     Permission,
     Alignment,
     TlbConflict,
+    /// A synchronous external abort, not on a translation table walk.
+    SyncExternal,
+    /// A synchronous external abort on a translation table walk.
+    SyncExternalOnWalk,
+    /// A parity or ECC error, not on a translation table walk.
+    SyncParity,
+    /// A parity or ECC error on a translation table walk.
+    SyncParityOnWalk,
     Other(u8),
 }
 
+impl Fault {
+    /// Returns `true` if the abort's `level` is the translation table level
+    /// the fault occurred at.
+    pub fn has_level(&self) -> bool {
+        use self::Fault::*;
+        match *self {
+            AddressSize | Translation | AccessFlag | Permission | SyncExternalOnWalk
+            | SyncParityOnWalk => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Fault::*;
+        match *self {
+            AddressSize => write!(f, "address size fault"),
+            Translation => write!(f, "translation fault"),
+            AccessFlag => write!(f, "access flag fault"),
+            Permission => write!(f, "permission fault"),
+            Alignment => write!(f, "alignment fault"),
+            TlbConflict => write!(f, "TLB conflict abort"),
+            SyncExternal => write!(f, "synchronous external abort"),
+            SyncExternalOnWalk => write!(f, "synchronous external abort on table walk"),
+            SyncParity => write!(f, "parity or ECC error"),
+            SyncParityOnWalk => write!(f, "parity or ECC error on table walk"),
+            Other(status) => write!(f, "fault with status {:#08b}", status),
+        }
+    }
+}
+
 impl From<u32> for Fault {
     fn from(val: u32) -> Fault {
         use self::Fault::*;
@@ -22,8 +65,12 @@ impl From<u32> for Fault {
             0b000100 => Translation,
             0b001000 => AccessFlag,
             0b001100 => Permission,
-            0b100000 => Alignment,
-            0b110000 => TlbConflict,
+            0b010000 if val & 0b11 == 0 => SyncExternal,
+            0b010100 => SyncExternalOnWalk,
+            0b011000 if val & 0b11 == 0 => SyncParity,
+            0b011100 => SyncParityOnWalk,
+            0b100000 if val & 0b11 == 0b01 => Alignment,
+            0b110000 if val & 0b11 == 0 => TlbConflict,
             _ => Other((val & 0b111111) as u8),
         }
     }
@@ -41,7 +88,8 @@ pub enum Syndrome {
     MsrMrsSystem,
     InstructionAbort { kind: Fault, level: u8 },
     PCAlignmentFault,
-    DataAbort { kind: Fault, level: u8 },
+    /// `write` is `true` if the abort was caused by a write.
+    DataAbort { kind: Fault, level: u8, write: bool },
     SpAlignmentFault,
     TrappedFpu,
     SError,
@@ -79,10 +127,12 @@ impl From<u32> for Syndrome {
             0b100100 => DataAbort {
                 kind: iss.into(),
                 level: (iss & 0b11) as u8,
+                write: iss & (1 << 6) != 0,
             },
             0b100101 => DataAbort {
                 kind: iss.into(),
                 level: (iss & 0b11) as u8,
+                write: iss & (1 << 6) != 0,
             },
             0b100110 => SpAlignmentFault, 
             0b101100 => TrappedFpu,
@@ -99,3 +149,52 @@ impl From<u32> for Syndrome {
         }
     }
 }
+
+impl Syndrome {
+    /// Returns `true` if `FAR_EL1` holds the faulting address of an exception
+    /// with this syndrome.
+    pub fn has_far(&self) -> bool {
+        use self::Syndrome::*;
+        match *self {
+            InstructionAbort { .. } | DataAbort { .. } | PCAlignmentFault | Watchpoint => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Syndrome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Syndrome::*;
+        match *self {
+            Unknown => write!(f, "undefined instruction"),
+            WfiWfe => write!(f, "trapped WFI or WFE"),
+            SimdFp => write!(f, "trapped SIMD or floating-point access"),
+            IllegalExecutionState => write!(f, "illegal execution state"),
+            Svc(num) => write!(f, "svc #{}", num),
+            Hvc(num) => write!(f, "hvc #{}", num),
+            Smc(num) => write!(f, "smc #{}", num),
+            MsrMrsSystem => write!(f, "trapped MSR, MRS or system instruction"),
+            InstructionAbort { kind, level } if kind.has_level() => {
+                write!(f, "instruction abort: {} at level {}", kind, level)
+            }
+            InstructionAbort { kind, .. } => write!(f, "instruction abort: {}", kind),
+            PCAlignmentFault => write!(f, "PC alignment fault"),
+            DataAbort { kind, level, write } => {
+                let access = if write { "write" } else { "read" };
+                if kind.has_level() {
+                    write!(f, "data abort on {}: {} at level {}", access, kind, level)
+                } else {
+                    write!(f, "data abort on {}: {}", access, kind)
+                }
+            }
+            SpAlignmentFault => write!(f, "SP alignment fault"),
+            TrappedFpu => write!(f, "floating-point exception"),
+            SError => write!(f, "SError"),
+            Breakpoint => write!(f, "hardware breakpoint"),
+            Step => write!(f, "software step"),
+            Watchpoint => write!(f, "watchpoint"),
+            Brk(num) => write!(f, "brk #{}", num),
+            Other(ec) => write!(f, "exception class {:#08b}", ec),
+        }
+    }
+}
@@ -1,9 +1,19 @@
 use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::mem::{self, MaybeUninit};
+use core::slice;
 use core::time::Duration;
 
-use crate::console::CONSOLE;
-use crate::process::State;
+use shim::io;
+use shim::path::PathBuf;
+
+use crate::console::{self, CONSOLE};
+use crate::param::{PAGE_MASK, PAGE_SIZE};
+use crate::process::{Process, State};
 use crate::traps::TrapFrame;
+use crate::vm::{PagePerm, PhysicalAddr, VirtualAddr, PAGE_REFS};
 use crate::SCHEDULER;
 use kernel_api::*;
 
@@ -37,13 +47,90 @@ pub fn sys_exit(tf: &mut TrapFrame) {
     unimplemented!("sys_exit()");
 }
 
-/// Write to console.
+/// Write to a file descriptor.
+///
+/// This system call takes three parameters: the file descriptor, the user
+/// virtual address of the buffer to write, and the length of the buffer in
+/// bytes. Only `STDOUT` and `STDERR` are supported; both write to the console.
 ///
-/// This system call takes one parameter: a u8 character to print.
+/// Returns `OsError::BadAddress` if any part of the buffer is not mapped and
+/// readable from user space.
 ///
-/// It only returns the usual status value.
-pub fn sys_write(b: u8, tf: &mut TrapFrame) {
-    unimplemented!("sys_write()");
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes written.
+pub fn sys_write(fd: u64, va: u64, len: u64, tf: &mut TrapFrame) {
+    let result = write_console(fd, va, len, tf);
+    set_result(tf, result);
+}
+
+fn write_console(fd: u64, va: u64, len: u64, tf: &TrapFrame) -> OsResult<u64> {
+    if fd != STDOUT && fd != STDERR {
+        return Err(OsError::InvalidArgument);
+    }
+
+    let user_buf = UserSlice::new(va, len)?;
+    user_buf.check(tf, false)?;
+
+    let mut buf = [0u8; 512];
+    let mut written = 0;
+    while written < user_buf.len() {
+        let size = core::cmp::min(buf.len(), user_buf.len() - written);
+        user_buf
+            .subslice(written, size)
+            .copy_from_user(tf, &mut buf[..size])?;
+
+        let mut console = CONSOLE.lock();
+        for &byte in buf[..size].iter() {
+            console.write_byte(byte);
+        }
+        written += size;
+    }
+
+    Ok(written as u64)
+}
+
+/// Read from a file descriptor.
+///
+/// This system call takes three parameters: the file descriptor, the user
+/// virtual address of the buffer to read into, and the length of the buffer
+/// in bytes. Only `STDIN` is supported; it blocks until at least one byte is
+/// available on the console and then reads as many bytes as are ready.
+///
+/// Returns `OsError::BadAddress` if any part of the buffer is not mapped and
+/// writable from user space.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes read.
+pub fn sys_read(fd: u64, va: u64, len: u64, tf: &mut TrapFrame) {
+    let result = read_console(fd, va, len, tf);
+    set_result(tf, result);
+}
+
+fn read_console(fd: u64, va: u64, len: u64, tf: &TrapFrame) -> OsResult<u64> {
+    if fd != STDIN {
+        return Err(OsError::InvalidArgument);
+    }
+
+    // Reject bad buffers before blocking on the console.
+    let user_buf = UserSlice::new(va, len)?;
+    user_buf.check(tf, true)?;
+    if user_buf.len() == 0 {
+        return Ok(0);
+    }
+
+    // Only hold the console once there is input, or a core printing would
+    // wait for as long as this process waits for a key.
+    let mut buf = alloc::vec![0u8; user_buf.len()];
+    let read = loop {
+        console::wait_for_byte();
+        let mut console = CONSOLE.lock();
+        if console.has_byte() {
+            break io::Read::read(&mut *console, &mut buf)?;
+        }
+    };
+    user_buf.subslice(0, read).copy_to_user(tf, &buf[..read])?;
+
+    Ok(read as u64)
 }
 
 /// Returns current process's ID.
@@ -56,7 +143,370 @@ pub fn sys_getpid(tf: &mut TrapFrame) {
     unimplemented!("sys_getpid()");
 }
 
+/// Creates a copy of the current process.
+///
+/// This system call does not take parameter. The child process resumes at the
+/// same point as its parent and shares its memory copy-on-write.
+///
+/// In addition to the usual status value, this system call returns a
+/// parameter: the child's ID in the parent, and `0` in the child. Returns
+/// `OsError::NoMemory` if the child could not be created.
+pub fn sys_fork(tf: &mut TrapFrame) {
+    let result = with_process(tf, |process| process.fork(tf))
+        .and_then(|child| SCHEDULER.add(child).ok_or(OsError::Unknown));
+    set_result(tf, result);
+}
+
+/// Sets the program break.
+///
+/// This system call takes one parameter: the new program break, or `0` to
+/// query the current one. Pages the heap no longer covers are unmapped.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the program break after the call. Returns `OsError::NoVmSpace`
+/// if the heap could not be moved there.
+pub fn sys_brk(addr: u64, tf: &mut TrapFrame) {
+    let result = with_process(tf, |process| process.set_brk(VirtualAddr::from(addr)));
+    set_result(tf, result.map(|brk| brk.as_u64()));
+}
+
+/// Maps anonymous, zero-filled memory.
+///
+/// This system call takes three parameters: the preferred address of the
+/// mapping or `0` for none, its length in bytes, and its protection, a
+/// combination of `PROT_READ`, `PROT_WRITE` and `PROT_EXEC`. Pages are mapped
+/// on first access.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the address of the mapping. Returns `OsError::InvalidArgument`
+/// if `len` is zero or `prot` is not supported, and `OsError::NoVmSpace` if
+/// there is no room for the mapping.
+pub fn sys_mmap(addr: u64, len: u64, prot: u64, tf: &mut TrapFrame) {
+    let result = prot_to_perm(prot).and_then(|perm| {
+        with_process(tf, |process| process.mmap(VirtualAddr::from(addr), len as usize, perm))
+    });
+    set_result(tf, result.map(|va| va.as_u64()));
+}
+
+/// Unmaps memory mapped by `mmap`.
+///
+/// This system call takes two parameters: the page-aligned start of the range
+/// to unmap and its length in bytes. Mappings only partly covered by the range
+/// are split.
+///
+/// Returns `OsError::InvalidArgument` if the range is not page aligned or
+/// reaches outside the area `mmap` allocates from.
+pub fn sys_munmap(addr: u64, len: u64, tf: &mut TrapFrame) {
+    let result = with_process(tf, |process| process.munmap(VirtualAddr::from(addr), len as usize));
+    set_result(tf, result.map(|_| 0));
+}
+
+/// Changes the protection of memory.
+///
+/// This system call takes three parameters: the page-aligned start of the
+/// range, its length in bytes, and the new protection, a combination of
+/// `PROT_READ`, `PROT_WRITE` and `PROT_EXEC`. Pages shared with another
+/// process after a fork become copy-on-write when made writable, and keep
+/// being copy-on-write if they were.
+///
+/// Returns `OsError::InvalidArgument` if the range is not page aligned or
+/// `prot` is not supported, and `OsError::BadAddress` if any page in the
+/// range is not part of the process's memory.
+pub fn sys_mprotect(addr: u64, len: u64, prot: u64, tf: &mut TrapFrame) {
+    let result = prot_to_perm(prot).and_then(|perm| {
+        with_process(tf, |process| process.mprotect(VirtualAddr::from(addr), len as usize, perm))
+    });
+    set_result(tf, result.map(|_| 0));
+}
+
+/// Returns the page permission for the protection flags `prot`. Every page
+/// is readable, so `PROT_READ` is required.
+fn prot_to_perm(prot: u64) -> OsResult<PagePerm> {
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 || prot & PROT_READ == 0 {
+        return Err(OsError::InvalidArgument);
+    }
+
+    Ok(match (prot & PROT_WRITE != 0, prot & PROT_EXEC != 0) {
+        (true, true) => PagePerm::RWX,
+        (true, false) => PagePerm::RW,
+        (false, true) => PagePerm::RX,
+        (false, false) => PagePerm::RO,
+    })
+}
+
+/// The maximum length in bytes of a path passed to a system call, excluding
+/// the terminating NUL.
+pub const MAX_PATH_LEN: usize = 512;
+
+/// Returns `true` if `[addr, addr + len)` lies within the user address space,
+/// `USER_IMG_BASE..=Process::get_max_va()`.
+fn is_user_range(addr: usize, len: usize) -> bool {
+    let base = Process::get_image_base().as_usize();
+    let max = Process::get_max_va().as_usize();
+    match addr.checked_add(len.saturating_sub(1)) {
+        Some(last) => addr >= base && last <= max,
+        None => false,
+    }
+}
+
+/// Runs `f` with the process that owns `tf`.
+fn with_process<F, R>(tf: &TrapFrame, f: F) -> OsResult<R>
+where
+    F: FnOnce(&mut Process) -> OsResult<R>,
+{
+    SCHEDULER
+        .with_running(tf, f)
+        .unwrap_or(Err(OsError::Unknown))
+}
+
+/// A range of bytes in the address space of the calling process.
+///
+/// A `UserSlice` is only ever accessed through the page table of its process:
+/// every page is translated and permission-checked before the kernel touches
+/// it through its physical address. Pages that are not mapped yet but lie in
+/// one of the process's regions are mapped on demand, exactly as if user
+/// space had touched them. Any other unmapped or inaccessible page is
+/// reported as `OsError::BadAddress` instead of faulting in the kernel.
+#[derive(Debug, Copy, Clone)]
+pub struct UserSlice {
+    addr: usize,
+    len: usize,
+}
+
+impl UserSlice {
+    /// Returns a `UserSlice` spanning `len` bytes from the user virtual
+    /// address `addr`.
+    ///
+    /// Returns `OsError::BadAddress` if the range is not entirely within the
+    /// user address space. The pages themselves are checked on access.
+    pub fn new(addr: u64, len: u64) -> OsResult<UserSlice> {
+        let (addr, len) = (addr as usize, len as usize);
+        if !is_user_range(addr, len) {
+            return Err(OsError::BadAddress);
+        }
+        Ok(UserSlice { addr, len })
+    }
+
+    /// Returns the length of the slice in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the `len` bytes of this slice starting at `offset`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the requested range exceeds this slice.
+    pub fn subslice(&self, offset: usize, len: usize) -> UserSlice {
+        assert!(offset.checked_add(len).map_or(false, |end| end <= self.len));
+        UserSlice {
+            addr: self.addr + offset,
+            len,
+        }
+    }
+
+    /// Checks that every page of this slice is mapped and accessible from user
+    /// space in the process that owns `tf`, and writable if `write` is `true`.
+    pub fn check(&self, tf: &TrapFrame, write: bool) -> OsResult<()> {
+        self.for_each_piece(tf, write, |_| ())
+    }
+
+    /// Copies this slice from user space into `dst`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `dst` is not exactly `self.len()` bytes long.
+    pub fn copy_from_user(&self, tf: &TrapFrame, dst: &mut [u8]) -> OsResult<()> {
+        assert_eq!(dst.len(), self.len);
+        let mut copied = 0;
+        self.for_each_piece(tf, false, |piece| {
+            dst[copied..copied + piece.len()].copy_from_slice(piece);
+            copied += piece.len();
+        })
+    }
+
+    /// Copies `src` into this slice in user space.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `src` is not exactly `self.len()` bytes long.
+    pub fn copy_to_user(&self, tf: &TrapFrame, src: &[u8]) -> OsResult<()> {
+        assert_eq!(src.len(), self.len);
+        let mut copied = 0;
+        self.for_each_piece(tf, true, |piece| {
+            piece.copy_from_slice(&src[copied..copied + piece.len()]);
+            copied += piece.len();
+        })
+    }
+
+    /// Calls `f` on each physically contiguous piece of this slice, in order.
+    ///
+    /// Every page is translated before `f` is called on any piece, so `f` is
+    /// never called if any page is inaccessible. Translated pages hold an
+    /// extra reference until `f` is done, so that translating a later page
+    /// cannot swap them out.
+    fn for_each_piece<F>(&self, tf: &TrapFrame, write: bool, mut f: F) -> OsResult<()>
+    where
+        F: FnMut(&mut [u8]),
+    {
+        with_process(tf, |process| {
+            // Track the remaining length rather than an end address: a slice
+            // may end at the very top of the address space.
+            let mut pieces: Vec<(PhysicalAddr, usize)> = Vec::new();
+            let mut addr = self.addr;
+            let mut remaining = self.len;
+            let mut result = Ok(());
+            while remaining > 0 {
+                let size = core::cmp::min(remaining, PAGE_SIZE - (addr & !PAGE_MASK));
+                match process.resolve(VirtualAddr::from(addr), write) {
+                    Some(pa) => {
+                        PAGE_REFS.inc(pa);
+                        pieces.push((pa, size));
+                    }
+                    None => {
+                        result = Err(OsError::BadAddress);
+                        break;
+                    }
+                }
+                addr = addr.wrapping_add(size);
+                remaining -= size;
+            }
+
+            for &(pa, size) in pieces.iter() {
+                if result.is_err() {
+                    break;
+                }
+                // The kernel maps all of physical memory, so `pa` is
+                // addressable from here.
+                let ptr = pa.to_virtual().as_usize() as *mut u8;
+                let piece = unsafe { slice::from_raw_parts_mut(ptr, size) };
+                f(piece);
+            }
+
+            for &(pa, _) in pieces.iter() {
+                PAGE_REFS.dec(pa);
+            }
+            result
+        })
+    }
+}
+
+/// A pointer to a `T` in the address space of the calling process.
+///
+/// `T` is copied in and out byte by byte, so it should be a plain-old-data
+/// type for which every bit pattern is valid.
+pub struct UserPtr<T> {
+    addr: usize,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T: Copy> UserPtr<T> {
+    /// Returns a `UserPtr` to the user virtual address `addr`.
+    ///
+    /// Returns `OsError::BadAddress` if `addr` is not aligned for `T` or the
+    /// pointee is not entirely within the user address space.
+    pub fn new(addr: u64) -> OsResult<UserPtr<T>> {
+        let addr = addr as usize;
+        if addr % mem::align_of::<T>() != 0 || !is_user_range(addr, mem::size_of::<T>()) {
+            return Err(OsError::BadAddress);
+        }
+        Ok(UserPtr {
+            addr,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Reads the pointee from user space.
+    pub fn read(&self, tf: &TrapFrame) -> OsResult<T> {
+        let mut val = MaybeUninit::<T>::uninit();
+        let bytes = unsafe {
+            slice::from_raw_parts_mut(val.as_mut_ptr() as *mut u8, mem::size_of::<T>())
+        };
+        self.as_slice().copy_from_user(tf, bytes)?;
+        Ok(unsafe { val.assume_init() })
+    }
+
+    /// Writes `val` to the pointee in user space.
+    pub fn write(&self, tf: &TrapFrame, val: T) -> OsResult<()> {
+        let bytes = unsafe {
+            slice::from_raw_parts(&val as *const T as *const u8, mem::size_of::<T>())
+        };
+        self.as_slice().copy_to_user(tf, bytes)
+    }
+
+    fn as_slice(&self) -> UserSlice {
+        UserSlice {
+            addr: self.addr,
+            len: mem::size_of::<T>(),
+        }
+    }
+}
+
+/// Reads a NUL-terminated UTF-8 string from the user virtual address `addr`.
+///
+/// Returns `OsError::BadAddress` if the string runs into an inaccessible page
+/// before its NUL, and `OsError::InvalidArgument` if it is longer than
+/// `max_len` bytes or is not valid UTF-8.
+pub fn read_user_str(tf: &TrapFrame, addr: u64, max_len: usize) -> OsResult<String> {
+    let mut bytes = Vec::new();
+    let mut addr = addr as usize;
+    loop {
+        // Never read across a page boundary at once: the next page may not be
+        // mapped even though the string ends before it.
+        let remaining = max_len + 1 - bytes.len();
+        let size = core::cmp::min(PAGE_SIZE - (addr & !PAGE_MASK), remaining);
+        let mut buf = alloc::vec![0u8; size];
+        UserSlice::new(addr as u64, size as u64)?.copy_from_user(tf, &mut buf)?;
+
+        if let Some(nul) = buf.iter().position(|&byte| byte == 0) {
+            bytes.extend_from_slice(&buf[..nul]);
+            return String::from_utf8(bytes).map_err(|_| OsError::InvalidArgument);
+        }
+
+        bytes.extend_from_slice(&buf);
+        if bytes.len() > max_len {
+            return Err(OsError::InvalidArgument);
+        }
+        addr = addr.checked_add(size).ok_or(OsError::BadAddress)?;
+    }
+}
+
+/// Reads a NUL-terminated path of at most `MAX_PATH_LEN` bytes from the user
+/// virtual address `addr`.
+pub fn read_user_path(tf: &TrapFrame, addr: u64) -> OsResult<PathBuf> {
+    read_user_str(tf, addr, MAX_PATH_LEN).map(PathBuf::from)
+}
+
+/// Stores the outcome of a system call into `tf`: the returned value in `x0`
+/// and the status value in `x7`.
+fn set_result(tf: &mut TrapFrame, result: OsResult<u64>) {
+    match result {
+        Ok(value) => {
+            tf.x[0] = value;
+            tf.x[7] = OsError::Ok as u64;
+        }
+        Err(e) => tf.x[7] = e as u64,
+    }
+}
+
 pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
     use crate::console::kprintln;
-    unimplemented!("handle_syscall()")
+
+    match num as usize {
+        NR_SLEEP => sys_sleep(tf.x[0] as u32, tf),
+        NR_TIME => sys_time(tf),
+        NR_EXIT => sys_exit(tf),
+        NR_WRITE => sys_write(tf.x[0], tf.x[1], tf.x[2], tf),
+        NR_GETPID => sys_getpid(tf),
+        NR_READ => sys_read(tf.x[0], tf.x[1], tf.x[2], tf),
+        NR_FORK => sys_fork(tf),
+        NR_BRK => sys_brk(tf.x[0], tf),
+        NR_MMAP => sys_mmap(tf.x[0], tf.x[1], tf.x[2], tf),
+        NR_MUNMAP => sys_munmap(tf.x[0], tf.x[1], tf),
+        NR_MPROTECT => sys_mprotect(tf.x[0], tf.x[1], tf.x[2], tf),
+        _ => {
+            kprintln!("unknown syscall: {}", num);
+            tf.x[7] = OsError::Unknown as u64;
+        }
+    }
 }
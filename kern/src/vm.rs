@@ -1,17 +1,110 @@
 use crate::console::kprintln;
-use crate::mutex::Mutex;
+use crate::mutex::RwLock;
 
 use aarch64::*;
 
 mod address;
+mod asid;
+mod frame;
 mod pagetable;
+mod refcount;
+mod region;
+mod swap;
 
 pub use self::address::{PhysicalAddr, VirtualAddr};
+pub use self::asid::{Asids, KERNEL_ASID};
+pub use self::frame::{FrameAllocator, FrameStats};
 pub use self::pagetable::*;
-use crate::param::{KERNEL_MASK_BITS, USER_MASK_BITS};
+pub use self::refcount::PageRefs;
+pub use self::region::Region;
+pub use self::swap::{SwapManager, SwapStats};
+use crate::param::{KERNEL_BASE, KERNEL_MASK_BITS, USER_MASK_BITS};
 
-/// Thread-safe (locking) wrapper around a kernel page table.
-pub struct VMManager(Mutex<Option<KernPageTable>>);
+/// `TCR_EL1.TG0` and `TCR_EL1.TG1` for the configured granule. The two fields
+/// encode granule sizes differently.
+#[cfg(not(feature = "granule-4k"))]
+const TCR_TG0: u64 = 0b01;
+#[cfg(not(feature = "granule-4k"))]
+const TCR_TG1: u64 = 0b11;
+#[cfg(feature = "granule-4k")]
+const TCR_TG0: u64 = 0b00;
+#[cfg(feature = "granule-4k")]
+const TCR_TG1: u64 = 0b10;
+
+/// The value of `MAIR_EL1`, indexed by `EntryAttr`.
+// (ref. D7.2.70: Memory Attribute Indirection Register)
+pub(crate) const MAIR: u64 =
+    (0xFF <<  0) |// AttrIdx=0: normal, IWBWA, OWBWA, NTR
+    (0x04 <<  8) |// AttrIdx=1: device, nGnRE (must be OSH too)
+    (0x44 << 16); // AttrIdx=2: non cacheable
+
+/// Returns the address at which the kernel's linear map of physical memory
+/// accesses the memory `va` is mapped to by the current translation tables,
+/// or `None` if `va` is not mapped for reading.
+///
+/// Writes through the returned address ignore the permissions of `va`'s
+/// mapping, which lets debuggers patch read-only code.
+pub fn linear_alias(va: usize) -> Option<usize> {
+    let par = translate_read(va as u64);
+    if par & PAR_EL1::F != 0 {
+        return None;
+    }
+    Some(KERNEL_BASE + (par & PAR_EL1::PA) as usize + (va & 0xfff))
+}
+
+/// Returns the value of `TCR_EL1` translating `2^(64 - t0sz)` bytes with
+/// `TTBR0` and the kernel's `2^(64 - KERNEL_MASK_BITS)` bytes with `TTBR1`,
+/// with 8-bit ASIDs taken from `TTBR0`.
+///
+/// # Panics
+///
+/// Panics if the current system does not support the configured memory
+/// translation granule size.
+pub(crate) fn tcr(t0sz: usize) -> u64 {
+    unsafe {
+        #[cfg(not(feature = "granule-4k"))]
+        assert!(ID_AA64MMFR0_EL1.get_value(ID_AA64MMFR0_EL1::TGran64) == 0);
+        #[cfg(feature = "granule-4k")]
+        assert!(ID_AA64MMFR0_EL1.get_value(ID_AA64MMFR0_EL1::TGran4) == 0);
+    }
+
+    let ips = unsafe { ID_AA64MMFR0_EL1.get_value(ID_AA64MMFR0_EL1::PARange) };
+
+    // (ref. D7.2.91: Translation Control Register)
+    (0b00 << 37) |// TBI=0, no tagging
+    (0b0  << 36) |// AS=0, 8-bit ASIDs
+    (ips  << 32) |// IPS
+    (TCR_TG1 << 30) |// TG1: granule
+    (0b11 << 28) |// SH1=3 inner
+    (0b01 << 26) |// ORGN1=1 write back
+    (0b01 << 24) |// IRGN1=1 write back
+    (0b0  << 23) |// EPD1 enables higher half
+    (0b0  << 22) |// A1=0, TTBR0 holds the ASID
+    ((KERNEL_MASK_BITS as u64) << 16) | // T1SZ=32 (4GB)
+    (TCR_TG0 << 14) |// TG0: granule
+    (0b11 << 12) |// SH0=3 inner
+    (0b01 << 10) |// ORGN0=1 write back
+    (0b01 <<  8) |// IRGN0=1 write back
+    (0b0  <<  7) |// EPD0 enables lower half
+    ((t0sz as u64) << 0) // T0SZ
+}
+
+/// Reference counts of the physical pages mapped into user page tables.
+pub static PAGE_REFS: PageRefs = PageRefs::new();
+
+/// The ASIDs of user page tables.
+pub static ASIDS: Asids = Asids::new();
+
+/// Thread-safe (locking) wrapper around the kernel page tables: the
+/// `KernPageTable` of the upper half, and the `IoPageTable` loaded in the
+/// lower half while no process runs.
+///
+/// The tables are only changed to unmap and remap guard pages, so every core
+/// may read them at once.
+pub struct VMManager {
+    kern: RwLock<Option<KernPageTable>>,
+    io: RwLock<Option<IoPageTable>>,
+}
 
 impl VMManager {
     /// Returns an uninitialized `VMManager`.
@@ -19,59 +112,40 @@ impl VMManager {
     /// The virtual memory manager must be initialized by calling `initialize()` and `setup()`
     /// before the first memory allocation. Failure to do will result in panics.
     pub const fn uninitialized() -> Self {
-        VMManager(Mutex::new(None))
+        VMManager {
+            kern: RwLock::new(None),
+            io: RwLock::new(None),
+        }
     }
 
     /// Initializes the virtual memory manager.
     /// The caller should assure that the method is invoked only once during the kernel
     /// initialization.
     pub fn initialize(&self) {
-        unimplemented!();
+        *self.kern.write() = Some(KernPageTable::new());
+        *self.io.write() = Some(IoPageTable::new());
     }
 
     /// Set up the virtual memory manager.
     /// The caller should assure that `initialize()` has been called before calling this function.
-    /// Sets proper configuration bits to MAIR_EL1, TCR_EL1, TTBR0_EL1, and TTBR1_EL1 registers.
+    /// Sets proper configuration bits to MAIR_EL1 and TCR_EL1, loads the kernel page table
+    /// into TTBR1_EL1 and the `IoPageTable` into TTBR0_EL1, replacing the boot page tables.
     ///
     /// # Panics
     ///
-    /// Panics if the current system does not support 64KB memory translation granule size.
+    /// Panics if the current system does not support the configured memory
+    /// translation granule size.
     pub fn setup(&self) {
-        let kern_page_table = self.0.lock();
-        let baddr = kern_page_table.as_ref().unwrap().get_baddr().as_u64();
+        let kern_baddr = self.get_baddr().as_u64();
+        let io_baddr = self.get_io_ttbr();
 
         unsafe {
-            assert!(ID_AA64MMFR0_EL1.get_value(ID_AA64MMFR0_EL1::TGran64) == 0);
-
-            let ips = ID_AA64MMFR0_EL1.get_value(ID_AA64MMFR0_EL1::PARange);
-
-            // (ref. D7.2.70: Memory Attribute Indirection Register)
-            MAIR_EL1.set(
-                (0xFF <<  0) |// AttrIdx=0: normal, IWBWA, OWBWA, NTR
-                (0x04 <<  8) |// AttrIdx=1: device, nGnRE (must be OSH too)
-                (0x44 << 16), // AttrIdx=2: non cacheable
-            );
-            // (ref. D7.2.91: Translation Control Register)
-            TCR_EL1.set(
-                (0b00 << 37) |// TBI=0, no tagging
-                (ips  << 32) |// IPS
-                (0b11 << 30) |// TG1=64k
-                (0b11 << 28) |// SH1=3 inner
-                (0b01 << 26) |// ORGN1=1 write back
-                (0b01 << 24) |// IRGN1=1 write back
-                (0b0  << 23) |// EPD1 enables higher half
-                ((USER_MASK_BITS as u64) << 16) | // T1SZ=34 (1GB)
-                (0b01 << 14) |// TG0=64k
-                (0b11 << 12) |// SH0=3 inner
-                (0b01 << 10) |// ORGN0=1 write back
-                (0b01 <<  8) |// IRGN0=1 write back
-                (0b0  <<  7) |// EPD0 enables lower half
-                ((KERNEL_MASK_BITS as u64) << 0), // T0SZ=32 (4GB)
-            );
+            MAIR_EL1.set(MAIR);
+            TCR_EL1.set(tcr(USER_MASK_BITS));
             isb();
 
-            TTBR0_EL1.set(baddr);
-            TTBR1_EL1.set(baddr);
+            TTBR0_EL1.set(io_baddr);
+            TTBR1_EL1.set(kern_baddr);
 
             asm!("dsb ish");
             isb();
@@ -80,10 +154,71 @@ impl VMManager {
             asm!("dsb sy");
             isb();
         }
+
+        // Drop the translations of the boot page tables.
+        tlb_invalidate_all();
     }
 
     /// Returns the base address of the kernel page table as `PhysicalAddr`.
     pub fn get_baddr(&self) -> PhysicalAddr {
-        unimplemented!();
+        self.kern
+            .read()
+            .as_ref()
+            .expect("VMManager uninitialized")
+            .get_baddr()
+    }
+
+    /// Returns the value of `TTBR0_EL1` selecting the `IoPageTable`, tagged
+    /// with `KERNEL_ASID`.
+    pub fn get_io_ttbr(&self) -> u64 {
+        let baddr = self
+            .io
+            .read()
+            .as_ref()
+            .expect("VMManager uninitialized")
+            .get_baddr()
+            .as_u64();
+        baddr | ((KERNEL_ASID as u64) << 48)
+    }
+
+    /// Calls `f` with the `IoPageTable` loaded into `TTBR0_EL1`, so that `f`
+    /// may access peripherals at their physical addresses, and returns its
+    /// result. Before `setup()`, the boot page tables map them already.
+    pub fn with_io_identity<R, F: FnOnce() -> R>(&self, f: F) -> R {
+        if self.io.read().is_none() {
+            return f();
+        }
+
+        let ttbr = self.get_io_ttbr();
+        unsafe {
+            let saved = TTBR0_EL1.get();
+            TTBR0_EL1.set(ttbr);
+            isb();
+
+            let result = f();
+
+            TTBR0_EL1.set(saved);
+            isb();
+            result
+        }
+    }
+
+    /// Unmaps the page at the kernel virtual address `va`, so that any access
+    /// to it faults.
+    pub fn unmap(&self, va: VirtualAddr) {
+        self.kern
+            .write()
+            .as_mut()
+            .expect("VMManager uninitialized")
+            .unmap(va)
+    }
+
+    /// Maps the page at the kernel virtual address `va` back after `unmap()`.
+    pub fn remap(&self, va: VirtualAddr) {
+        self.kern
+            .write()
+            .as_mut()
+            .expect("VMManager uninitialized")
+            .remap(va)
     }
 }
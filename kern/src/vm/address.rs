@@ -1,6 +1,8 @@
 use core::fmt;
 use core::ops::{Add, AddAssign, BitAnd, BitOr, Sub, SubAssign};
 
+use crate::param::KERNEL_BASE;
+
 /// A virtual address.
 #[derive(Copy, Clone, PartialEq)]
 pub struct VirtualAddr(usize);
@@ -117,3 +119,26 @@ macro_rules! impl_for {
 
 impl_for!(VirtualAddr);
 impl_for!(PhysicalAddr);
+
+impl PhysicalAddr {
+    /// Returns the kernel virtual address at which this physical address is
+    /// mapped: every physical address `pa` is mapped at `KERNEL_BASE + pa`.
+    pub fn to_virtual(&self) -> VirtualAddr {
+        VirtualAddr(KERNEL_BASE + self.0)
+    }
+}
+
+impl VirtualAddr {
+    /// Returns the physical address this kernel virtual address is mapped
+    /// to.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `self` is not in the kernel's mapping of physical memory.
+    pub fn to_physical(&self) -> PhysicalAddr {
+        if self.0 < KERNEL_BASE {
+            panic!("{:?} is not a kernel address", self);
+        }
+        PhysicalAddr(self.0 - KERNEL_BASE)
+    }
+}
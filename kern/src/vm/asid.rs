@@ -0,0 +1,48 @@
+use crate::mutex::Mutex;
+
+use aarch64::tlb_invalidate_asid;
+
+/// The number of ASIDs with 8-bit ASIDs (`TCR_EL1.AS` = 0).
+const NUM_ASIDS: usize = 256;
+
+/// ASID 0 tags the lower-half table loaded while no process runs, and is
+/// never handed out.
+pub const KERNEL_ASID: u16 = 0;
+
+/// Allocator of the address space identifiers tagging the TLB entries of
+/// user page tables.
+///
+/// Every `UserPageTable` holds its own ASID, so switching `TTBR0` between
+/// processes needs no TLB invalidation. An ASID is invalidated when it is
+/// freed, before it can be reused.
+pub struct Asids(Mutex<[u64; NUM_ASIDS / 64]>);
+
+impl Asids {
+    /// Returns an `Asids` in which only `KERNEL_ASID` is in use.
+    pub const fn new() -> Asids {
+        Asids(Mutex::new([1 << KERNEL_ASID, 0, 0, 0]))
+    }
+
+    /// Returns a free ASID, or `None` if every ASID is in use.
+    pub fn alloc(&self) -> Option<u16> {
+        let mut used = self.0.lock();
+        for (i, word) in used.iter_mut().enumerate() {
+            if *word != !0 {
+                let bit = (!*word).trailing_zeros() as usize;
+                *word |= 1 << bit;
+                return Some((i * 64 + bit) as u16);
+            }
+        }
+        None
+    }
+
+    /// Invalidates the TLB entries tagged with `asid` and makes it free.
+    pub fn free(&self, asid: u16) {
+        tlb_invalidate_asid(asid);
+
+        let asid = asid as usize;
+        let mut used = self.0.lock();
+        assert!(used[asid / 64] & (1 << (asid % 64)) != 0, "ASID {} is not in use", asid);
+        used[asid / 64] &= !(1 << (asid % 64));
+    }
+}
@@ -0,0 +1,211 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::allocator;
+use crate::mutex::Mutex;
+use crate::param::{KERNEL_HEAP_SIZE, PAGE_MASK, PAGE_SIZE};
+use crate::vm::PhysicalAddr;
+
+/// The numbers of page frames managed by a `FrameAllocator`.
+#[derive(Debug, Copy, Clone)]
+pub struct FrameStats {
+    /// The number of frames.
+    pub total: usize,
+    /// The number of frames not allocated.
+    pub free: usize,
+}
+
+impl FrameStats {
+    /// Returns the number of allocated frames.
+    pub fn used(&self) -> usize {
+        self.total - self.free
+    }
+}
+
+impl fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} frames of {} KiB: {} used, {} free",
+            self.total,
+            PAGE_SIZE / 1024,
+            self.used(),
+            self.free
+        )
+    }
+}
+
+/// A bitmap of the page frames from physical address `base`: a set bit
+/// marks an allocated frame.
+struct Frames {
+    base: usize,
+    bitmap: Vec<u64>,
+    total: usize,
+    free: usize,
+}
+
+impl Frames {
+    /// Returns a `Frames` in which the frames between the physical addresses
+    /// `start` and `end` are free.
+    fn new(start: usize, end: usize) -> Frames {
+        let base = (start + PAGE_SIZE - 1) & PAGE_MASK;
+        let total = end.saturating_sub(base) / PAGE_SIZE;
+        Frames {
+            base,
+            bitmap: vec![0; (total + 63) / 64],
+            total,
+            free: total,
+        }
+    }
+
+    fn is_used(&self, frame: usize) -> bool {
+        self.bitmap[frame / 64] & (1 << (frame % 64)) != 0
+    }
+
+    fn set_used(&mut self, frame: usize, used: bool) {
+        if used {
+            self.bitmap[frame / 64] |= 1 << (frame % 64);
+        } else {
+            self.bitmap[frame / 64] &= !(1 << (frame % 64));
+        }
+    }
+
+    /// Returns the first frame of the lowest run of `count` free frames.
+    fn find(&self, count: usize) -> Option<usize> {
+        let mut run = 0;
+        let mut frame = 0;
+        while frame < self.total {
+            if run == 0 && frame % 64 == 0 && self.bitmap[frame / 64] == !0 {
+                frame += 64;
+                continue;
+            }
+
+            if self.is_used(frame) {
+                run = 0;
+            } else {
+                run += 1;
+                if run == count {
+                    return Some(frame + 1 - count);
+                }
+            }
+            frame += 1;
+        }
+        None
+    }
+
+    fn alloc(&mut self, count: usize) -> Option<PhysicalAddr> {
+        if count == 0 || count > self.free {
+            return None;
+        }
+
+        let first = self.find(count)?;
+        for frame in first..first + count {
+            self.set_used(frame, true);
+        }
+        self.free -= count;
+        Some(PhysicalAddr::from(self.base + first * PAGE_SIZE))
+    }
+
+    fn dealloc(&mut self, pa: PhysicalAddr, count: usize) {
+        let addr = pa.as_usize();
+        if addr < self.base || addr % PAGE_SIZE != 0 {
+            panic!("{:?} is not a page frame", pa);
+        }
+
+        let first = (addr - self.base) / PAGE_SIZE;
+        if first + count > self.total {
+            panic!("{:?} is not a page frame", pa);
+        }
+
+        for frame in first..first + count {
+            if !self.is_used(frame) {
+                panic!("page frame at {:#x} freed twice", self.base + frame * PAGE_SIZE);
+            }
+            self.set_used(frame, false);
+        }
+        self.free += count;
+    }
+}
+
+/// Thread-safe (locking) allocator of physical page frames.
+///
+/// The RAM reported by `allocator::memory_map()` is split in two: the first
+/// `KERNEL_HEAP_SIZE` bytes after the kernel image are the kernel heap, and
+/// the page frames in the rest are handed out here, to back page tables,
+/// user pages and kernel stacks.
+pub struct FrameAllocator(Mutex<Option<Frames>>);
+
+impl FrameAllocator {
+    /// Returns an uninitialized `FrameAllocator`.
+    ///
+    /// The frame allocator must be initialized by calling `initialize()`
+    /// after the kernel heap and before the first frame allocation. Failure
+    /// to do will result in panics.
+    pub const fn uninitialized() -> Self {
+        FrameAllocator(Mutex::new(None))
+    }
+
+    /// Initializes the frame allocator with the frames following the kernel
+    /// heap.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the system's memory map could not be retrieved.
+    pub fn initialize(&self) {
+        let (start, end) = allocator::memory_map().expect("failed to find memory map");
+        *self.0.lock() = Some(Frames::new(start.saturating_add(KERNEL_HEAP_SIZE), end));
+    }
+
+    /// Allocates `count` contiguous page frames and returns the physical
+    /// address of the first. Returns `None` if no such run of frames is free.
+    /// The frames' contents are not initialized.
+    pub fn alloc(&self, count: usize) -> Option<PhysicalAddr> {
+        self.0
+            .lock()
+            .as_mut()
+            .expect("frame allocator uninitialized")
+            .alloc(count)
+    }
+
+    /// Frees the `count` page frames starting at the physical address `pa`,
+    /// which were allocated together by `alloc()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the frames is not allocated.
+    pub fn dealloc(&self, pa: PhysicalAddr, count: usize) {
+        self.0
+            .lock()
+            .as_mut()
+            .expect("frame allocator uninitialized")
+            .dealloc(pa, count)
+    }
+
+    /// Returns the numbers of frames in total and free.
+    pub fn stats(&self) -> FrameStats {
+        let frames = self.0.lock();
+        let frames = frames.as_ref().expect("frame allocator uninitialized");
+        FrameStats {
+            total: frames.total,
+            free: frames.free,
+        }
+    }
+}
+
+impl fmt::Debug for FrameAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.lock().as_ref() {
+            Some(frames) => write!(
+                f,
+                "FrameAllocator {{ base: {:#x}, {} }}",
+                frames.base,
+                FrameStats {
+                    total: frames.total,
+                    free: frames.free,
+                }
+            ),
+            None => write!(f, "Not yet initialized"),
+        }
+    }
+}
@@ -1,17 +1,16 @@
-use core::iter::Chain;
 use core::ops::{Deref, DerefMut};
-use core::slice::Iter;
 
 use alloc::boxed::Box;
 use alloc::fmt;
-use core::alloc::{GlobalAlloc, Layout};
 
 use crate::allocator;
 use crate::param::*;
-use crate::vm::{PhysicalAddr, VirtualAddr};
-use crate::ALLOCATOR;
+use crate::vm::{PhysicalAddr, VirtualAddr, ASIDS, PAGE_REFS};
+use crate::{FRAMES, SWAP};
 
 use aarch64::vmsa::*;
+use aarch64::{tlb_invalidate_asid, tlb_invalidate_asid_va, tlb_invalidate_va};
+use kernel_api::{OsError, OsResult};
 use shim::const_assert_size;
 
 #[repr(C)]
@@ -21,167 +20,845 @@ const_assert_size!(Page, PAGE_SIZE);
 impl Page {
     pub const SIZE: usize = PAGE_SIZE;
     pub const ALIGN: usize = PAGE_SIZE;
+}
 
-    fn layout() -> Layout {
-        unsafe { Layout::from_size_align_unchecked(Self::SIZE, Self::ALIGN) }
-    }
+/// Returns the physical address of the kernel pointer `ptr`.
+fn phys(ptr: *const u8) -> PhysicalAddr {
+    VirtualAddr::from(ptr).to_physical()
 }
 
-#[repr(C)]
-#[repr(align(65536))]
-pub struct L2PageTable {
-    pub entries: [RawL2Entry; 8192],
+/// Returns a kernel pointer to the physical address `pa`.
+fn virt(pa: PhysicalAddr) -> *mut u8 {
+    pa.to_virtual().as_usize() as *mut u8
+}
+
+/// Allocates a page frame, evicting a user page to the swap file if no frame
+/// is free.
+fn alloc_frame() -> Option<PhysicalAddr> {
+    FRAMES.alloc(1).or_else(|| SWAP.evict())
 }
-const_assert_size!(L2PageTable, PAGE_SIZE);
 
-impl L2PageTable {
-    /// Returns a new `L2PageTable`
-    fn new() -> L2PageTable {
-        unimplemented!("L2PageTable::new()")
+/// The number of descriptors in a translation table, which fills a page.
+const ENTRIES: usize = PAGE_SIZE / 8;
+
+/// The number of virtual address bits resolved by each level of tables.
+const LEVEL_BITS: usize = PAGE_SHIFT - 3;
+
+/// The level of the tables holding page descriptors.
+const LAST_LEVEL: usize = 3;
+
+/// The output address bits of a table or page descriptor for the configured
+/// granule. With the 64KB granule, this is `RawL3Entry::ADDR`.
+const ADDR_MASK: u64 = (PAGE_MASK as u64) & ((1 << 48) - 1);
+
+/// A translation table of any level: a page of descriptors. The last level
+/// holds page descriptors; the levels above hold table descriptors pointing
+/// to the tables of the next level.
+#[repr(C)]
+#[cfg_attr(not(feature = "granule-4k"), repr(align(65536)))]
+#[cfg_attr(feature = "granule-4k", repr(align(4096)))]
+pub struct Table {
+    pub entries: [RawL3Entry; ENTRIES],
+}
+const_assert_size!(Table, PAGE_SIZE);
+
+impl Table {
+    /// Allocates a zeroed `Table`, in which every descriptor is invalid, in
+    /// a page frame. Returns `None` if no frame is left.
+    fn alloc() -> Option<*mut Table> {
+        let table = virt(alloc_frame()?);
+        unsafe { table.write_bytes(0, PAGE_SIZE) };
+        Some(table as *mut Table)
     }
 
     /// Returns a `PhysicalAddr` of the pagetable.
     pub fn as_ptr(&self) -> PhysicalAddr {
-        unimplemented!("L2PageTable::as_ptr()")
+        phys(self as *const Table as *const u8)
     }
 }
 
+#[repr(transparent)]
 #[derive(Copy, Clone)]
 pub struct L3Entry(RawL3Entry);
 
 impl L3Entry {
-    /// Returns a new `L3Entry`.
-    fn new() -> L3Entry {
-        unimplemented!("L3Entry::new()")
-    }
-
     /// Returns `true` if the L3Entry is valid and `false` otherwise.
     fn is_valid(&self) -> bool {
-        unimplemented!("L3Entry::is_valid()")
+        self.0.get_value(RawL3Entry::VALID) == EntryValid::Valid
     }
 
     /// Extracts `ADDR` field of the L3Entry and returns as a `PhysicalAddr`
     /// if valid. Otherwise, return `None`.
     fn get_page_addr(&self) -> Option<PhysicalAddr> {
-        unimplemented!("LeEntry::get_page_add()")
+        if self.is_valid() {
+            Some(PhysicalAddr::from(self.0.get_masked(ADDR_MASK)))
+        } else {
+            None
+        }
     }
 }
 
-#[repr(C)]
-#[repr(align(65536))]
-pub struct L3PageTable {
-    pub entries: [L3Entry; 8192],
-}
-const_assert_size!(L3PageTable, PAGE_SIZE);
-
-impl L3PageTable {
-    /// Returns a new `L3PageTable`.
-    fn new() -> L3PageTable {
-        unimplemented!("L3PageTable::new()")
+/// Returns the table a valid table descriptor `entry` points to, through
+/// the kernel's mapping of physical memory.
+fn next_table(entry: RawL3Entry) -> Option<*mut Table> {
+    if entry.get_value(RawL3Entry::VALID) == EntryValid::Valid
+        && entry.get_value(RawL3Entry::TYPE) == EntryType::Table
+    {
+        Some(virt(entry.get_masked(ADDR_MASK).into()) as *mut Table)
+    } else {
+        None
     }
+}
 
-    /// Returns a `PhysicalAddr` of the pagetable.
-    pub fn as_ptr(&self) -> PhysicalAddr {
-        unimplemented!("L3PageTable::as_ptr()")
-    }
+/// Returns the bit position of the lowest virtual address bit indexing the
+/// tables of `level`.
+const fn level_shift(level: usize) -> usize {
+    PAGE_SHIFT + (LAST_LEVEL - level) * LEVEL_BITS
 }
 
-#[repr(C)]
-#[repr(align(65536))]
+/// A multi-level translation table for an address space of `2^va_bits`
+/// bytes, walked from the root table down to `LAST_LEVEL`.
+///
+/// The number of levels follows from the granule and `va_bits`, exactly as
+/// the MMU derives its starting level from the granule and `TxSZ`. Tables
+/// below the root are allocated when a page is first mapped through them and
+/// freed when the `PageTable` is dropped.
 pub struct PageTable {
-    pub l2: L2PageTable,
-    pub l3: [L3PageTable; 2],
+    root: *mut Table,
+    start_level: usize,
+    va_bits: usize,
 }
 
+// The tables are owned by the `PageTable` alone.
+unsafe impl Send for PageTable {}
+
 impl PageTable {
-    /// Returns a new `Box` containing `PageTable`.
-    /// Entries in L2PageTable should be initialized properly before return.
-    fn new(perm: u64) -> Box<PageTable> {
-        unimplemented!("PageTable::new()")
+    /// Returns a new `Box` containing `PageTable` for an address space of
+    /// `2^va_bits` bytes, with an empty root table.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `va_bits` is not between the page size and the 48 bits the
+    /// MMU translates, or if the root table could not be allocated.
+    fn new(va_bits: usize) -> Box<PageTable> {
+        assert!(va_bits > PAGE_SHIFT && va_bits <= 48);
+
+        let levels = (va_bits - PAGE_SHIFT + LEVEL_BITS - 1) / LEVEL_BITS;
+        let root = Table::alloc().expect("failed to allocate a translation table");
+        Box::new(PageTable {
+            root,
+            start_level: LAST_LEVEL + 1 - levels,
+            va_bits,
+        })
+    }
+
+    /// Returns the index into the table of `level` for the virtual address
+    /// `va`.
+    fn index(va: usize, level: usize) -> usize {
+        (va >> level_shift(level)) & (ENTRIES - 1)
     }
 
-    /// Returns the (L2index, L3index) extracted from the given virtual address.
-    /// Since we are only supporting 1GB virtual memory in this system, L2index
-    /// should be smaller than 2.
+    /// Checks that `va` can be translated by this table.
     ///
     /// # Panics
     ///
     /// Panics if the virtual address is not properly aligned to page size.
-    /// Panics if extracted L2index exceeds the number of L3PageTable.
-    fn locate(va: VirtualAddr) -> (usize, usize) {
-        unimplemented!("PageTable::localte()")
+    /// Panics if the virtual address is outside the address space.
+    fn locate(&self, va: VirtualAddr) -> usize {
+        let va = va.as_usize();
+        if va % PAGE_SIZE != 0 {
+            panic!("virtual address {:#x} is not aligned to page size", va);
+        }
+        if va >> self.va_bits != 0 {
+            panic!("virtual address {:#x} is out of range", va);
+        }
+
+        va
+    }
+
+    /// Walks the tables down to the page descriptor for `va`. Returns `None`
+    /// if a table on the way is missing.
+    fn walk(&self, va: VirtualAddr) -> Option<*mut RawL3Entry> {
+        let va = self.locate(va);
+        let mut table = self.root;
+        for level in self.start_level..LAST_LEVEL {
+            let entry = unsafe { (*table).entries[PageTable::index(va, level)] };
+            table = next_table(entry)?;
+        }
+
+        Some(unsafe { &mut (*table).entries[PageTable::index(va, LAST_LEVEL)] as *mut RawL3Entry })
+    }
+
+    /// Like `walk()`, but allocates the missing tables on the way. Returns
+    /// `OsError::NoMemory` if a table could not be allocated.
+    fn walk_alloc(&mut self, va: VirtualAddr) -> OsResult<&mut RawL3Entry> {
+        let va = self.locate(va);
+        let mut table = self.root;
+        for level in self.start_level..LAST_LEVEL {
+            let entry = unsafe { &mut (*table).entries[PageTable::index(va, level)] };
+            table = match next_table(*entry) {
+                Some(next) => next,
+                None => {
+                    let next = Table::alloc().ok_or(OsError::NoMemory)?;
+                    entry
+                        .set_masked(phys(next as *const u8).as_u64(), ADDR_MASK)
+                        .set_value(EntryType::Table, RawL3Entry::TYPE)
+                        .set_value(EntryValid::Valid, RawL3Entry::VALID);
+                    next
+                }
+            };
+        }
+
+        Ok(unsafe { &mut (*table).entries[PageTable::index(va, LAST_LEVEL)] })
     }
 
     /// Returns `true` if the L3entry indicated by the given virtual address is valid.
     /// Otherwise, `false` is returned.
     pub fn is_valid(&self, va: VirtualAddr) -> bool {
-        unimplemented!("PageTable::is_valid()")
+        L3Entry(self.get_entry(va)).is_valid()
     }
 
     /// Returns `true` if the L3entry indicated by the given virtual address is invalid.
     /// Otherwise, `true` is returned.
     pub fn is_invalid(&self, va: VirtualAddr) -> bool {
-        unimplemented!("PageTable::is_invalid()")
+        !self.is_valid(va)
+    }
+
+    /// Returns the RawL3Entry indicated by the given virtual address. The
+    /// entry is invalid if no table maps the address yet.
+    pub fn get_entry(&self, va: VirtualAddr) -> RawL3Entry {
+        match self.walk(va) {
+            Some(entry) => unsafe { *entry },
+            None => RawL3Entry::new(0),
+        }
     }
 
     /// Set the given RawL3Entry `entry` to the L3Entry indicated by the given virtual
-    /// address.
+    /// address, allocating the tables on the way if needed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a table could not be allocated. Use `try_set_entry()` to
+    /// handle that case.
     pub fn set_entry(&mut self, va: VirtualAddr, entry: RawL3Entry) -> &mut Self {
-        unimplemented!("PageTable::set_entry()")
+        self.try_set_entry(va, entry)
+            .expect("failed to allocate a translation table");
+        self
+    }
+
+    /// Like `set_entry()`, but returns `OsError::NoMemory` if a table could
+    /// not be allocated.
+    pub fn try_set_entry(&mut self, va: VirtualAddr, entry: RawL3Entry) -> OsResult<()> {
+        *self.walk_alloc(va)? = entry;
+        Ok(())
     }
 
-    /// Returns a base address of the pagetable. The returned `PhysicalAddr` value
-    /// will point the start address of the L2PageTable.
+    /// Returns a base address of the pagetable. The returned `PhysicalAddr`
+    /// value will point the start address of the root table.
     pub fn get_baddr(&self) -> PhysicalAddr {
-        unimplemented!("PageTable::get_baddr()")
+        phys(self.root as *const u8)
+    }
+
+    /// Calls `f` with the virtual address and entry of every valid page
+    /// descriptor, in address order.
+    pub fn for_each_page<F: FnMut(VirtualAddr, &mut L3Entry)>(&mut self, mut f: F) {
+        self.for_each_entry(|va, entry| {
+            if entry.is_valid() {
+                f(va, entry);
+            }
+        });
+    }
+
+    /// Calls `f` with the virtual address and entry of every non-zero page
+    /// descriptor, valid or not, in address order.
+    pub fn for_each_entry<F: FnMut(VirtualAddr, &mut L3Entry)>(&mut self, mut f: F) {
+        fn visit<F: FnMut(VirtualAddr, &mut L3Entry)>(
+            table: *mut Table,
+            level: usize,
+            base: usize,
+            f: &mut F,
+        ) {
+            for (i, entry) in unsafe { (*table).entries.iter_mut() }.enumerate() {
+                let va = base | (i << level_shift(level));
+                if level == LAST_LEVEL {
+                    let entry = unsafe { &mut *(entry as *mut RawL3Entry as *mut L3Entry) };
+                    if entry.0.get() != 0 {
+                        f(VirtualAddr::from(va), entry);
+                    }
+                } else if let Some(next) = next_table(*entry) {
+                    visit(next, level + 1, va, f);
+                }
+            }
+        }
+
+        visit(self.root, self.start_level, 0, &mut f);
     }
 }
 
-// FIXME: Implement `IntoIterator` for `&PageTable`.
+impl Drop for PageTable {
+    /// Frees every table. The pages they map are not freed.
+    fn drop(&mut self) {
+        fn free(table: *mut Table, level: usize) {
+            if level < LAST_LEVEL {
+                for entry in unsafe { (*table).entries.iter() } {
+                    if let Some(next) = next_table(*entry) {
+                        free(next, level + 1);
+                    }
+                }
+            }
+            FRAMES.dealloc(phys(table as *const u8), 1);
+        }
+
+        free(self.root, self.start_level);
+    }
+}
+
+/// Returns a valid page `RawL3Entry` that maps to the physical address `addr`
+/// with the given access permission, memory attribute and shareability.
+fn page_entry(addr: PhysicalAddr, perm: u64, attr: u64, sh: u64) -> RawL3Entry {
+    let mut entry = RawL3Entry::new(0);
+    entry
+        .set_masked(addr.as_u64(), ADDR_MASK)
+        .set_value(1, RawL3Entry::AF)
+        .set_value(sh, RawL3Entry::SH)
+        .set_value(perm, RawL3Entry::AP)
+        .set_value(attr, RawL3Entry::ATTR)
+        .set_value(PageType::Page, RawL3Entry::TYPE)
+        .set_value(EntryValid::Valid, RawL3Entry::VALID);
+    entry
+}
 
 pub struct KernPageTable(Box<PageTable>);
 
 impl KernPageTable {
-    /// Returns a new `KernPageTable`. `KernPageTable` should have a `Pagetable`
-    /// created with `KERN_RW` permission.
-    ///
-    /// Set L3entry of ARM physical address starting at 0x00000000 for RAM and
-    /// physical address range from `IO_BASE` to `IO_BASE_END` for peripherals.
-    /// Each L3 entry should have correct value for lower attributes[10:0] as well
-    /// as address[47:16]. Refer to the definition of `RawL3Entry` in `vmsa.rs` for
-    /// more details.
+    /// Returns a new `KernPageTable` translating the `2^(64 - KERNEL_MASK_BITS)`
+    /// bytes of the upper half from `KERNEL_BASE`, with `KERN_RW` permission.
+    ///
+    /// Physical memory is mapped linearly: RAM starting at physical address
+    /// 0x00000000 as normal memory, and the peripherals from `IO_BASE_PHYS`
+    /// to `IO_BASE_PHYS_END` and the local peripherals of the cores from
+    /// `LOCAL_BASE_PHYS` to `LOCAL_BASE_PHYS_END` as nGnRE device memory
+    /// only. Offsets into the
+    /// table are thus physical addresses. No kernel page is executable from
+    /// EL0, and peripherals are not executable at all.
     pub fn new() -> KernPageTable {
-        unimplemented!("KernPageTable::new()")
+        let mut pt = PageTable::new(64 - KERNEL_MASK_BITS);
+        let (_, end) = allocator::memory_map().expect("failed to find memory map");
+
+        let mut addr = 0;
+        while addr + PAGE_SIZE <= end.min(IO_BASE_PHYS) {
+            pt.set_entry(addr.into(), KernPageTable::ram_entry(addr.into()));
+            addr += PAGE_SIZE;
+        }
+
+        let devices = [
+            (IO_BASE_PHYS, IO_BASE_PHYS_END),
+            (LOCAL_BASE_PHYS, LOCAL_BASE_PHYS_END),
+        ];
+        for &(start, end) in devices.iter() {
+            let mut addr = start;
+            while addr < end {
+                let mut entry = page_entry(addr.into(), EntryPerm::KERN_RW, EntryAttr::Dev, EntrySh::OSh);
+                entry.set_value(1, RawL3Entry::UXN).set_value(1, RawL3Entry::PXN);
+                pt.set_entry(addr.into(), entry);
+                addr += PAGE_SIZE;
+            }
+        }
+
+        KernPageTable(pt)
+    }
+
+    /// Returns the entry mapping the page of RAM at `addr`.
+    fn ram_entry(addr: PhysicalAddr) -> RawL3Entry {
+        let mut entry = page_entry(addr, EntryPerm::KERN_RW, EntryAttr::Mem, EntrySh::ISh);
+        entry.set_value(1, RawL3Entry::UXN);
+        entry
+    }
+
+    /// Unmaps the page of RAM at the kernel virtual address `va`, so that
+    /// any access to it faults. Used for guard pages.
+    pub fn unmap(&mut self, va: VirtualAddr) {
+        self.set_entry(va - VirtualAddr::from(KERNEL_BASE), RawL3Entry::new(0));
+        tlb_invalidate_va(va.as_u64());
+    }
+
+    /// Maps the page of RAM at the kernel virtual address `va` back after
+    /// `unmap()`.
+    pub fn remap(&mut self, va: VirtualAddr) {
+        let local = va - VirtualAddr::from(KERNEL_BASE);
+        self.set_entry(local, KernPageTable::ram_entry(va.to_physical()));
+        tlb_invalidate_va(va.as_u64());
     }
 }
 
+/// The table loaded into `TTBR0` while no process runs, and around calls
+/// into `libsd`.
+///
+/// `libsd` is prebuilt against the physical addresses of the EMMC controller,
+/// so the peripherals from `IO_BASE_PHYS` to `IO_BASE_PHYS_END` are identity
+/// mapped here, for EL1 only. Nothing else is mapped in the lower half
+/// outside of user page tables.
+pub struct IoPageTable(Box<PageTable>);
+
+impl IoPageTable {
+    /// Returns a new `IoPageTable` translating the `USER_MAX_VM_SIZE` bytes
+    /// of the lower half. Its entries are tagged with `KERNEL_ASID`.
+    pub fn new() -> IoPageTable {
+        let mut pt = PageTable::new(64 - USER_MASK_BITS);
+
+        let mut addr = IO_BASE_PHYS;
+        while addr < IO_BASE_PHYS_END {
+            let mut entry = page_entry(addr.into(), EntryPerm::KERN_RW, EntryAttr::Dev, EntrySh::OSh);
+            entry
+                .set_value(1, RawL3Entry::NG)
+                .set_value(1, RawL3Entry::UXN)
+                .set_value(1, RawL3Entry::PXN);
+            pt.set_entry(addr.into(), entry);
+            addr += PAGE_SIZE;
+        }
+
+        IoPageTable(pt)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum PagePerm {
     RW,
     RO,
+    RX,
     RWX,
 }
 
-pub struct UserPageTable(Box<PageTable>);
+impl PagePerm {
+    /// Returns `true` if pages with this permission are writable from EL0.
+    pub fn is_writable(&self) -> bool {
+        match self {
+            PagePerm::RW | PagePerm::RWX => true,
+            PagePerm::RO | PagePerm::RX => false,
+        }
+    }
+
+    /// Returns `true` if pages with this permission are executable from EL0.
+    pub fn is_executable(&self) -> bool {
+        match self {
+            PagePerm::RX | PagePerm::RWX => true,
+            PagePerm::RW | PagePerm::RO => false,
+        }
+    }
+
+    /// Sets the AP and execute-never bits of `entry` for this permission.
+    /// User pages are never executable at EL1.
+    ///
+    /// A page shared copy-on-write stays `USER_RO` until it is copied.
+    fn apply(&self, entry: &mut RawL3Entry) {
+        let cow = entry.get_value(RawL3Entry::SW) & SW_COW != 0;
+        let ap = if self.is_writable() && !cow {
+            EntryPerm::USER_RW
+        } else {
+            EntryPerm::USER_RO
+        };
+        let uxn = if self.is_executable() { 0 } else { 1 };
+
+        entry
+            .set_value(ap, RawL3Entry::AP)
+            .set_value(uxn, RawL3Entry::UXN)
+            .set_value(1, RawL3Entry::PXN);
+    }
+}
+
+/// Value of `RawL3Entry::SW` bit marking a page shared copy-on-write. Such a
+/// page is mapped `USER_RO` but is writable once copied.
+const SW_COW: u64 = 0b0001;
+
+/// Value of `RawL3Entry::SW` bit marking an invalid entry whose page was
+/// evicted to the swap file. Its `ADDR` field holds the swap slot, and its
+/// other fields are kept for when the page is swapped back in.
+const SW_SWAPPED: u64 = 0b0010;
+
+/// Returns the swap slot of the page `entry` maps, if it was swapped out.
+fn swapped_slot(entry: RawL3Entry) -> Option<usize> {
+    if entry.get_value(RawL3Entry::VALID) != EntryValid::Valid
+        && entry.get_value(RawL3Entry::SW) & SW_SWAPPED != 0
+    {
+        Some((entry.get_masked(ADDR_MASK) >> PAGE_SHIFT) as usize)
+    } else {
+        None
+    }
+}
+
+/// Returns `entry` made invalid and recording that its page is in swap
+/// `slot`.
+pub(super) fn swapped_entry(mut entry: RawL3Entry, slot: usize) -> RawL3Entry {
+    let sw = entry.get_value(RawL3Entry::SW) | SW_SWAPPED;
+    entry
+        .set_masked((slot << PAGE_SHIFT) as u64, ADDR_MASK)
+        .set_value(sw, RawL3Entry::SW)
+        .set_value(EntryValid::Invalid, RawL3Entry::VALID);
+    entry
+}
+
+/// Returns `true` if the page `entry` maps was accessed since its access
+/// flag was last cleared.
+pub(super) fn is_accessed(entry: RawL3Entry) -> bool {
+    entry.get_value(RawL3Entry::AF) == 1
+}
+
+/// Sets or clears the access flag of `entry`. An access through an entry
+/// whose access flag is clear raises an access flag fault.
+pub(super) fn set_accessed(entry: &mut RawL3Entry, accessed: bool) {
+    entry.set_value(accessed as u64, RawL3Entry::AF);
+}
+
+/// A user page table, loaded into `TTBR0` with its own ASID. Its pages are
+/// mapped non-global, so their TLB entries are tagged with the ASID.
+pub struct UserPageTable {
+    pt: Box<PageTable>,
+    asid: u16,
+}
 
 impl UserPageTable {
-    /// Returns a new `UserPageTable` containing a `PageTable` created with
-    /// `USER_RW` permission.
-    pub fn new() -> UserPageTable {
-        unimplemented!("UserPageTable::new()")
+    /// Returns a new, empty `UserPageTable` translating the lower
+    /// `USER_MAX_VM_SIZE` bytes of the address space, of which the user
+    /// process may use the addresses from `USER_IMG_BASE`.
+    ///
+    /// Returns `OsError::NoVmSpace` if no ASID is free.
+    pub fn new() -> OsResult<UserPageTable> {
+        let asid = ASIDS.alloc().ok_or(OsError::NoVmSpace)?;
+        Ok(UserPageTable {
+            pt: PageTable::new(64 - USER_MASK_BITS),
+            asid,
+        })
+    }
+
+    /// Returns the value of `TTBR0_EL1` selecting this page table: its base
+    /// address and its ASID.
+    pub fn ttbr(&self) -> u64 {
+        self.get_baddr().as_u64() | ((self.asid as u64) << 48)
     }
 
-    /// Allocates a page and set an L3 entry translates given virtual address to the
-    /// physical address of the allocated page. Returns the allocated page.
+    /// Translates the user virtual address `va` into the physical address it
+    /// is mapped to.
     ///
-    /// # Panics
-    /// Panics if the virtual address is lower than `USER_IMG_BASE`.
-    /// Panics if the virtual address has already been allocated.
-    /// Panics if allocator fails to allocate a page.
+    /// Returns `None` if `va` lies outside of `USER_IMG_BASE..USER_MAX_VM_SIZE`,
+    /// if the page containing `va` is not mapped, or if the page is not
+    /// accessible from EL0. If `write` is `true`, the page must also be
+    /// writable from EL0.
+    pub fn translate(&self, va: VirtualAddr, write: bool) -> Option<PhysicalAddr> {
+        if va.as_usize() < USER_IMG_BASE || va.as_usize() >= USER_MAX_VM_SIZE {
+            return None;
+        }
+
+        let offset = va.as_usize() & !PAGE_MASK;
+        let page = VirtualAddr::from(va.as_usize() & PAGE_MASK);
+        let entry = self.get_entry(page);
+        if entry.get_value(RawL3Entry::VALID) != EntryValid::Valid {
+            return None;
+        }
+
+        let allowed = match entry.get_value(RawL3Entry::AP) {
+            EntryPerm::USER_RW => true,
+            EntryPerm::USER_RO => !write,
+            _ => false,
+        };
+        if !allowed {
+            return None;
+        }
+
+        Some(PhysicalAddr::from(entry.get_masked(ADDR_MASK) as usize + offset))
+    }
+
+    /// Allocates a zeroed page and set an L3 entry translates given virtual
+    /// address to the physical address of the allocated page. Returns the
+    /// allocated page.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OsError::BadAddress` if the virtual address is lower than
+    /// `USER_IMG_BASE` or not aligned to page size.
+    /// Returns `OsError::InvalidArgument` if the virtual address has already
+    /// been allocated, even if its page is swapped out.
+    /// Returns `OsError::NoMemory` if no page frame is left for the page or
+    /// a table on the way to it, and no page could be swapped out.
+    pub fn alloc(&mut self, va: VirtualAddr, perm: PagePerm) -> OsResult<&mut [u8]> {
+        if va.as_usize() < USER_IMG_BASE || va.as_usize() & !PAGE_MASK != 0 {
+            return Err(OsError::BadAddress);
+        }
+
+        if self.is_valid(va) || self.is_swapped(va) {
+            return Err(OsError::InvalidArgument);
+        }
+
+        let asid = self.asid;
+        let slot = self.walk_alloc(va)?;
+        let frame = alloc_frame().ok_or(OsError::NoMemory)?;
+        let page = virt(frame);
+
+        let mut entry = page_entry(frame, EntryPerm::USER_RW, EntryAttr::Mem, EntrySh::ISh);
+        entry.set_value(1, RawL3Entry::NG);
+        perm.apply(&mut entry);
+        *slot = entry;
+        PAGE_REFS.inc(frame);
+        SWAP.track(frame, slot, asid, va.as_usize());
+
+        unsafe {
+            page.write_bytes(0, PAGE_SIZE);
+            Ok(core::slice::from_raw_parts_mut(page, PAGE_SIZE))
+        }
+    }
+
+    /// Changes the permission of the page containing the user virtual address
+    /// `va` to `perm`. Does nothing if the page is neither mapped nor swapped
+    /// out.
+    ///
+    /// A page that other page tables share, such as a read-only page after a
+    /// fork, is made copy-on-write rather than writable, so that the first
+    /// write copies it.
+    pub fn protect(&mut self, va: VirtualAddr, perm: PagePerm) {
+        if va.as_usize() < USER_IMG_BASE {
+            return;
+        }
+
+        let page = VirtualAddr::from(va.as_usize() & PAGE_MASK);
+        let mut entry = self.get_entry(page);
+        if entry.get_value(RawL3Entry::VALID) != EntryValid::Valid
+            && swapped_slot(entry).is_none()
+        {
+            return;
+        }
+
+        let shared = entry.get_value(RawL3Entry::VALID) == EntryValid::Valid
+            && PAGE_REFS.get(PhysicalAddr::from(entry.get_masked(ADDR_MASK))) > 1;
+        if perm.is_writable() && shared {
+            let sw = entry.get_value(RawL3Entry::SW) | SW_COW;
+            entry.set_value(sw, RawL3Entry::SW);
+        }
+
+        perm.apply(&mut entry);
+        self.set_entry(page, entry);
+        tlb_invalidate_asid_va(self.asid, page.as_u64());
+    }
+
+    /// Unmaps the page containing the user virtual address `va`, freeing it
+    /// once no other page table shares it, or freeing its swap slot if it is
+    /// swapped out. Does nothing if the page is not mapped.
+    pub fn dealloc(&mut self, va: VirtualAddr) {
+        if va.as_usize() < USER_IMG_BASE {
+            return;
+        }
+
+        let page = VirtualAddr::from(va.as_usize() & PAGE_MASK);
+        let entry = self.get_entry(page);
+        if let Some(slot) = swapped_slot(entry) {
+            self.set_entry(page, RawL3Entry::new(0));
+            SWAP.free(slot);
+            return;
+        }
+        if entry.get_value(RawL3Entry::VALID) != EntryValid::Valid {
+            return;
+        }
+
+        let addr = PhysicalAddr::from(entry.get_masked(ADDR_MASK));
+        self.untrack(page, addr);
+        self.set_entry(page, RawL3Entry::new(0));
+        tlb_invalidate_asid_va(self.asid, page.as_u64());
+
+        if PAGE_REFS.dec(addr) == 0 {
+            FRAMES.dealloc(addr, 1);
+        }
+    }
+
+    /// Returns a copy of this page table for a forked process.
+    ///
+    /// No page is copied. Writable pages are shared copy-on-write: their
+    /// entries in both tables become `USER_RO` and are marked `SW_COW`.
+    /// Read-only pages are shared as they are. Every shared page gains a
+    /// reference and is tracked for swapping with the child's entry too, and
+    /// stale writable TLB entries of this table are invalidated. Swapped out
+    /// pages are copied to new swap slots for the child.
+    ///
+    /// Returns `OsError::NoMemory` if the child's tables or swap slots could
+    /// not be allocated, and `OsError::NoVmSpace` if no ASID is free for it.
+    pub fn fork(&mut self) -> OsResult<UserPageTable> {
+        let mut child = UserPageTable::new()?;
+        let mut result = Ok(());
+        self.pt.for_each_entry(|va, entry| {
+            if result.is_err() {
+                return;
+            }
+
+            let raw = &mut entry.0;
+            if let Some(slot) = swapped_slot(*raw) {
+                result = SWAP.duplicate(slot).ok_or(OsError::NoMemory).and_then(|copy| {
+                    child.try_set_entry(va, swapped_entry(*raw, copy)).map_err(|e| {
+                        SWAP.free(copy);
+                        e
+                    })
+                });
+                return;
+            }
+
+            if raw.get_value(RawL3Entry::AP) == EntryPerm::USER_RW {
+                let sw = raw.get_value(RawL3Entry::SW) | SW_COW;
+                raw.set_value(EntryPerm::USER_RO, RawL3Entry::AP)
+                    .set_value(sw, RawL3Entry::SW);
+            }
+
+            result = child.try_set_entry(va, *raw);
+            if result.is_ok() {
+                let pa = PhysicalAddr::from(raw.get_masked(ADDR_MASK));
+                PAGE_REFS.inc(pa);
+                child.track(va, pa);
+            }
+        });
+
+        tlb_invalidate_asid(self.asid);
+        result.map(|_| child)
+    }
+
+    /// Resolves a write to the copy-on-write page containing the user virtual
+    /// address `va`, making the page writable again. The caller checks that
+    /// the page is meant to be writable.
+    ///
+    /// If other page tables still share the page, its contents are copied to
+    /// a newly allocated page which replaces it in this table. Otherwise this
+    /// table holds the last reference and the page is reclaimed in place.
+    ///
+    /// Returns `OsError::BadAddress` if the page is not a copy-on-write page,
+    /// and `OsError::NoMemory` if a copy could not be allocated.
+    pub fn handle_cow(&mut self, va: VirtualAddr) -> OsResult<()> {
+        if va.as_usize() < USER_IMG_BASE {
+            return Err(OsError::BadAddress);
+        }
+
+        let page = VirtualAddr::from(va.as_usize() & PAGE_MASK);
+        let mut entry = self.get_entry(page);
+        if entry.get_value(RawL3Entry::VALID) != EntryValid::Valid
+            || entry.get_value(RawL3Entry::SW) & SW_COW == 0
+        {
+            return Err(OsError::BadAddress);
+        }
+
+        let old = PhysicalAddr::from(entry.get_masked(ADDR_MASK));
+        if PAGE_REFS.get(old) > 1 {
+            // Shared pages are never evicted, so `old` stays put while a
+            // frame is found for the copy.
+            let new = alloc_frame().ok_or(OsError::NoMemory)?;
+
+            unsafe { core::ptr::copy_nonoverlapping(virt(old), virt(new), PAGE_SIZE) };
+            PAGE_REFS.inc(new);
+            self.untrack(page, old);
+            if PAGE_REFS.dec(old) == 0 {
+                // The other sharers went away while the page was copied.
+                FRAMES.dealloc(old, 1);
+            }
+            entry.set_masked(new.as_u64(), ADDR_MASK);
+        }
+
+        let sw = entry.get_value(RawL3Entry::SW) & !SW_COW;
+        entry
+            .set_value(EntryPerm::USER_RW, RawL3Entry::AP)
+            .set_value(sw, RawL3Entry::SW);
+        self.set_entry(page, entry);
+        tlb_invalidate_asid_va(self.asid, page.as_u64());
+        self.track(page, PhysicalAddr::from(entry.get_masked(ADDR_MASK)));
+
+        Ok(())
+    }
+
+    /// Resolves a translation fault at the user virtual address `va`, which
+    /// lies in a region with permission `perm`: a page that was swapped out
+    /// is read back in, and a page that was never mapped is mapped zeroed.
     ///
-    /// TODO. use Result<T> and make it failurable
-    /// TODO. use perm properly
-    pub fn alloc(&mut self, va: VirtualAddr, _perm: PagePerm) -> &mut [u8] {
-        unimplemented!("alloc()");
+    /// Returns `OsError::NoMemory` if no page could be allocated.
+    pub fn handle_fault(&mut self, va: VirtualAddr, perm: PagePerm) -> OsResult<()> {
+        let page = VirtualAddr::from(va.as_usize() & PAGE_MASK);
+        if self.translate(page, false).is_some() {
+            // Another access already mapped the page.
+            return Ok(());
+        }
+
+        if self.is_swapped(page) {
+            return self.swap_in(page);
+        }
+
+        self.alloc(page, perm)?;
+        Ok(())
+    }
+
+    /// Returns `true` if the page at the user virtual address `va` is
+    /// swapped out.
+    pub fn is_swapped(&self, va: VirtualAddr) -> bool {
+        swapped_slot(self.get_entry(va)).is_some()
+    }
+
+    /// Reads the swapped out page containing the user virtual address `va`
+    /// back into a page frame and maps it again.
+    ///
+    /// Returns `OsError::BadAddress` if the page is not swapped out,
+    /// `OsError::NoMemory` if no frame could be found for it, and an I/O
+    /// error if it could not be read.
+    pub fn swap_in(&mut self, va: VirtualAddr) -> OsResult<()> {
+        if va.as_usize() < USER_IMG_BASE {
+            return Err(OsError::BadAddress);
+        }
+
+        let page = VirtualAddr::from(va.as_usize() & PAGE_MASK);
+        let mut entry = self.get_entry(page);
+        let slot = swapped_slot(entry).ok_or(OsError::BadAddress)?;
+
+        let frame = alloc_frame().ok_or(OsError::NoMemory)?;
+        if let Err(e) = SWAP.swap_in(slot, frame) {
+            FRAMES.dealloc(frame, 1);
+            return Err(e.into());
+        }
+
+        let sw = entry.get_value(RawL3Entry::SW) & !SW_SWAPPED;
+        entry
+            .set_masked(frame.as_u64(), ADDR_MASK)
+            .set_value(sw, RawL3Entry::SW)
+            .set_value(EntryValid::Valid, RawL3Entry::VALID);
+        set_accessed(&mut entry, true);
+        self.set_entry(page, entry);
+        PAGE_REFS.inc(frame);
+        self.track(page, frame);
+
+        Ok(())
+    }
+
+    /// Sets the access flag of the page containing the user virtual address
+    /// `va`, after an access flag fault.
+    ///
+    /// Returns `OsError::BadAddress` if the page is not mapped.
+    pub fn mark_accessed(&mut self, va: VirtualAddr) -> OsResult<()> {
+        if va.as_usize() < USER_IMG_BASE {
+            return Err(OsError::BadAddress);
+        }
+
+        let page = VirtualAddr::from(va.as_usize() & PAGE_MASK);
+        let mut entry = self.get_entry(page);
+        if entry.get_value(RawL3Entry::VALID) != EntryValid::Valid {
+            return Err(OsError::BadAddress);
+        }
+
+        set_accessed(&mut entry, true);
+        self.set_entry(page, entry);
+        tlb_invalidate_asid_va(self.asid, page.as_u64());
+        Ok(())
+    }
+
+    /// Makes the page in the frame at `pa`, mapped at `page` by this table,
+    /// a candidate for eviction.
+    fn track(&self, page: VirtualAddr, pa: PhysicalAddr) {
+        if let Some(entry) = self.walk(page) {
+            SWAP.track(pa, entry, self.asid, page.as_usize());
+        }
+    }
+
+    /// Stops tracking the frame at `pa` for this table's mapping at `page`.
+    fn untrack(&self, page: VirtualAddr, pa: PhysicalAddr) {
+        if let Some(entry) = self.walk(page) {
+            SWAP.untrack(pa, entry);
+        }
     }
 }
 
@@ -193,7 +870,7 @@ impl Deref for KernPageTable {
     }
 }
 
-impl Deref for UserPageTable {
+impl Deref for IoPageTable {
     type Target = PageTable;
 
     fn deref(&self) -> &Self::Target {
@@ -201,6 +878,14 @@ impl Deref for UserPageTable {
     }
 }
 
+impl Deref for UserPageTable {
+    type Target = PageTable;
+
+    fn deref(&self) -> &Self::Target {
+        &self.pt
+    }
+}
+
 impl DerefMut for KernPageTable {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.0
@@ -209,9 +894,24 @@ impl DerefMut for KernPageTable {
 
 impl DerefMut for UserPageTable {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.pt
+    }
+}
+
+impl Drop for UserPageTable {
+    fn drop(&mut self) {
+        self.pt.for_each_entry(|_, entry| {
+            if let Some(slot) = swapped_slot(entry.0) {
+                SWAP.free(slot);
+            } else if let Some(addr) = entry.get_page_addr() {
+                SWAP.untrack(addr, &mut entry.0);
+                if PAGE_REFS.dec(addr) == 0 {
+                    FRAMES.dealloc(addr, 1);
+                }
+            }
+        });
+        ASIDS.free(self.asid);
     }
 }
 
-// FIXME: Implement `Drop` for `UserPageTable`.
 // FIXME: Implement `fmt::Debug` as you need.
@@ -0,0 +1,61 @@
+use crate::mutex::Mutex;
+use crate::param::{IO_BASE_PHYS, PAGE_SIZE};
+use crate::vm::PhysicalAddr;
+
+/// The number of physical pages that reference counts are kept for: every
+/// page of RAM below the peripherals.
+const MAX_PAGES: usize = IO_BASE_PHYS / PAGE_SIZE;
+
+/// Reference counts of physical pages mapped into user page tables.
+///
+/// A page allocated by `UserPageTable::alloc()` starts with one reference,
+/// and every other page table sharing it copy-on-write holds one more. The
+/// page is freed when its last reference is dropped.
+pub struct PageRefs(Mutex<[u16; MAX_PAGES]>);
+
+impl PageRefs {
+    /// Returns a `PageRefs` in which every page has no references.
+    pub const fn new() -> PageRefs {
+        PageRefs(Mutex::new([0; MAX_PAGES]))
+    }
+
+    /// Returns the index of the page containing `pa`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pa` is not in RAM.
+    fn index(pa: PhysicalAddr) -> usize {
+        let index = pa.as_usize() / PAGE_SIZE;
+        if index >= MAX_PAGES {
+            panic!("{:?} is not a page of RAM", pa);
+        }
+        index
+    }
+
+    /// Returns the number of references to the page containing `pa`.
+    pub fn get(&self, pa: PhysicalAddr) -> usize {
+        self.0.lock()[PageRefs::index(pa)] as usize
+    }
+
+    /// Adds a reference to the page containing `pa` and returns the new
+    /// number of references.
+    pub fn inc(&self, pa: PhysicalAddr) -> usize {
+        let mut refs = self.0.lock();
+        let count = &mut refs[PageRefs::index(pa)];
+        *count = count.checked_add(1).expect("too many references to a page");
+        *count as usize
+    }
+
+    /// Drops a reference to the page containing `pa` and returns the number
+    /// of references left. The caller must free the page when this is zero.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the page has no references.
+    pub fn dec(&self, pa: PhysicalAddr) -> usize {
+        let mut refs = self.0.lock();
+        let count = &mut refs[PageRefs::index(pa)];
+        *count = count.checked_sub(1).expect("page has no references");
+        *count as usize
+    }
+}
@@ -0,0 +1,87 @@
+use core::fmt;
+
+use crate::param::PAGE_MASK;
+use crate::vm::{PagePerm, VirtualAddr};
+
+/// A range of user virtual memory that a process is allowed to access.
+///
+/// Pages of a region are not necessarily mapped: a translation fault on an
+/// address inside a region is resolved by mapping a zeroed page with the
+/// region's permission.
+#[derive(Copy, Clone)]
+pub struct Region {
+    start: usize,
+    len: usize,
+    perm: PagePerm,
+}
+
+impl Region {
+    /// Returns a new region of `len` bytes starting at `start`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `start` or `len` is not aligned to the page size.
+    pub fn new(start: VirtualAddr, len: usize, perm: PagePerm) -> Region {
+        assert!(start.as_usize() & !PAGE_MASK == 0 && len & !PAGE_MASK == 0);
+        Region {
+            start: start.as_usize(),
+            len,
+            perm,
+        }
+    }
+
+    /// Returns the first address of the region.
+    pub fn start(&self) -> VirtualAddr {
+        VirtualAddr::from(self.start)
+    }
+
+    /// Returns the length of the region in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the permission pages of this region are mapped with.
+    pub fn perm(&self) -> PagePerm {
+        self.perm
+    }
+
+    /// Sets the length of the region to `len` bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` is not aligned to the page size.
+    pub fn set_len(&mut self, len: usize) {
+        assert!(len & !PAGE_MASK == 0);
+        self.len = len;
+    }
+
+    /// Returns `true` if the region shares at least one byte with the `len`
+    /// bytes starting at `start`. An empty range overlaps nothing.
+    pub fn overlaps(&self, start: VirtualAddr, len: usize) -> bool {
+        let start = start.as_usize();
+        if self.len == 0 || len == 0 {
+            false
+        } else if start >= self.start {
+            start - self.start < self.len
+        } else {
+            self.start - start < len
+        }
+    }
+
+    /// Returns `true` if `va` lies within the region.
+    pub fn contains(&self, va: VirtualAddr) -> bool {
+        // A region may end at the very top of the address space, so compare
+        // offsets instead of computing an end address.
+        va.as_usize() >= self.start && va.as_usize() - self.start < self.len
+    }
+}
+
+impl fmt::Debug for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Region")
+            .field("start", &self.start())
+            .field("len", &self.len)
+            .field("perm", &self.perm)
+            .finish()
+    }
+}
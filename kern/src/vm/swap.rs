@@ -0,0 +1,351 @@
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use aarch64::tlb_invalidate_asid_va;
+use aarch64::vmsa::RawL3Entry;
+use fat32::traits::{BlockDevice, Entry, FileSystem};
+use fat32::vfat::VFatHandle;
+use shim::io;
+
+use crate::console::kprintln;
+use crate::fs::sd::Sd;
+use crate::mutex::Mutex;
+use crate::param::{IO_BASE_PHYS, PAGE_SIZE, SWAP_FILE};
+use crate::vm::pagetable::{is_accessed, set_accessed, swapped_entry};
+use crate::vm::{PhysicalAddr, PAGE_REFS};
+use crate::FILESYSTEM;
+
+/// The number of page frames the swap keeps owners for: every page of RAM
+/// below the peripherals.
+const MAX_PAGES: usize = IO_BASE_PHYS / PAGE_SIZE;
+
+/// The number of SD card sectors holding a swapped out page.
+const SECTORS_PER_SLOT: u64 = (PAGE_SIZE / 512) as u64;
+
+/// The numbers of page-sized slots of the swap file, and of pages moved.
+#[derive(Debug, Copy, Clone)]
+pub struct SwapStats {
+    /// The number of slots.
+    pub total: usize,
+    /// The number of slots not holding a page.
+    pub free: usize,
+    /// The number of pages written out to the swap file so far.
+    pub evicted: usize,
+    /// The number of pages read back from the swap file so far.
+    pub swapped_in: usize,
+}
+
+impl fmt::Display for SwapStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} slots of {} KiB: {} used, {} free; {} pages out, {} in",
+            self.total,
+            PAGE_SIZE / 1024,
+            self.total - self.free,
+            self.free,
+            self.evicted,
+            self.swapped_in
+        )
+    }
+}
+
+/// The page descriptor mapping a user page that may be evicted, and where
+/// the page is mapped, for TLB maintenance.
+#[derive(Copy, Clone)]
+struct Owner {
+    entry: *mut RawL3Entry,
+    asid: u16,
+    va: usize,
+}
+
+struct Swap {
+    /// The SD card sectors of the swap file, as `(first, count)` extents.
+    extents: Vec<(u64, u64)>,
+    /// A bitmap of the slots: a set bit marks a slot holding a page.
+    slots: Vec<u64>,
+    total: usize,
+    free: usize,
+    /// The owner of every evictable page frame, indexed by frame number.
+    owners: Vec<Option<Owner>>,
+    /// The other owners of frames shared after a fork, by frame number. One
+    /// of them takes over when the owner stops mapping the frame, so that
+    /// the page table left holding it can have it evicted.
+    sharers: BTreeMap<usize, Vec<Owner>>,
+    /// The frame the clock hand points at.
+    hand: usize,
+    evicted: usize,
+    swapped_in: usize,
+}
+
+// The page descriptors owners point to are only touched with `SWAP` locked
+// or by the page table holding them, which untracks them before it drops
+// them.
+unsafe impl Send for Swap {}
+
+impl Swap {
+    /// Returns the SD card sector holding the `index`th sector of `slot`.
+    fn sector(&self, slot: usize, index: u64) -> io::Result<u64> {
+        let mut offset = slot as u64 * SECTORS_PER_SLOT + index;
+        for &(first, count) in self.extents.iter() {
+            if offset < count {
+                return Ok(first + offset);
+            }
+            offset -= count;
+        }
+        Err(io::Error::new(io::ErrorKind::InvalidInput, "slot out of range"))
+    }
+
+    fn read_slot(&self, slot: usize, page: *mut u8) -> io::Result<()> {
+        for i in 0..SECTORS_PER_SLOT {
+            let buf = unsafe { core::slice::from_raw_parts_mut(page.add(i as usize * 512), 512) };
+            Sd.read_sector(self.sector(slot, i)?, buf)?;
+        }
+        Ok(())
+    }
+
+    fn write_slot(&self, slot: usize, page: *const u8) -> io::Result<()> {
+        for i in 0..SECTORS_PER_SLOT {
+            let buf = unsafe { core::slice::from_raw_parts(page.add(i as usize * 512), 512) };
+            Sd.write_sector(self.sector(slot, i)?, buf)?;
+        }
+        Ok(())
+    }
+
+    fn alloc_slot(&mut self) -> Option<usize> {
+        for (i, word) in self.slots.iter_mut().enumerate() {
+            if *word != !0 {
+                let slot = i * 64 + (!*word).trailing_zeros() as usize;
+                if slot >= self.total {
+                    return None;
+                }
+                *word |= 1 << (slot % 64);
+                self.free -= 1;
+                return Some(slot);
+            }
+        }
+        None
+    }
+
+    fn free_slot(&mut self, slot: usize) {
+        let bit = 1 << (slot % 64);
+        assert!(self.slots[slot / 64] & bit != 0, "swap slot {} freed twice", slot);
+        self.slots[slot / 64] &= !bit;
+        self.free += 1;
+    }
+
+    /// Runs the clock over the tracked frames until it finds one that was
+    /// not accessed since the hand last passed, clearing the access flags it
+    /// passes. Pages shared by several page tables are skipped.
+    fn evict(&mut self) -> Option<PhysicalAddr> {
+        if self.free == 0 {
+            return None;
+        }
+
+        // After one turn every access flag is clear, so two turns suffice.
+        for _ in 0..2 * self.owners.len() {
+            let frame = self.hand;
+            self.hand = (self.hand + 1) % self.owners.len();
+
+            let owner = match self.owners[frame] {
+                Some(owner) => owner,
+                None => continue,
+            };
+            let pa = PhysicalAddr::from(frame * PAGE_SIZE);
+            if PAGE_REFS.get(pa) != 1 {
+                continue;
+            }
+
+            let entry = unsafe { &mut *owner.entry };
+            if is_accessed(*entry) {
+                set_accessed(entry, false);
+                tlb_invalidate_asid_va(owner.asid, owner.va as u64);
+                continue;
+            }
+
+            let slot = self.alloc_slot()?;
+            let mapped = *entry;
+            *entry = swapped_entry(mapped, slot);
+            tlb_invalidate_asid_va(owner.asid, owner.va as u64);
+
+            if let Err(e) = self.write_slot(slot, pa.to_virtual().as_usize() as *const u8) {
+                kprintln!("swap: failed to write slot {}: {:?}", slot, e);
+                *entry = mapped;
+                self.free_slot(slot);
+                return None;
+            }
+
+            self.owners[frame] = None;
+            self.sharers.remove(&frame);
+            PAGE_REFS.dec(pa);
+            self.evicted += 1;
+            return Some(pa);
+        }
+
+        None
+    }
+}
+
+/// Thread-safe (locking) manager of the swap file, to which user pages are
+/// evicted when no page frame is left.
+///
+/// The swap file is a preallocated file at `SWAP_FILE` on the FAT volume,
+/// split into page-sized slots. Its sectors are looked up once and then
+/// accessed on the SD card directly, bypassing the file system's cache.
+///
+/// Pages are chosen with the clock (second-chance) algorithm over the page
+/// frames of user pages. A page is tracked with the page descriptors that
+/// map it; once a single page table maps it, the clock hand clears its `AF`
+/// bit, so that the next access faults and sets it again, and evicts it if
+/// it is still clear when the hand comes back. Pages shared after a fork are
+/// never evicted while they are shared. An evicted page's descriptor is made invalid and records the
+/// slot, until the page is swapped back in on fault.
+pub struct SwapManager(Mutex<Option<Swap>>);
+
+impl SwapManager {
+    /// Returns an uninitialized `SwapManager`, with which nothing is swapped.
+    pub const fn uninitialized() -> Self {
+        SwapManager(Mutex::new(None))
+    }
+
+    /// Initializes swapping with the swap file at `SWAP_FILE`. If there is no
+    /// such file, swapping stays disabled.
+    ///
+    /// The file system must be initialized before.
+    pub fn initialize(&self) {
+        let file = match FILESYSTEM.open(SWAP_FILE).map(|entry| entry.into_file()) {
+            Ok(Some(file)) => file,
+            _ => {
+                kprintln!("swap: no {}, swapping disabled", SWAP_FILE);
+                return;
+            }
+        };
+
+        let extents = match file.vfat.lock(|vfat| vfat.chain_extents(file.start)) {
+            Ok(extents) => extents,
+            Err(e) => {
+                kprintln!("swap: failed to read {}: {:?}", SWAP_FILE, e);
+                return;
+            }
+        };
+        let sectors: u64 = extents.iter().map(|&(_, count)| count).sum();
+        let total = core::cmp::min(file.size as u64, sectors * 512) as usize / PAGE_SIZE;
+
+        *self.0.lock() = Some(Swap {
+            extents,
+            slots: vec![0; (total + 63) / 64],
+            total,
+            free: total,
+            owners: vec![None; MAX_PAGES],
+            sharers: BTreeMap::new(),
+            hand: 0,
+            evicted: 0,
+            swapped_in: 0,
+        });
+    }
+
+    /// Makes the user page in the frame at `pa`, mapped by the page
+    /// descriptor `entry` at `va` in the address space tagged with `asid`,
+    /// a candidate for eviction. A frame that is already tracked with
+    /// another descriptor is shared: `entry` is kept as one of its sharers.
+    pub fn track(&self, pa: PhysicalAddr, entry: *mut RawL3Entry, asid: u16, va: usize) {
+        if let Some(swap) = self.0.lock().as_mut() {
+            let frame = pa.as_usize() / PAGE_SIZE;
+            let new = Owner { entry, asid, va };
+            match swap.owners[frame] {
+                Some(owner) if owner.entry != entry => {
+                    let sharers = swap.sharers.entry(frame).or_insert_with(Vec::new);
+                    sharers.retain(|sharer| sharer.entry != entry);
+                    sharers.push(new);
+                }
+                _ => swap.owners[frame] = Some(new),
+            }
+        }
+    }
+
+    /// Stops tracking the frame at `pa` with the page descriptor `entry`. If
+    /// `entry` was its owner, one of its sharers becomes the owner. Must be
+    /// called before `entry` stops mapping it.
+    pub fn untrack(&self, pa: PhysicalAddr, entry: *mut RawL3Entry) {
+        if let Some(swap) = self.0.lock().as_mut() {
+            let frame = pa.as_usize() / PAGE_SIZE;
+            let mut sharers = swap.sharers.remove(&frame).unwrap_or_default();
+            if swap.owners[frame].map_or(false, |owner| owner.entry == entry) {
+                swap.owners[frame] = sharers.pop();
+            } else {
+                sharers.retain(|sharer| sharer.entry != entry);
+            }
+            if !sharers.is_empty() {
+                swap.sharers.insert(frame, sharers);
+            }
+        }
+    }
+
+    /// Evicts a user page to the swap file and returns its frame, which the
+    /// caller now owns. Returns `None` if swapping is disabled, the swap file
+    /// is full, or no page can be evicted.
+    pub fn evict(&self) -> Option<PhysicalAddr> {
+        self.0.lock().as_mut()?.evict()
+    }
+
+    /// Reads the page in `slot` into the frame at `pa` and frees the slot.
+    pub fn swap_in(&self, slot: usize, pa: PhysicalAddr) -> io::Result<()> {
+        let mut swap = self.0.lock();
+        let swap = swap.as_mut().expect("swap uninitialized");
+        swap.read_slot(slot, pa.to_virtual().as_usize() as *mut u8)?;
+        swap.free_slot(slot);
+        swap.swapped_in += 1;
+        Ok(())
+    }
+
+    /// Copies the page in `slot` to a new slot and returns the new slot, or
+    /// `None` if no slot is free or the copy failed.
+    pub fn duplicate(&self, slot: usize) -> Option<usize> {
+        let mut swap = self.0.lock();
+        let swap = swap.as_mut().expect("swap uninitialized");
+        let copy = swap.alloc_slot()?;
+
+        // Sectors are transferred in 32-bit words.
+        let mut page = vec![0u32; PAGE_SIZE / 4];
+        let buf = page.as_mut_ptr() as *mut u8;
+        match swap.read_slot(slot, buf).and_then(|_| swap.write_slot(copy, buf)) {
+            Ok(()) => Some(copy),
+            Err(e) => {
+                kprintln!("swap: failed to copy slot {}: {:?}", slot, e);
+                swap.free_slot(copy);
+                None
+            }
+        }
+    }
+
+    /// Frees `slot`, whose page is no longer mapped.
+    pub fn free(&self, slot: usize) {
+        self.0
+            .lock()
+            .as_mut()
+            .expect("swap uninitialized")
+            .free_slot(slot)
+    }
+
+    /// Returns the swap file's slot counts, or `None` if swapping is
+    /// disabled.
+    pub fn stats(&self) -> Option<SwapStats> {
+        self.0.lock().as_ref().map(|swap| SwapStats {
+            total: swap.total,
+            free: swap.free,
+            evicted: swap.evicted,
+            swapped_in: swap.swapped_in,
+        })
+    }
+}
+
+impl fmt::Debug for SwapManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.stats() {
+            Some(stats) => write!(f, "SwapManager {{ {} }}", stats),
+            None => write!(f, "Not yet initialized"),
+        }
+    }
+}
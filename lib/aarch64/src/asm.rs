@@ -35,6 +35,98 @@ pub fn sev() {
     unsafe { asm!("sev" ::::"volatile") };
 }
 
+/// Invalidate all EL1&0 TLB entries in the inner shareable domain.
+#[inline(always)]
+pub fn tlb_invalidate_all() {
+    unsafe {
+        asm!("dsb ishst
+              tlbi vmalle1is
+              dsb ish
+              isb"
+             :::: "volatile");
+    }
+}
+
+/// Invalidate the EL1&0 TLB entries for virtual address `va`, for all ASIDs,
+/// in the inner shareable domain.
+#[inline(always)]
+pub fn tlb_invalidate_va(va: u64) {
+    unsafe {
+        asm!("dsb ishst
+              tlbi vaae1is, $0
+              dsb ish
+              isb"
+             :: "r"((va >> 12) & 0xfff_ffff_ffff) :: "volatile");
+    }
+}
+
+/// Invalidate the EL1&0 TLB entries for virtual address `va` tagged with
+/// `asid`, in the inner shareable domain. Global entries for `va` are
+/// invalidated too.
+#[inline(always)]
+pub fn tlb_invalidate_asid_va(asid: u16, va: u64) {
+    unsafe {
+        asm!("dsb ishst
+              tlbi vae1is, $0
+              dsb ish
+              isb"
+             :: "r"(((asid as u64) << 48) | ((va >> 12) & 0xfff_ffff_ffff)) :: "volatile");
+    }
+}
+
+/// Invalidate the non-global EL1&0 TLB entries tagged with `asid`, in the
+/// inner shareable domain.
+#[inline(always)]
+pub fn tlb_invalidate_asid(asid: u16) {
+    unsafe {
+        asm!("dsb ishst
+              tlbi aside1is, $0
+              dsb ish
+              isb"
+             :: "r"((asid as u64) << 48) :: "volatile");
+    }
+}
+
+/// Translate the virtual address `va` for a read at EL1 with the current
+/// translation tables. Returns the resulting `PAR_EL1`.
+#[inline(always)]
+pub fn translate_read(va: u64) -> u64 {
+    let par;
+    unsafe {
+        asm!("at s1e1r, $1
+              isb
+              mrs $0, PAR_EL1"
+             : "=r"(par) : "r"(va) :: "volatile");
+    }
+    par
+}
+
+/// Make the instruction at virtual address `va`, which was just written,
+/// visible to instruction fetches.
+#[inline(always)]
+pub fn sync_icache(va: u64) {
+    unsafe {
+        asm!("dc cvau, $0
+              dsb ish
+              ic ivau, $0
+              dsb ish
+              isb"
+             :: "r"(va) :: "volatile");
+    }
+}
+
+/// Clean and invalidate the data cache line holding virtual address `va` to
+/// the point of coherency, so that it is seen by accesses that bypass the
+/// caches.
+#[inline(always)]
+pub fn clean_dcache(va: u64) {
+    unsafe {
+        asm!("dc civac, $0
+              dsb sy"
+             :: "r"(va) :: "volatile");
+    }
+}
+
 /// Enable (unmask) interrupts
 #[inline(always)]
 pub unsafe fn sti() {
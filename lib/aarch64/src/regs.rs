@@ -225,3 +225,42 @@ defreg!(CNTHCTL_EL2, [
 ]);
 
 defreg!(CNTVOFF_EL2);
+
+// (ref. D10.8 Generic Timer registers)
+defreg!(CNTFRQ_EL0);
+defreg!(CNTPCT_EL0);
+defreg!(CNTVCT_EL0);
+
+defreg!(CNTP_CTL_EL0, [
+    ISTATUS [2-2], // The timer condition is met
+    IMASK   [1-1], // The timer interrupt is masked
+    ENABLE  [0-0], // The timer is enabled
+]);
+defreg!(CNTP_TVAL_EL0);
+defreg!(CNTP_CVAL_EL0);
+
+defreg!(CNTV_CTL_EL0, [
+    ISTATUS [2-2], // The timer condition is met
+    IMASK   [1-1], // The timer interrupt is masked
+    ENABLE  [0-0], // The timer is enabled
+]);
+defreg!(CNTV_TVAL_EL0);
+defreg!(CNTV_CVAL_EL0);
+
+// (ref. D13.3.11 Monitor Debug System Control Register)
+defreg!(MDSCR_EL1, [
+    MDE  [15-15], // Monitor debug events
+    KDE  [13-13], // Local (kernel) debug enable
+    SS   [00-00], // Software step control
+]);
+
+// (ref. D13.3.25 OS Lock Access Register)
+defreg!(OSLAR_EL1, [
+    OSLK [00-00], // The OS Lock
+]);
+
+// (ref. D13.2.98 Physical Address Register)
+defreg!(PAR_EL1, [
+    PA   [47-12], // The output address of a successful translation
+    F    [00-00], // The translation aborted
+]);
@@ -58,8 +58,12 @@ defbit!(RawL2Entry, [
 ]);
 
 defbit!(RawL3Entry, [
+    SW    [58-55], // Reserved for software use
+    UXN   [54-54], // Unprivileged (EL0) execute never
+    PXN   [53-53], // Privileged (EL1) execute never
     ADDR  [47-16],
 
+    NG    [11-11], // Not global: the entry belongs to the current ASID
     AF    [10-10],
     SH    [09-08],
     AP    [07-06],
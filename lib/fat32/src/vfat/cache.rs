@@ -60,13 +60,13 @@ impl CachedPartition {
 
     /// Returns the number of physical sectors that corresponds to
     /// one logical sector.
-    fn factor(&self) -> u64 {
+    pub(crate) fn factor(&self) -> u64 {
         self.partition.sector_size / self.device.sector_size()
     }
 
     /// Maps a user's request for a sector `virt` to the physical sector.
     /// Returns `None` if the virtual sector number is out of range.
-    fn virtual_to_physical(&self, virt: u64) -> Option<u64> {
+    pub(crate) fn virtual_to_physical(&self, virt: u64) -> Option<u64> {
         if virt >= self.partition.num_sectors {
             return None;
         }
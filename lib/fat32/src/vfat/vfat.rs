@@ -190,6 +190,41 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
 
 
 
+    /// Returns the physical sectors of the device holding the cluster chain
+    /// starting at `start`, as `(first sector, number of sectors)` extents in
+    /// chain order. Consecutive clusters are merged into one extent.
+    ///
+    /// This lets a caller access a file's data on the device directly,
+    /// bypassing the sector cache.
+    pub fn chain_extents(&mut self, start: Cluster) -> io::Result<Vec<(u64, u64)>> {
+        let sectors = self.sectors_per_cluster as u64 * self.device.factor();
+        let mut extents: Vec<(u64, u64)> = Vec::new();
+        let mut cluster = start;
+        if cluster.cluster_number() < 2 {
+            // An empty file has no clusters.
+            return Ok(extents);
+        }
+        loop {
+            let logical = self.data_start_sector
+                + (cluster.cluster_number() as u64 - 2) * self.sectors_per_cluster as u64;
+            let first = self.device.virtual_to_physical(logical).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "cluster out of range")
+            })?;
+
+            match extents.last_mut() {
+                Some(last) if last.0 + last.1 == first => last.1 += sectors,
+                _ => extents.push((first, sectors)),
+            }
+
+            match self.fat_entry(cluster)?.status() {
+                Status::Data(next) => cluster = next,
+                Status::Eoc(_) => break,
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid entry")),
+            }
+        }
+        Ok(extents)
+    }
+
     pub fn find_cluster(&mut self, start: Cluster, offset: usize) -> io::Result<(Cluster, usize)>
     {
         let size = self.bytes_per_sector as usize * self.sectors_per_cluster as usize;
@@ -0,0 +1,129 @@
+//! Program startup support for user processes.
+//!
+//! The kernel starts a process with the following layout at its stack
+//! pointer, from lower to higher addresses:
+//!
+//! ```text
+//! sp -> argc
+//!       argv[0], ..., argv[argc - 1], NULL
+//!       envp[0], ..., NULL
+//!       (type, value) auxiliary vector pairs, terminated by AT_NULL
+//!       16 random bytes, argument and environment strings
+//! ```
+//!
+//! For convenience, `argc`, `argv` and `envp` are also passed in `x0`, `x1`
+//! and `x2`, so `_start` can receive them as ordinary arguments and hand them
+//! to `start()`.
+
+use core::slice;
+use core::str;
+
+use crate::syscall::exit;
+use crate::AT_NULL;
+
+/// The command-line arguments, environment and auxiliary vector of a user
+/// process.
+#[derive(Copy, Clone)]
+pub struct Args {
+    argc: usize,
+    argv: *const *const u8,
+    envp: *const *const u8,
+}
+
+impl Args {
+    /// Returns an `Args` wrapping the values the kernel passed to `_start`.
+    ///
+    /// # Safety
+    ///
+    /// `argv` must point to `argc` NUL-terminated strings followed by a null
+    /// pointer, and `envp` must point to a null-terminated array of
+    /// NUL-terminated strings followed by the auxiliary vector.
+    pub unsafe fn from_raw(argc: usize, argv: *const *const u8, envp: *const *const u8) -> Args {
+        Args { argc, argv, envp }
+    }
+
+    /// Returns the number of arguments, including the program name.
+    pub fn len(&self) -> usize {
+        self.argc
+    }
+
+    /// Returns the `i`th argument, or `None` if `i` is out of range or the
+    /// argument is not valid UTF-8.
+    pub fn get(&self, i: usize) -> Option<&'static str> {
+        if i >= self.argc {
+            return None;
+        }
+        unsafe { c_str(*self.argv.add(i)) }
+    }
+
+    /// Returns an iterator over the arguments.
+    pub fn iter(&self) -> impl Iterator<Item = &'static str> {
+        let args = *self;
+        (0..self.argc).filter_map(move |i| args.get(i))
+    }
+
+    /// Returns an iterator over the `KEY=VALUE` environment strings.
+    pub fn env(&self) -> impl Iterator<Item = &'static str> {
+        let envp = self.envp;
+        (0..)
+            .map(move |i| unsafe { *envp.add(i) })
+            .take_while(|ptr| !ptr.is_null())
+            .filter_map(|ptr| unsafe { c_str(ptr) })
+    }
+
+    /// Returns the value of the environment variable `key`, if it is set.
+    pub fn var(&self, key: &str) -> Option<&'static str> {
+        self.env().find_map(|entry| {
+            let mut parts = entry.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(k), Some(value)) if k == key => Some(value),
+                _ => None,
+            }
+        })
+    }
+
+    /// Returns the value of the auxiliary vector entry of type `ty` (one of
+    /// the `AT_*` constants), if the kernel provided it.
+    pub fn aux(&self, ty: u64) -> Option<u64> {
+        unsafe {
+            let mut ptr = self.envp;
+            while !(*ptr).is_null() {
+                ptr = ptr.add(1);
+            }
+
+            let mut auxv = ptr.add(1) as *const u64;
+            while *auxv != AT_NULL {
+                if *auxv == ty {
+                    return Some(*auxv.add(1));
+                }
+                auxv = auxv.add(2);
+            }
+        }
+        None
+    }
+}
+
+/// Returns the NUL-terminated string at `ptr` if it is valid UTF-8.
+unsafe fn c_str(ptr: *const u8) -> Option<&'static str> {
+    let mut len = 0;
+    while *ptr.add(len) != 0 {
+        len += 1;
+    }
+    str::from_utf8(slice::from_raw_parts(ptr, len)).ok()
+}
+
+/// Calls `main` with the arguments the kernel passed to `_start`, then exits
+/// the process.
+///
+/// # Safety
+///
+/// The arguments must be exactly those the kernel passed to `_start`.
+pub unsafe fn start(
+    argc: usize,
+    argv: *const *const u8,
+    envp: *const *const u8,
+    main: fn(Args),
+) -> ! {
+    main(Args::from_raw(argc, argv, envp));
+    exit();
+}
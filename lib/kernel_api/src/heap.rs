@@ -0,0 +1,155 @@
+//! The user-space heap, installed as the global allocator so that user
+//! programs can use `extern crate alloc`.
+//!
+//! Small allocations are served from power-of-two size classes carved out of
+//! memory obtained with `sbrk()`; a freed block goes back on the free list of
+//! its class. Larger allocations get their own `mmap()` mapping, which is
+//! unmapped when the allocation is freed.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{spin_loop_hint, AtomicBool, Ordering};
+
+use crate::syscall::{brk, mmap, munmap, sbrk};
+use crate::{PROT_READ, PROT_WRITE};
+
+/// The smallest page size the kernel may be configured with. `mmap()` returns
+/// addresses aligned to at least this.
+const PAGE_SIZE: usize = 4 * 1024;
+
+/// The smallest size class is `1 << MIN_CLASS_SHIFT` bytes, large enough to
+/// hold a `FreeBlock`.
+const MIN_CLASS_SHIFT: usize = 4;
+/// The number of size classes; the largest holds blocks of `MAX_BLOCK` bytes.
+const NUM_CLASSES: usize = 12;
+const MAX_BLOCK: usize = 1 << (MIN_CLASS_SHIFT + NUM_CLASSES - 1);
+
+/// Size classes are refilled `CHUNK_SIZE` bytes at a time. Chunks are aligned
+/// to their size, so every block is aligned to its own size.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[global_allocator]
+static ALLOCATOR: Allocator = Allocator::new();
+
+#[alloc_error_handler]
+fn oom(layout: Layout) -> ! {
+    crate::println!("out of memory: {:?}", layout);
+    crate::syscall::exit()
+}
+
+/// A free block, linked into the free list of its size class.
+struct FreeBlock {
+    next: *mut FreeBlock,
+}
+
+struct Heap {
+    free: [*mut FreeBlock; NUM_CLASSES],
+}
+
+impl Heap {
+    /// Returns the size class for `layout`, or `None` if it is too large for
+    /// any class.
+    fn class(layout: Layout) -> Option<usize> {
+        let size = layout
+            .size()
+            .max(layout.align())
+            .max(1 << MIN_CLASS_SHIFT)
+            .checked_next_power_of_two()?;
+        if size > MAX_BLOCK {
+            return None;
+        }
+        Some(size.trailing_zeros() as usize - MIN_CLASS_SHIFT)
+    }
+
+    unsafe fn alloc(&mut self, class: usize) -> *mut u8 {
+        if self.free[class].is_null() && !self.refill(class) {
+            return ptr::null_mut();
+        }
+
+        let block = self.free[class];
+        self.free[class] = (*block).next;
+        block as *mut u8
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, class: usize) {
+        let block = ptr as *mut FreeBlock;
+        (*block).next = self.free[class];
+        self.free[class] = block;
+    }
+
+    /// Grows the heap by a chunk and splits it into blocks of `class`.
+    /// Returns `false` if the heap could not be grown.
+    unsafe fn refill(&mut self, class: usize) -> bool {
+        let current = match brk(ptr::null_mut()) {
+            Ok(current) => current as usize,
+            Err(_) => return false,
+        };
+        let pad = current.wrapping_neg() & (CHUNK_SIZE - 1);
+        let chunk = match sbrk((pad + CHUNK_SIZE) as isize) {
+            Ok(old) => old as usize + pad,
+            Err(_) => return false,
+        };
+
+        let size = 1 << (class + MIN_CLASS_SHIFT);
+        for block in (chunk..chunk + CHUNK_SIZE).step_by(size).rev() {
+            self.dealloc(block as *mut u8, class);
+        }
+        true
+    }
+}
+
+/// The global allocator: a `Heap` behind a spin lock.
+struct Allocator {
+    locked: AtomicBool,
+    heap: UnsafeCell<Heap>,
+}
+
+unsafe impl Sync for Allocator {}
+
+impl Allocator {
+    const fn new() -> Allocator {
+        Allocator {
+            locked: AtomicBool::new(false),
+            heap: UnsafeCell::new(Heap {
+                free: [ptr::null_mut(); NUM_CLASSES],
+            }),
+        }
+    }
+
+    fn with_heap<R, F: FnOnce(&mut Heap) -> R>(&self, f: F) -> R {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            spin_loop_hint();
+        }
+
+        let result = f(unsafe { &mut *self.heap.get() });
+        self.locked.store(false, Ordering::Release);
+        result
+    }
+}
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match Heap::class(layout) {
+            Some(class) => self.with_heap(|heap| heap.alloc(class)),
+            None if layout.align() <= PAGE_SIZE => {
+                mmap(ptr::null_mut(), layout.size(), PROT_READ | PROT_WRITE)
+                    .unwrap_or(ptr::null_mut())
+            }
+            None => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match Heap::class(layout) {
+            Some(class) => self.with_heap(|heap| heap.dealloc(ptr, class)),
+            None => {
+                let _ = munmap(ptr, layout.size());
+            }
+        }
+    }
+}
@@ -1,10 +1,15 @@
 #![feature(asm)]
+#![feature(alloc_error_handler)]
 #![no_std]
 
 use core::fmt;
 
 use shim::io;
 
+#[cfg(feature = "user-space")]
+pub mod crt0;
+#[cfg(feature = "user-space")]
+mod heap;
 #[cfg(feature = "user-space")]
 pub mod syscall;
 
@@ -79,3 +84,24 @@ pub const NR_TIME: usize = 2;
 pub const NR_EXIT: usize = 3;
 pub const NR_WRITE: usize = 4;
 pub const NR_GETPID: usize = 5;
+pub const NR_READ: usize = 6;
+pub const NR_FORK: usize = 7;
+pub const NR_BRK: usize = 8;
+pub const NR_MMAP: usize = 9;
+pub const NR_MUNMAP: usize = 10;
+pub const NR_MPROTECT: usize = 11;
+
+pub const STDIN: u64 = 0;
+pub const STDOUT: u64 = 1;
+pub const STDERR: u64 = 2;
+
+// Page protection flags for `mmap` and `mprotect`.
+pub const PROT_READ: u64 = 1 << 0;
+pub const PROT_WRITE: u64 = 1 << 1;
+pub const PROT_EXEC: u64 = 1 << 2;
+
+// Auxiliary vector entry types, as in the System V ABI.
+pub const AT_NULL: u64 = 0;
+pub const AT_PAGESZ: u64 = 6;
+pub const AT_ENTRY: u64 = 9;
+pub const AT_RANDOM: u64 = 25;
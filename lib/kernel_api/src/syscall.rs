@@ -46,21 +46,210 @@ pub fn exit() -> ! {
     unimplemented!("exit()");
 }
 
-pub fn write(b: u8) {
-    unimplemented!("write()");
+/// Writes the bytes in `buf` to the file descriptor `fd`. Returns the number
+/// of bytes written.
+pub fn write(fd: u64, buf: &[u8]) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut written: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(written), "=r"(ecode)
+             : "r"(fd), "r"(buf.as_ptr() as u64), "r"(buf.len() as u64), "i"(NR_WRITE)
+             : "x0", "x1", "x2", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, written as usize)
+}
+
+/// Reads from the file descriptor `fd` into `buf`, blocking until at least
+/// one byte is available. Returns the number of bytes read.
+pub fn read(fd: u64, buf: &mut [u8]) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut read: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(read), "=r"(ecode)
+             : "r"(fd), "r"(buf.as_mut_ptr() as u64), "r"(buf.len() as u64), "i"(NR_READ)
+             : "x0", "x1", "x2", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, read as usize)
 }
 
 pub fn getpid() -> u64 {
     unimplemented!("getpid()");
 }
 
+/// Creates a copy of the calling process. Returns the new process's ID in the
+/// parent and `0` in the child.
+pub fn fork() -> OsResult<u64> {
+    let mut ecode: u64;
+    let mut pid: u64;
+
+    unsafe {
+        asm!("svc $2
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(pid), "=r"(ecode)
+             : "i"(NR_FORK)
+             : "x0", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, pid)
+}
+
+/// Sets the program break to `addr`, or queries it if `addr` is null.
+/// Returns the program break after the call.
+pub fn brk(addr: *mut u8) -> OsResult<*mut u8> {
+    let mut ecode: u64;
+    let mut brk: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              svc $3
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(brk), "=r"(ecode)
+             : "r"(addr as u64), "i"(NR_BRK)
+             : "x0", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, brk as *mut u8)
+}
 
-struct Console;
+/// Moves the program break by `incr` bytes. Returns the previous program
+/// break, which is the start of the new memory when growing the heap.
+pub fn sbrk(incr: isize) -> OsResult<*mut u8> {
+    let old = brk(core::ptr::null_mut())?;
+    if incr != 0 {
+        brk(old.wrapping_offset(incr))?;
+    }
+    Ok(old)
+}
+
+/// Maps `len` bytes of zero-filled memory with protection `prot`, a
+/// combination of `PROT_READ`, `PROT_WRITE` and `PROT_EXEC`. `addr`, if it is
+/// not null, is the preferred address of the mapping. Returns the address of
+/// the mapping.
+pub fn mmap(addr: *mut u8, len: usize, prot: u64) -> OsResult<*mut u8> {
+    let mut ecode: u64;
+    let mut va: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(va), "=r"(ecode)
+             : "r"(addr as u64), "r"(len as u64), "r"(prot), "i"(NR_MMAP)
+             : "x0", "x1", "x2", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, va as *mut u8)
+}
+
+/// Unmaps the `len` bytes starting at the page-aligned address `addr`, which
+/// must have been mapped by `mmap()`.
+pub fn munmap(addr: *mut u8, len: usize) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("mov x0, $1
+              mov x1, $2
+              svc $3
+              mov $0, x7"
+             : "=r"(ecode)
+             : "r"(addr as u64), "r"(len as u64), "i"(NR_MUNMAP)
+             : "x0", "x1", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Changes the protection of the `len` bytes starting at the page-aligned
+/// address `addr` to `prot`, a combination of `PROT_READ`, `PROT_WRITE` and
+/// `PROT_EXEC`.
+pub fn mprotect(addr: *mut u8, len: usize, prot: u64) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("mov x0, $1
+              mov x1, $2
+              mov x2, $3
+              svc $4
+              mov $0, x7"
+             : "=r"(ecode)
+             : "r"(addr as u64), "r"(len as u64), "r"(prot), "i"(NR_MPROTECT)
+             : "x0", "x1", "x2", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+
+/// Buffers formatted output so that a whole `print!` usually costs a single
+/// `write` system call.
+struct Console {
+    buf: [u8; Console::SIZE],
+    len: usize,
+}
+
+impl Console {
+    const SIZE: usize = 256;
+
+    fn new() -> Console {
+        Console {
+            buf: [0; Console::SIZE],
+            len: 0,
+        }
+    }
+
+    /// Writes out everything buffered so far.
+    fn flush(&mut self) -> fmt::Result {
+        let mut pending = &self.buf[..self.len];
+        while !pending.is_empty() {
+            match write(STDOUT, pending) {
+                Ok(0) | Err(_) => return Err(fmt::Error),
+                Ok(n) => pending = &pending[n..],
+            }
+        }
+        self.len = 0;
+        Ok(())
+    }
+}
 
 impl fmt::Write for Console {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for b in s.bytes() {
-            write(b);
+        let mut bytes = s.as_bytes();
+        while !bytes.is_empty() {
+            if self.len == Console::SIZE {
+                self.flush()?;
+            }
+            let n = core::cmp::min(bytes.len(), Console::SIZE - self.len);
+            self.buf[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+            self.len += n;
+            bytes = &bytes[n..];
         }
         Ok(())
     }
@@ -81,6 +270,7 @@ macro_rules! println {
 }
 
 pub fn vprint(args: fmt::Arguments) {
-    let mut c = Console;
+    let mut c = Console::new();
     c.write_fmt(args).unwrap();
+    c.flush().unwrap();
 }
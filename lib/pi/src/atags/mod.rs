@@ -3,8 +3,10 @@ mod raw;
 
 pub use self::atag::*;
 
+use crate::common::KERNEL_BASE;
+
 /// The address at which the firmware loads the ATAGS.
-const ATAG_BASE: usize = 0x100;
+const ATAG_BASE: usize = KERNEL_BASE + 0x100;
 
 /// An iterator over the ATAGS on this system.
 pub struct Atags {
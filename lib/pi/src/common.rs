@@ -1,6 +1,27 @@
-/// The address where I/O peripherals are mapped to.
-pub const IO_BASE: usize = 0x3F000000;
-pub const IO_BASE_END: usize = 0x40000000;
+/// The virtual address at which physical memory is mapped. With the
+/// `higher-half` feature, physical address `pa` is accessed at
+/// `KERNEL_BASE + pa` in the upper half of the address space; otherwise
+/// physical memory is accessed directly.
+#[cfg(feature = "higher-half")]
+pub const KERNEL_BASE: usize = 0xffff_ffff_0000_0000;
+#[cfg(not(feature = "higher-half"))]
+pub const KERNEL_BASE: usize = 0;
+
+/// The physical address where I/O peripherals are mapped to.
+pub const IO_BASE_PHYS: usize = 0x3F000000;
+pub const IO_BASE_PHYS_END: usize = 0x40000000;
+
+/// The address where I/O peripherals are accessed.
+pub const IO_BASE: usize = KERNEL_BASE + IO_BASE_PHYS;
+pub const IO_BASE_END: usize = KERNEL_BASE + IO_BASE_PHYS_END;
+
+/// The physical address where the BCM2836 local peripherals, private to the
+/// ARM cores, are mapped to.
+pub const LOCAL_BASE_PHYS: usize = 0x40000000;
+pub const LOCAL_BASE_PHYS_END: usize = 0x40040000;
+
+/// The address where the local peripherals are accessed.
+pub const LOCAL_BASE: usize = KERNEL_BASE + LOCAL_BASE_PHYS;
 
 /// The base address of the `GPIO` registers
 pub const GPIO_BASE: usize = IO_BASE + 0x200000;
@@ -8,8 +29,8 @@ pub const GPIO_BASE: usize = IO_BASE + 0x200000;
 /// The number of cores in Rpi3
 pub const NCORES: usize = 4;
 
-/// The base of physical addresses that each core is spinning on
-pub const SPINNING_BASE: *mut usize = 0xd8 as *mut usize;
+/// The base of addresses that each core is spinning on
+pub const SPINNING_BASE: *mut usize = (KERNEL_BASE + 0xd8) as *mut usize;
 
 /// Generates `pub enums` with no variants for each `ident` passed in.
 pub macro states($($name:ident),*) {
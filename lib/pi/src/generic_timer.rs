@@ -0,0 +1,108 @@
+use core::time::Duration;
+
+use aarch64::*;
+
+/// The timers of the ARM generic timer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Kind {
+    /// The EL1 physical timer, compared against `CNTPCT_EL0`. It raises the
+    /// `CntPns` local interrupt.
+    Physical,
+    /// The virtual timer, compared against `CNTVCT_EL0`, which is offset from
+    /// the physical count by `CNTVOFF_EL2` (zero). It raises the `CntV` local
+    /// interrupt.
+    Virtual,
+}
+
+/// A timer of the ARM generic timer.
+///
+/// Every core has its own physical and virtual timers, accessed through
+/// system registers; a `GenericTimer` is the timer of the core it is used
+/// on. The counters run at `frequency()` and are shared by all cores.
+pub struct GenericTimer {
+    kind: Kind,
+}
+
+impl GenericTimer {
+    /// Returns a new instance of the `kind` timer of the current core.
+    pub fn new(kind: Kind) -> GenericTimer {
+        GenericTimer { kind }
+    }
+
+    /// Returns the frequency of the system counter in Hz.
+    pub fn frequency() -> u64 {
+        unsafe { CNTFRQ_EL0.get() }
+    }
+
+    /// Returns the value of the counter this timer compares against.
+    pub fn count(&self) -> u64 {
+        unsafe {
+            // Keep the read from being hoisted above earlier instructions.
+            isb();
+            match self.kind {
+                Kind::Physical => CNTPCT_EL0.get(),
+                Kind::Virtual => CNTVCT_EL0.get(),
+            }
+        }
+    }
+
+    /// Reads the counter and returns the elapsed time as a `Duration`.
+    pub fn read(&self) -> Duration {
+        let nanos = self.count() as u128 * 1_000_000_000 / GenericTimer::frequency() as u128;
+        Duration::from_nanos(nanos as u64)
+    }
+
+    /// Sets up this timer to fire `t` duration from now and enables it. If
+    /// its interrupt is routed to the core by the local interrupt controller
+    /// and IRQs are unmasked, then an interrupt will be issued in `t`
+    /// duration, and is pending until the timer is set up again or disabled.
+    pub fn tick_in(&mut self, t: Duration) {
+        let ticks = t.as_nanos() * GenericTimer::frequency() as u128 / 1_000_000_000;
+        let compare = self.count().saturating_add(ticks as u64);
+        unsafe {
+            match self.kind {
+                Kind::Physical => {
+                    CNTP_CVAL_EL0.set(compare);
+                    CNTP_CTL_EL0.set(CNTP_CTL_EL0::ENABLE);
+                }
+                Kind::Virtual => {
+                    CNTV_CVAL_EL0.set(compare);
+                    CNTV_CTL_EL0.set(CNTV_CTL_EL0::ENABLE);
+                }
+            }
+            isb();
+        }
+    }
+
+    /// Disables this timer, which also clears its interrupt.
+    pub fn disable(&mut self) {
+        unsafe {
+            match self.kind {
+                Kind::Physical => CNTP_CTL_EL0.set(0),
+                Kind::Virtual => CNTV_CTL_EL0.set(0),
+            }
+            isb();
+        }
+    }
+
+    /// Returns `true` if this timer is enabled and has fired.
+    pub fn is_pending(&self) -> bool {
+        unsafe {
+            match self.kind {
+                Kind::Physical => {
+                    CNTP_CTL_EL0.get_masked(CNTP_CTL_EL0::ENABLE | CNTP_CTL_EL0::ISTATUS)
+                        == CNTP_CTL_EL0::ENABLE | CNTP_CTL_EL0::ISTATUS
+                }
+                Kind::Virtual => {
+                    CNTV_CTL_EL0.get_masked(CNTV_CTL_EL0::ENABLE | CNTV_CTL_EL0::ISTATUS)
+                        == CNTV_CTL_EL0::ENABLE | CNTV_CTL_EL0::ISTATUS
+                }
+            }
+        }
+    }
+}
+
+/// Returns the time elapsed on the system counter.
+pub fn current_time() -> Duration {
+    GenericTimer::new(Kind::Physical).read()
+}
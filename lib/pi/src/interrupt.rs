@@ -1,86 +1,156 @@
+use core::convert::TryFrom;
+
 use crate::common::IO_BASE;
+use crate::local_interrupt::{LocalController, LocalInterrupt};
 
 use volatile::prelude::*;
 use volatile::{Volatile, ReadVolatile};
 
 const INT_BASE: usize = IO_BASE + 0xB000 + 0x200;
 
-#[derive(Copy, Clone, PartialEq)]
-pub enum Interrupt {
+macro_rules! interrupts {
+    ($($(#[$attr:meta])* $name:ident = $irq:expr,)*) => {
+        /// The interrupt sources of the BCM2837's interrupt controller. The
+        /// GPU interrupts are numbered 0 to 63 by their bit in the pending 1
+        /// and 2 registers; the ARM-side interrupts of the basic pending
+        /// register follow from 64. The FIQ control register numbers them the
+        /// same way.
+        #[derive(Copy, Clone, Debug, PartialEq)]
+        pub enum Interrupt {
+            $($(#[$attr])* $name = $irq,)*
+        }
+
+        /// Every interrupt, by number.
+        const INTERRUPTS: [Interrupt; Interrupt::MAX] = [$(Interrupt::$name,)*];
+    };
+}
+
+interrupts! {
+    Timer0 = 0,
     Timer1 = 1,
+    Timer2 = 2,
     Timer3 = 3,
+    Codec0 = 4,
+    Codec1 = 5,
+    Codec2 = 6,
+    Jpeg = 7,
+    Isp = 8,
     Usb = 9,
+    V3d = 10,
+    Transposer = 11,
+    MulticoreSync0 = 12,
+    MulticoreSync1 = 13,
+    MulticoreSync2 = 14,
+    MulticoreSync3 = 15,
+    Dma0 = 16,
+    Dma1 = 17,
+    Dma2 = 18,
+    Dma3 = 19,
+    Dma4 = 20,
+    Dma5 = 21,
+    Dma6 = 22,
+    Dma7 = 23,
+    Dma8 = 24,
+    Dma9 = 25,
+    Dma10 = 26,
+    /// Shared by DMA channels 11 to 14.
+    Dma11 = 27,
+    /// Raised by every DMA channel.
+    DmaShared = 28,
+    /// The mini UART and the SPI 1 and 2 masters.
+    Aux = 29,
+    Arm = 30,
+    VpuDma = 31,
+    HostPort = 32,
+    VideoScaler = 33,
+    Ccp2Tx = 34,
+    Sdc = 35,
+    Dsi0 = 36,
+    Ave = 37,
+    Cam0 = 38,
+    Cam1 = 39,
+    Hdmi0 = 40,
+    Hdmi1 = 41,
+    PixelValve1 = 42,
+    I2cSpiSlave = 43,
+    Dsi1 = 44,
+    Pwa0 = 45,
+    Pwa1 = 46,
+    Cpr = 47,
+    Smi = 48,
     Gpio0 = 49,
     Gpio1 = 50,
     Gpio2 = 51,
     Gpio3 = 52,
+    I2c = 53,
+    Spi = 54,
+    Pcm = 55,
+    /// The SD host controller.
+    SdHost = 56,
+    /// The PL011 UART.
     Uart = 57,
+    Slimbus = 58,
+    Vec = 59,
+    Cpg = 60,
+    Rng = 61,
+    /// The Arasan EMMC controller.
+    Emmc = 62,
+    AvsPmon = 63,
+    ArmTimer = 64,
+    ArmMailbox = 65,
+    ArmDoorbell0 = 66,
+    ArmDoorbell1 = 67,
+    Gpu0Halted = 68,
+    Gpu1Halted = 69,
+    IllegalAccess1 = 70,
+    IllegalAccess0 = 71,
 }
 
 impl Interrupt {
-    pub const MAX: usize = 8;
+    pub const MAX: usize = 72;
 
     pub fn iter() -> core::slice::Iter<'static, Interrupt> {
-        use Interrupt::*;
-        [Timer1, Timer3, Usb, Gpio0, Gpio1, Gpio2, Gpio3, Uart].into_iter()
+        INTERRUPTS.iter()
     }
 
     pub fn to_index(i: Interrupt) -> usize {
-        use Interrupt::*;
-        match i {
-            Timer1 => 0,
-            Timer3 => 1,
-            Usb => 2,
-            Gpio0 => 3,
-            Gpio1 => 4,
-            Gpio2 => 5,
-            Gpio3 => 6,
-            Uart => 7,
-        }
+        i as usize
     }
 
-    pub fn from_index(i: usize) -> Interrupt {
-        use Interrupt::*;
-        match i {
-            0 => Timer1,
-            1 => Timer3,
-            2 => Usb,
-            3 => Gpio0,
-            4 => Gpio1,
-            5 => Gpio2,
-            6 => Gpio3,
-            7 => Uart,
-            _ => panic!("Unknown interrupt: {}", i),
-        }
+    /// Returns the interrupt numbered `i`, if there is one.
+    pub fn from_index(i: usize) -> Option<Interrupt> {
+        INTERRUPTS.get(i).cloned()
     }
 }
 
+impl TryFrom<usize> for Interrupt {
+    type Error = usize;
 
-impl From<usize> for Interrupt {
-    fn from(irq: usize) -> Interrupt {
-        use Interrupt::*;
-        match irq {
-            1 => Timer1,
-            3 => Timer3,
-            9 => Usb,
-            49 => Gpio0,
-            50 => Gpio1,
-            51 => Gpio2,
-            52 => Gpio3,
-            57 => Uart,
-            _ => panic!("Unkonwn irq: {}", irq),
-        }
+    /// Returns the interrupt numbered `irq`, or `Err(irq)` if there is none.
+    fn try_from(irq: usize) -> Result<Interrupt, usize> {
+        Interrupt::from_index(irq).ok_or(irq)
     }
 }
 
 #[repr(C)]
 #[allow(non_snake_case)]
 struct Registers {
-    // FIXME: Fill me in.
+    IRQ_BASIC_PENDING: ReadVolatile<u32>,
+    IRQ_PENDING: [ReadVolatile<u32>; 2],
+    FIQ_CONTROL: Volatile<u32>,
+    ENABLE_IRQS: [Volatile<u32>; 2],
+    ENABLE_BASIC_IRQS: Volatile<u32>,
+    DISABLE_IRQS: [Volatile<u32>; 2],
+    DISABLE_BASIC_IRQS: Volatile<u32>,
 }
 
+/// The FIQ control register's bit enabling the FIQ.
+const FIQ_ENABLE: u32 = 1 << 7;
+
 /// An interrupt controller. Used to enable and disable interrupts as well as to
 /// check if an interrupt is pending.
+///
+/// One interrupt at a time can be routed to FIQ instead of IRQ.
 pub struct Controller {
     registers: &'static mut Registers
 }
@@ -95,16 +165,139 @@ impl Controller {
 
     /// Enables the interrupt `int`.
     pub fn enable(&mut self, int: Interrupt) {
-        unimplemented!()
+        let irq = int as usize;
+        match irq {
+            0..=63 => self.registers.ENABLE_IRQS[irq / 32].write(1 << (irq % 32)),
+            _ => self.registers.ENABLE_BASIC_IRQS.write(1 << (irq - 64)),
+        }
     }
 
     /// Disables the interrupt `int`.
     pub fn disable(&mut self, int: Interrupt) {
-        unimplemented!()
+        let irq = int as usize;
+        match irq {
+            0..=63 => self.registers.DISABLE_IRQS[irq / 32].write(1 << (irq % 32)),
+            _ => self.registers.DISABLE_BASIC_IRQS.write(1 << (irq - 64)),
+        }
     }
 
     /// Returns `true` if `int` is pending. Otherwise, returns `false`.
     pub fn is_pending(&self, int: Interrupt) -> bool {
-        unimplemented!()
+        self.pending() & (1 << int as u32) != 0
+    }
+
+    /// Returns the bits of the pending interrupts, by interrupt number.
+    pub fn pending(&self) -> u128 {
+        let basic = self.registers.IRQ_BASIC_PENDING.read() & 0xff;
+        let pending1 = self.registers.IRQ_PENDING[0].read();
+        let pending2 = self.registers.IRQ_PENDING[1].read();
+        (basic as u128) << 64 | (pending2 as u128) << 32 | pending1 as u128
+    }
+
+    /// Routes `int` to FIQ instead of IRQ, in place of the interrupt routed
+    /// there before, if any.
+    pub fn enable_fiq(&mut self, int: Interrupt) {
+        self.disable(int);
+        self.registers.FIQ_CONTROL.write(FIQ_ENABLE | int as u32);
+    }
+
+    /// Stops routing any interrupt to FIQ.
+    pub fn disable_fiq(&mut self) {
+        self.registers.FIQ_CONTROL.write(0);
+    }
+
+    /// Returns the interrupt routed to FIQ, if any.
+    pub fn fiq(&self) -> Option<Interrupt> {
+        let control = self.registers.FIQ_CONTROL.read();
+        if control & FIQ_ENABLE == 0 {
+            return None;
+        }
+        Interrupt::from_index((control & !FIQ_ENABLE) as usize)
+    }
+}
+
+/// The source of an IRQ taken by a core: an interrupt of the core's local
+/// interrupt controller, or one of the GPU-side controller.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Source {
+    Local(LocalInterrupt),
+    Gpu(Interrupt),
+}
+
+impl Source {
+    pub const MAX: usize = LocalInterrupt::MAX + Interrupt::MAX;
+
+    /// Returns the index of `source` among all sources: local ones first.
+    pub fn to_index(source: Source) -> usize {
+        match source {
+            Source::Local(int) => LocalInterrupt::to_index(int),
+            Source::Gpu(int) => LocalInterrupt::MAX + Interrupt::to_index(int),
+        }
+    }
+
+    /// Returns the source of index `i`, if there is one.
+    pub fn from_index(i: usize) -> Option<Source> {
+        if i < LocalInterrupt::MAX {
+            Some(Source::Local(LocalInterrupt::from_index(i)))
+        } else {
+            Interrupt::from_index(i - LocalInterrupt::MAX).map(Source::Gpu)
+        }
+    }
+}
+
+impl From<Interrupt> for Source {
+    fn from(int: Interrupt) -> Source {
+        Source::Gpu(int)
+    }
+}
+
+impl From<LocalInterrupt> for Source {
+    fn from(int: LocalInterrupt) -> Source {
+        Source::Local(int)
+    }
+}
+
+/// An iterator over the pending IRQ sources of a core, as returned by
+/// `pending()`.
+pub struct Pending {
+    /// The pending local sources, by `LocalInterrupt`, but for `Gpu`.
+    local: u32,
+    /// The pending GPU sources, by `Interrupt::to_index()`.
+    gpu: u128,
+}
+
+impl Iterator for Pending {
+    type Item = Source;
+
+    fn next(&mut self) -> Option<Source> {
+        if self.local != 0 {
+            let bit = self.local.trailing_zeros() as usize;
+            self.local &= !(1 << bit);
+            return Some(Source::Local(LocalInterrupt::from_index(bit)));
+        }
+        if self.gpu != 0 {
+            let bit = self.gpu.trailing_zeros() as usize;
+            self.gpu &= !(1 << bit);
+            return Interrupt::from_index(bit).map(Source::Gpu);
+        }
+        None
+    }
+}
+
+/// Returns the pending IRQ sources of `core`: its pending local sources,
+/// and, if the GPU interrupt is pending, each pending GPU source in place of
+/// `LocalInterrupt::Gpu`, but for the one routed to FIQ.
+pub fn pending(core: usize) -> Pending {
+    let mut local = LocalController::new(core).pending() & ((1 << LocalInterrupt::MAX) - 1);
+    let mut gpu = 0;
+    if local & (1 << LocalInterrupt::Gpu as u32) != 0 {
+        local &= !(1 << LocalInterrupt::Gpu as u32);
+        let controller = Controller::new();
+        gpu = controller.pending();
+        // The interrupt routed to FIQ is taken as an FIQ, not here.
+        if let Some(int) = controller.fiq() {
+            gpu &= !(1 << int as u32);
+        }
     }
+    Pending { local, gpu }
 }
@@ -7,7 +7,9 @@
 
 pub mod atags;
 pub mod common;
+pub mod generic_timer;
 pub mod gpio;
 pub mod interrupt;
+pub mod local_interrupt;
 pub mod timer;
 pub mod uart;
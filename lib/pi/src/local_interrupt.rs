@@ -0,0 +1,195 @@
+use crate::common::{LOCAL_BASE, NCORES};
+
+use volatile::prelude::*;
+use volatile::{ReadVolatile, Reserved, Volatile, WriteVolatile};
+
+/// The interrupt sources of a core, as reported by the BCM2836 local
+/// interrupt controller. The discriminant is the bit of the source in the
+/// core's IRQ and FIQ source registers.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LocalInterrupt {
+    /// The secure physical timer.
+    CntPs = 0,
+    /// The non-secure (EL1) physical timer.
+    CntPns = 1,
+    /// The hypervisor physical timer.
+    CntHp = 2,
+    /// The virtual timer.
+    CntV = 3,
+    Mailbox0 = 4,
+    Mailbox1 = 5,
+    Mailbox2 = 6,
+    Mailbox3 = 7,
+    /// An interrupt of the GPU-side controller, `interrupt::Controller`.
+    Gpu = 8,
+    /// The performance monitors.
+    Pmu = 9,
+    /// Outstanding AXI transfers; reported to core 0 only.
+    AxiOutstanding = 10,
+    /// The local timer.
+    LocalTimer = 11,
+}
+
+impl LocalInterrupt {
+    pub const MAX: usize = 12;
+
+    pub fn iter() -> core::slice::Iter<'static, LocalInterrupt> {
+        use LocalInterrupt::*;
+        [
+            CntPs, CntPns, CntHp, CntV, Mailbox0, Mailbox1, Mailbox2, Mailbox3, Gpu, Pmu,
+            AxiOutstanding, LocalTimer,
+        ]
+        .into_iter()
+    }
+
+    pub fn to_index(i: LocalInterrupt) -> usize {
+        i as usize
+    }
+
+    pub fn from_index(i: usize) -> LocalInterrupt {
+        match LocalInterrupt::iter().nth(i) {
+            Some(&int) => int,
+            None => panic!("Unknown local interrupt: {}", i),
+        }
+    }
+
+    /// Returns the mailbox interrupt of `mailbox`.
+    pub fn mailbox(mailbox: usize) -> LocalInterrupt {
+        LocalInterrupt::from_index(LocalInterrupt::Mailbox0 as usize + mailbox)
+    }
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    CONTROL: Volatile<u32>,
+    __r0: Reserved<u32>,
+    CORE_TIMER_PRESCALER: Volatile<u32>,
+    GPU_INT_ROUTING: Volatile<u32>,
+    PMU_INT_ROUTING_SET: WriteVolatile<u32>,
+    PMU_INT_ROUTING_CLR: WriteVolatile<u32>,
+    __r1: Reserved<u32>,
+    CORE_TIMER_LS: Volatile<u32>,
+    CORE_TIMER_MS: Volatile<u32>,
+    LOCAL_INT_ROUTING: Volatile<u32>,
+    __r2: Reserved<u32>,
+    AXI_COUNTERS: Volatile<u32>,
+    AXI_INT: Volatile<u32>,
+    LOCAL_TIMER_CONTROL: Volatile<u32>,
+    LOCAL_TIMER_WRITE: WriteVolatile<u32>,
+    __r3: Reserved<u32>,
+    CORE_TIMER_INT_CONTROL: [Volatile<u32>; NCORES],
+    CORE_MAILBOX_INT_CONTROL: [Volatile<u32>; NCORES],
+    CORE_IRQ_SOURCE: [ReadVolatile<u32>; NCORES],
+    CORE_FIQ_SOURCE: [ReadVolatile<u32>; NCORES],
+    /// Writing sets bits of a mailbox of a core.
+    CORE_MAILBOX_SET: [[WriteVolatile<u32>; 4]; NCORES],
+    /// Reading returns a mailbox of a core; writing clears bits of it.
+    CORE_MAILBOX_CLR: [[Volatile<u32>; 4]; NCORES],
+}
+
+/// The BCM2836 local interrupt controller, as seen by one core. Used to
+/// route the core's timer, mailbox and performance monitor interrupts to it,
+/// to send mailbox messages to any core, and to check which of the core's
+/// interrupt sources are pending.
+///
+/// GPU interrupts, which the `interrupt::Controller` enables, are routed to
+/// core 0 and show up as `LocalInterrupt::Gpu` there.
+pub struct LocalController {
+    core: usize,
+    registers: &'static mut Registers,
+}
+
+impl LocalController {
+    /// Returns a new handle to the local interrupt controller of `core`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `core >= NCORES`.
+    pub fn new(core: usize) -> LocalController {
+        assert!(core < NCORES, "no core {}", core);
+        LocalController {
+            core,
+            registers: unsafe { &mut *(LOCAL_BASE as *mut Registers) },
+        }
+    }
+
+    /// Routes the interrupt of the generic timer `int`, one of `CntPs`,
+    /// `CntPns`, `CntHp` and `CntV`, to this core's IRQ.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `int` is not a timer interrupt.
+    pub fn enable_timer(&mut self, int: LocalInterrupt) {
+        let bit = LocalController::timer_bit(int);
+        self.registers.CORE_TIMER_INT_CONTROL[self.core].or_mask(bit);
+    }
+
+    /// Stops routing the interrupt of the generic timer `int` to this core.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `int` is not a timer interrupt.
+    pub fn disable_timer(&mut self, int: LocalInterrupt) {
+        let bit = LocalController::timer_bit(int);
+        self.registers.CORE_TIMER_INT_CONTROL[self.core].and_mask(!bit);
+    }
+
+    fn timer_bit(int: LocalInterrupt) -> u32 {
+        match int {
+            LocalInterrupt::CntPs
+            | LocalInterrupt::CntPns
+            | LocalInterrupt::CntHp
+            | LocalInterrupt::CntV => 1 << (int as u32),
+            _ => panic!("{:?} is not a timer interrupt", int),
+        }
+    }
+
+    /// Routes the interrupt of `mailbox` (0 to 3) of this core to its IRQ.
+    pub fn enable_mailbox(&mut self, mailbox: usize) {
+        self.registers.CORE_MAILBOX_INT_CONTROL[self.core].or_mask(1 << mailbox);
+    }
+
+    /// Stops routing the interrupt of `mailbox` of this core to it.
+    pub fn disable_mailbox(&mut self, mailbox: usize) {
+        self.registers.CORE_MAILBOX_INT_CONTROL[self.core].and_mask(!(1 << mailbox));
+    }
+
+    /// Routes the performance monitor interrupt to this core's IRQ.
+    pub fn enable_pmu(&mut self) {
+        self.registers.PMU_INT_ROUTING_SET.write(1 << self.core);
+    }
+
+    /// Stops routing the performance monitor interrupt to this core.
+    pub fn disable_pmu(&mut self) {
+        self.registers.PMU_INT_ROUTING_CLR.write(1 << self.core);
+    }
+
+    /// Sets the bits of `value` in `mailbox` of `core`, which interrupts
+    /// `core` if it enabled the mailbox.
+    pub fn send(&mut self, core: usize, mailbox: usize, value: u32) {
+        self.registers.CORE_MAILBOX_SET[core][mailbox].write(value);
+    }
+
+    /// Returns the contents of `mailbox` of this core.
+    pub fn mailbox(&self, mailbox: usize) -> u32 {
+        self.registers.CORE_MAILBOX_CLR[self.core][mailbox].read()
+    }
+
+    /// Clears the bits of `value` in `mailbox` of this core. The mailbox
+    /// interrupt is pending for as long as a bit is set.
+    pub fn clear_mailbox(&mut self, mailbox: usize, value: u32) {
+        self.registers.CORE_MAILBOX_CLR[self.core][mailbox].write(value);
+    }
+
+    /// Returns the bits of this core's pending IRQ sources, by `LocalInterrupt`.
+    pub fn pending(&self) -> u32 {
+        self.registers.CORE_IRQ_SOURCE[self.core].read()
+    }
+
+    /// Returns `true` if `int` is pending for this core. Otherwise, returns
+    /// `false`.
+    pub fn is_pending(&self, int: LocalInterrupt) -> bool {
+        self.pending() & (1 << (int as u32)) != 0
+    }
+}
@@ -43,7 +43,11 @@ impl Timer {
     /// interrupts for timer 1 are enabled and IRQs are unmasked, then a timer
     /// interrupt will be issued in `t` duration.
     pub fn tick_in(&mut self, t: Duration) {
-        unimplemented!()
+        // The compare registers match the low 32 bits of the counter only.
+        let now = self.registers.CLO.read();
+        self.registers.COMPARE[1].write(now.wrapping_add(t.as_micros() as u32));
+        // Acknowledge the previous match, which clears the interrupt.
+        self.registers.CS.write(1 << 1);
     }
 }
 
@@ -65,5 +69,5 @@ pub fn spin_sleep(t: Duration) {
 /// interrupts for timer 1 are enabled and IRQs are unmasked, then a timer
 /// interrupt will be issued in `t` duration.
 pub fn tick_in(t: Duration) {
-    unimplemented!()
+    Timer::new().tick_in(t)
 }
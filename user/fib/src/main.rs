@@ -4,6 +4,7 @@
 
 mod cr0;
 
+use kernel_api::crt0::Args;
 use kernel_api::println;
 use kernel_api::syscall::{getpid, time};
 
@@ -15,10 +16,12 @@ fn fib(n: u64) -> u64 {
     }
 }
 
-fn main() {
+fn main(args: Args) {
+    let n = args.get(1).and_then(|arg| arg.parse().ok()).unwrap_or(40);
+
     println!("Started...");
 
-    let rtn = fib(40);
+    let rtn = fib(n);
 
     println!("Ended: Result = {}", rtn);
 }
@@ -0,0 +1,33 @@
+use core::mem::zeroed;
+use core::panic::PanicInfo;
+use core::ptr::write_volatile;
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    loop {}
+}
+
+unsafe fn zeros_bss() {
+    extern "C" {
+        static mut __bss_beg: u64;
+        static mut __bss_end: u64;
+    }
+
+    let mut iter: *mut u64 = &mut __bss_beg;
+    let end: *mut u64 = &mut __bss_end;
+
+    while iter < end {
+        write_volatile(iter, zeroed());
+        iter = iter.add(1);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _start(
+    argc: usize,
+    argv: *const *const u8,
+    envp: *const *const u8,
+) -> ! {
+    zeros_bss();
+    kernel_api::crt0::start(argc, argv, envp, crate::main);
+}
@@ -0,0 +1,82 @@
+#![feature(asm)]
+#![no_std]
+#![no_main]
+
+mod cr0;
+
+use core::ptr::{read_volatile, write_volatile};
+
+use kernel_api::crt0::Args;
+use kernel_api::println;
+use kernel_api::syscall::{fork, mmap, mprotect};
+use kernel_api::{PROT_READ, PROT_WRITE};
+
+/// Lives in the image's `.data`, which starts out shared with the child.
+static mut SHARED: u64 = 42;
+
+fn spin(n: u64) {
+    for i in 0..n {
+        unsafe { asm!("" :: "r"(i) :: "volatile") };
+    }
+}
+
+fn main(_args: Args) {
+    let mut stack = [42u64; 8];
+
+    // A page that is read-only when forked, and so shared as it is.
+    let page = match mmap(core::ptr::null_mut(), 8, PROT_READ | PROT_WRITE) {
+        Ok(page) => page as *mut u64,
+        Err(e) => {
+            println!("mmap failed: {:?}", e);
+            return;
+        }
+    };
+    unsafe { write_volatile(page, 42) };
+    if let Err(e) = mprotect(page as *mut u8, 8, PROT_READ) {
+        println!("mprotect failed: {:?}", e);
+        return;
+    }
+
+    let (name, value) = match fork() {
+        Ok(0) => ("child", 2),
+        Ok(_) => ("parent", 1),
+        Err(e) => {
+            println!("fork failed: {:?}", e);
+            return;
+        }
+    };
+
+    // Each process writes its own value, gives the other time to write
+    // theirs, and checks that it still sees only its own. Only the child
+    // makes the read-only page writable again and writes to it.
+    let mut protected = 42;
+    unsafe {
+        write_volatile(&mut SHARED, value);
+        for slot in stack.iter_mut() {
+            write_volatile(slot, value);
+        }
+        if value == 2 {
+            match mprotect(page as *mut u8, 8, PROT_READ | PROT_WRITE) {
+                Ok(()) => {
+                    write_volatile(page, value);
+                    protected = value;
+                }
+                Err(e) => println!("{}: mprotect failed: {:?}", name, e),
+            }
+        }
+    }
+
+    spin(1 << 20);
+
+    let data_ok = unsafe { read_volatile(&SHARED) } == value;
+    let stack_ok = stack.iter().all(|slot| unsafe { read_volatile(slot) } == value);
+    let page_ok = unsafe { read_volatile(page) } == protected;
+    if data_ok && stack_ok && page_ok {
+        println!("{}: PASS", name);
+    } else {
+        println!(
+            "{}: FAIL (data: {}, stack: {}, mprotect: {})",
+            name, data_ok, stack_ok, page_ok
+        );
+    }
+}
@@ -23,8 +23,11 @@ unsafe fn zeros_bss() {
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn _start() -> ! {
+pub unsafe extern "C" fn _start(
+    argc: usize,
+    argv: *const *const u8,
+    envp: *const *const u8,
+) -> ! {
     zeros_bss();
-    crate::main();
-    kernel_api::syscall::exit();
+    kernel_api::crt0::start(argc, argv, envp, crate::main);
 }